@@ -8,6 +8,8 @@
 //   Commands:
 //     0x03 SET LED, DATA is [0 - 1, 0 - 1] for LED 0 blue, 1 red and state 0 off, 1 on
 //     0x04 SET TEXT, DATA is [ASCII] for 84 printable ASCII chars
+//     0x06 DRAW BITMAP, DATA is 512 bytes, 32 rows of 16 bytes, 1 bit per pixel, MSB is leftmost
+//     0x07 SET ROW, DATA is [0 - 3, ASCII] for the row and 21 printable ASCII chars, other rows are left as they are
 
 #include <Wire.h>
 #include <Adafruit_GFX.h>
@@ -39,6 +41,11 @@ const byte COMMAND_BUTTON_RELEASED = 0x02;
 const byte COMMAND_SET_LED = 0x03;
 const byte COMMAND_SET_TEXT = 0x04;
 const byte COMMAND_SYNC = 0x05;
+const byte COMMAND_DRAW_BITMAP = 0x06;
+const byte COMMAND_SET_ROW = 0x07;
+
+const int ROW_HEIGHT = 8;
+const int COLUMNS = 21;
 
 const byte COMMAND_LED_BLUE = 0;
 const byte COMMAND_LED_RED = 1;
@@ -129,7 +136,31 @@ void loop() {
         display.println(text);
         display.display();
         break;
-      }        
+      }
+      case COMMAND_SET_ROW: {
+        int row = Serial.read();
+        char text[COLUMNS + 1];
+        Serial.readBytes(text, COLUMNS);
+        text[COLUMNS] = 0;
+        display.fillRect(0, row * ROW_HEIGHT, SCREEN_WIDTH, ROW_HEIGHT, SSD1306_BLACK);
+        display.setCursor(0, row * ROW_HEIGHT);
+        display.print(text);
+        display.display();
+        break;
+      }
+      case COMMAND_DRAW_BITMAP: {
+        // read a row at a time as there isn't enough RAM for a second full screen buffer
+        byte row[SCREEN_WIDTH / 8];
+        display.clearDisplay();
+        for (int y = 0; y < SCREEN_HEIGHT; y++) {
+          if (Serial.readBytes(row, sizeof(row)) != sizeof(row)) {
+            break;
+          }
+          display.drawBitmap(0, y, row, SCREEN_WIDTH, 1, SSD1306_WHITE);
+        }
+        display.display();
+        break;
+      }
     }
   }
 
@@ -0,0 +1,243 @@
+use std::time::Duration;
+use crate::{CommLibResult, DISPLAY_HEIGHT, DISPLAY_WIDTH, TooLong, UnsupportedChar};
+use crate::canvas::Canvas;
+use crate::font::FontSize;
+use crate::layout::{Align, COLUMNS, fit};
+
+const GAP: usize = 1;
+const FONT_SIZES_LARGEST_FIRST: [FontSize; 4] = [FontSize::Huge, FontSize::Large, FontSize::Medium, FontSize::Small];
+
+/// Height of large numbers in text mode
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BigSize {
+    /// 2 rows, leaves room for a title and caption
+    Two,
+    /// 4 rows, fills the screen
+    Four,
+}
+
+impl BigSize {
+    pub fn rows(&self) -> usize {
+        match self {
+            BigSize::Two => 2,
+            BigSize::Four => 4,
+        }
+    }
+}
+
+/// Segments are labelled as on a seven segment display
+/// a top, b top right, c bottom right, d bottom, e bottom left, f top left, g middle
+fn segments(chr: char) -> Option<&'static str> {
+    Some(match chr {
+        '0' => "abcdef",
+        '1' => "bc",
+        '2' => "abdeg",
+        '3' => "abcdg",
+        '4' => "bcfg",
+        '5' => "acdfg",
+        '6' => "acdefg",
+        '7' => "abc",
+        '8' => "abcdefg",
+        '9' => "abcdfg",
+        '-' => "g",
+        _ => return None
+    })
+}
+
+fn glyph(chr: char, size: BigSize) -> CommLibResult<Vec<String>> {
+    let narrow = |two: [&str; 2], four: [&str; 4]| -> Vec<String> {
+        match size {
+            BigSize::Two => two.iter().map(|row| row.to_string()).collect(),
+            BigSize::Four => four.iter().map(|row| row.to_string()).collect(),
+        }
+    };
+    match chr {
+        ':' => return Ok(narrow([".", "."], [" ", ".", " ", "."])),
+        '.' => return Ok(narrow([" ", "."], [" ", " ", " ", "."])),
+        ' ' => return Ok(narrow([" ", " "], [" ", " ", " ", " "])),
+        _ => {}
+    }
+    let segments = segments(chr).ok_or(UnsupportedChar(chr))?;
+    let seg = |name: char, chr: char| if segments.contains(name) { chr } else { ' ' };
+    Ok(match size {
+        BigSize::Two => {
+            let top_middle = match (segments.contains('a'), segments.contains('g')) {
+                (true, true) => '=',
+                (true, false) => '~',
+                (false, true) => '_',
+                (false, false) => ' ',
+            };
+            vec![
+                format!("{}{}{}", seg('f', '|'), top_middle, seg('b', '|')),
+                format!("{}{}{}", seg('e', '|'), seg('d', '_'), seg('c', '|')),
+            ]
+        }
+        BigSize::Four => vec![
+            format!(" {} ", seg('a', '_')),
+            format!("{}{}{}", seg('f', '|'), seg('g', '_'), seg('b', '|')),
+            format!("{} {}", seg('e', '|'), seg('c', '|')),
+            format!("{}{}{}", seg('e', '|'), seg('d', '_'), seg('c', '|')),
+        ]
+    })
+}
+
+/// Render `text` as large characters, supports 0-9, ':', '-', '.' and space
+///
+/// Returns [BigSize::rows] lines, not padded to the screen width
+pub fn big_text(text: &str, size: BigSize) -> CommLibResult<Vec<String>> {
+    let mut rows = vec![String::new(); size.rows()];
+    for (i, chr) in text.chars().enumerate() {
+        let glyph = glyph(chr, size)?;
+        for (row, part) in rows.iter_mut().zip(glyph) {
+            if i > 0 {
+                row.push_str(&" ".repeat(GAP));
+            }
+            row.push_str(&part);
+        }
+    }
+    if rows[0].chars().count() > COLUMNS {
+        return Err(TooLong);
+    }
+    Ok(rows)
+}
+
+/// Large `HH:MM`, centered
+pub fn clock(hours: u32, minutes: u32, size: BigSize) -> Vec<String> {
+    let text = format!("{:02}:{:02}", hours % 24, minutes % 60);
+    centered(&text, size).expect("Clock always fits")
+}
+
+/// Large `MM:SS`, or `HH:MM` if there is an hour or more left, centered
+pub fn countdown(remaining: Duration, size: BigSize) -> Vec<String> {
+    let seconds = remaining.as_secs();
+    let text = if seconds >= 3600 {
+        format!("{:02}:{:02}", (seconds / 3600).min(99), (seconds / 60) % 60)
+    } else {
+        format!("{:02}:{:02}", seconds / 60, seconds % 60)
+    };
+    centered(&text, size).expect("Countdown always fits")
+}
+
+/// Large number with `unit` in normal text on the last row, i.e. `21.5 C`
+/// Returns [TooLong] if it doesn't fit on the screen
+pub fn readout(value: f32, decimals: usize, unit: &str, size: BigSize) -> CommLibResult<Vec<String>> {
+    let number = big_text(&format!("{:.*}", decimals, value), size)?;
+    let width = number[0].chars().count() + GAP + unit.chars().count();
+    if width > COLUMNS {
+        return Err(TooLong);
+    }
+    let last = number.len() - 1;
+    let rows = number.into_iter()
+        .enumerate()
+        .map(|(i, mut row)| {
+            row.push_str(&" ".repeat(GAP));
+            if i == last {
+                row.push_str(unit);
+            }
+            fit(&row, COLUMNS, Align::Center)
+        })
+        .collect();
+    Ok(rows)
+}
+
+fn centered(text: &str, size: BigSize) -> CommLibResult<Vec<String>> {
+    Ok(big_text(text, size)?
+        .iter()
+        .map(|row| fit(row, COLUMNS, Align::Center))
+        .collect())
+}
+
+/// Draw `text` centered on the canvas using the largest font that fits
+/// Returns the font used or [TooLong] if it doesn't fit at any size
+pub fn draw_big(canvas: &mut Canvas, text: &str) -> CommLibResult<FontSize> {
+    let size = largest_fit(text)?;
+    let x = (DISPLAY_WIDTH - size.text_width(text)) / 2;
+    let y = (DISPLAY_HEIGHT - size.height()) / 2;
+    canvas.text(x as isize, y as isize, text, size, true);
+    Ok(size)
+}
+
+/// Draw value with `unit` in the small font at the bottom right of the number
+pub fn draw_readout(canvas: &mut Canvas, value: f32, decimals: usize, unit: &str) -> CommLibResult<FontSize> {
+    let number = format!("{:.*}", decimals, value);
+    let unit_width = FontSize::Small.text_width(unit);
+    let size = FONT_SIZES_LARGEST_FIRST
+        .into_iter()
+        .find(|size| size.text_width(&number) + size.scale() + unit_width <= DISPLAY_WIDTH && size.height() <= DISPLAY_HEIGHT)
+        .ok_or(TooLong)?;
+    let width = size.text_width(&number) + size.scale() + unit_width;
+    let x = ((DISPLAY_WIDTH - width) / 2) as isize;
+    let y = ((DISPLAY_HEIGHT - size.height()) / 2) as isize;
+    let number_width = canvas.text(x, y, &number, size, true) as isize;
+    let unit_y = y + (size.height() - FontSize::Small.height()) as isize;
+    canvas.text(x + number_width + size.scale() as isize, unit_y, unit, FontSize::Small, true);
+    Ok(size)
+}
+
+/// Draw `HH:MM` as large as possible
+pub fn draw_clock(canvas: &mut Canvas, hours: u32, minutes: u32) -> FontSize {
+    draw_big(canvas, &format!("{:02}:{:02}", hours % 24, minutes % 60)).expect("Clock always fits")
+}
+
+fn largest_fit(text: &str) -> CommLibResult<FontSize> {
+    FONT_SIZES_LARGEST_FIRST
+        .into_iter()
+        .find(|size| size.text_width(text) <= DISPLAY_WIDTH && size.height() <= DISPLAY_HEIGHT)
+        .ok_or(TooLong)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CommLibError;
+
+    #[test]
+    fn text_layout() {
+        assert_eq!(big_text("12", BigSize::Two).unwrap(), vec!["  |  =|", "  | |_ "]);
+        assert_eq!(big_text("8-", BigSize::Four).unwrap(), vec![" _     ", "|_|  _ ", "| |    ", "|_|    "]);
+        assert_eq!(big_text("1:", BigSize::Four).unwrap(), vec!["     ", "  | .", "  |  ", "  | ."]);
+    }
+
+    #[test]
+    fn text_that_doesnt_fit() {
+        assert!(matches!(big_text("123456", BigSize::Two), Err(CommLibError::TooLong)));
+        assert!(big_text("12345", BigSize::Two).is_ok());
+        assert!(matches!(big_text("1x", BigSize::Two), Err(CommLibError::UnsupportedChar('x'))));
+    }
+
+    #[test]
+    fn clocks_are_centered() {
+        let rows = clock(7, 5, BigSize::Four);
+        assert_eq!(rows.len(), 4);
+        assert!(rows.iter().all(|row| row.chars().count() == COLUMNS));
+        assert_eq!(rows[1], fit("| |   | . | | |_ ", COLUMNS, Align::Center));
+        assert_eq!(clock(31, 65, BigSize::Two), clock(7, 5, BigSize::Two));
+    }
+
+    #[test]
+    fn countdowns_switch_to_hours() {
+        assert_eq!(countdown(Duration::from_secs(125), BigSize::Two), clock(2, 5, BigSize::Two));
+        assert_eq!(countdown(Duration::from_secs(3725), BigSize::Two), clock(1, 2, BigSize::Two));
+        assert_eq!(countdown(Duration::from_secs(200 * 3600), BigSize::Two), centered("99:00", BigSize::Two).unwrap());
+    }
+
+    #[test]
+    fn readouts_have_the_unit_on_the_last_row() {
+        let rows = readout(21.5, 1, "C", BigSize::Two).unwrap();
+        assert_eq!(rows[0].trim(), "=|   |   |=");
+        assert_eq!(rows[1].trim(), "|_    | .  _| C");
+        assert!(rows.iter().all(|row| row.chars().count() == COLUMNS));
+        assert!(matches!(readout(12345.0, 0, "rpm", BigSize::Two), Err(CommLibError::TooLong)));
+    }
+
+    #[test]
+    fn pixel_text_uses_the_largest_font_that_fits() {
+        let mut canvas = Canvas::new();
+        assert_eq!(draw_clock(&mut canvas, 12, 34), FontSize::Huge);
+        // 116 pixels wide, so centered with 6 pixels either side
+        assert!((0..32).all(|y| (0..6).all(|x| !canvas.get(x, y)) && (122..128).all(|x| !canvas.get(x, y))));
+        assert_eq!(draw_big(&mut Canvas::new(), "123456789").unwrap(), FontSize::Medium);
+        assert!(matches!(draw_big(&mut Canvas::new(), &"1".repeat(22)), Err(CommLibError::TooLong)));
+        assert_eq!(draw_readout(&mut Canvas::new(), 21.5, 1, "C").unwrap(), FontSize::Huge);
+    }
+}
@@ -0,0 +1,149 @@
+use crate::{CommLibResult, InvalidImage};
+
+/// 1 bit image of any size, can be drawn on to a [crate::canvas::Canvas]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Bitmap {
+    width: usize,
+    height: usize,
+    pixels: Vec<bool>,
+}
+
+impl Bitmap {
+    pub fn new(width: usize, height: usize) -> Self {
+        Bitmap { width, height, pixels: vec![false; width * height] }
+    }
+}
+
+impl Bitmap {
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Returns false for pixels outside of the image
+    pub fn get(&self, x: usize, y: usize) -> bool {
+        x < self.width && y < self.height && self.pixels[y * self.width + x]
+    }
+
+    /// Pixels outside of the image are ignored
+    pub fn set(&mut self, x: usize, y: usize, on: bool) {
+        if x < self.width && y < self.height {
+            self.pixels[y * self.width + x] = on;
+        }
+    }
+
+    pub fn invert(&mut self) {
+        self.pixels.iter_mut().for_each(|pixel| *pixel = !*pixel);
+    }
+}
+
+/// How colour pixels are reduced to on/off
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Conversion {
+    /// Pixels with a brightness (0-255) at or above the value are on
+    Threshold(u8),
+    /// Error diffusion, better for photos and gradients
+    FloydSteinberg,
+}
+
+impl Bitmap {
+    /// Convert an RGBA8 image to 1 bit
+    ///
+    /// Transparent pixels are treated as black (off)
+    pub fn from_rgba(width: usize, height: usize, rgba: &[u8], conversion: Conversion) -> CommLibResult<Bitmap> {
+        if rgba.len() != width * height * 4 {
+            return Err(InvalidImage(format!("expected {} bytes for {}x{} RGBA, was {}", width * height * 4, width, height, rgba.len())));
+        }
+        let mut luma = rgba.chunks_exact(4)
+            .map(|px| {
+                let value = 0.299 * px[0] as f32 + 0.587 * px[1] as f32 + 0.114 * px[2] as f32;
+                value * (px[3] as f32 / 255.0)
+            })
+            .collect::<Vec<f32>>();
+
+        let mut bitmap = Bitmap::new(width, height);
+        match conversion {
+            Conversion::Threshold(threshold) => {
+                for (pixel, value) in bitmap.pixels.iter_mut().zip(luma) {
+                    *pixel = value >= threshold as f32;
+                }
+            }
+            Conversion::FloydSteinberg => {
+                for y in 0..height {
+                    for x in 0..width {
+                        let idx = y * width + x;
+                        let old = luma[idx];
+                        let on = old >= 128.0;
+                        bitmap.pixels[idx] = on;
+                        let error = old - if on { 255.0 } else { 0.0 };
+                        if x + 1 < width {
+                            luma[idx + 1] += error * 7.0 / 16.0;
+                        }
+                        if y + 1 < height {
+                            if x > 0 {
+                                luma[idx + width - 1] += error * 3.0 / 16.0;
+                            }
+                            luma[idx + width] += error * 5.0 / 16.0;
+                            if x + 1 < width {
+                                luma[idx + width + 1] += error / 16.0;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        Ok(bitmap)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grey(width: usize, height: usize, value: u8, alpha: u8) -> Vec<u8> {
+        [value, value, value, alpha].repeat(width * height)
+    }
+
+    #[test]
+    fn threshold() {
+        let mut rgba = grey(2, 1, 200, 255);
+        rgba[4..8].copy_from_slice(&[255, 255, 255, 0]);
+        let bitmap = Bitmap::from_rgba(2, 1, &rgba, Conversion::Threshold(128)).unwrap();
+        // transparent white is off
+        assert!(bitmap.get(0, 0));
+        assert!(!bitmap.get(1, 0));
+        assert!(!Bitmap::from_rgba(2, 1, &rgba, Conversion::Threshold(201)).unwrap().get(0, 0));
+    }
+
+    #[test]
+    fn dithering_keeps_brightness() {
+        let bitmap = Bitmap::from_rgba(16, 16, &grey(16, 16, 128, 255), Conversion::FloydSteinberg).unwrap();
+        let on = (0..16).flat_map(|y| (0..16).map(move |x| (x, y))).filter(|(x, y)| bitmap.get(*x, *y)).count();
+        assert!((120..=136).contains(&on), "{} of 256 on", on);
+        // neighbours alternate instead of all being on
+        assert_ne!(bitmap.get(0, 0), bitmap.get(1, 0));
+
+        let black = Bitmap::from_rgba(4, 4, &grey(4, 4, 0, 255), Conversion::FloydSteinberg).unwrap();
+        assert_eq!(black, Bitmap::new(4, 4));
+    }
+
+    #[test]
+    fn wrong_size_is_an_error() {
+        assert!(Bitmap::from_rgba(2, 2, &grey(2, 1, 0, 255), Conversion::Threshold(128)).is_err());
+    }
+
+    #[test]
+    fn outside_pixels_are_ignored() {
+        let mut bitmap = Bitmap::new(2, 2);
+        bitmap.set(2, 0, true);
+        bitmap.set(1, 1, true);
+        assert!(!bitmap.get(2, 0));
+        assert!(bitmap.get(1, 1));
+        bitmap.invert();
+        assert!(!bitmap.get(1, 1));
+        assert!(bitmap.get(0, 0));
+    }
+}
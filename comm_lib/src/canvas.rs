@@ -0,0 +1,275 @@
+use crate::{BITMAP_LEN, CommLibResult, DISPLAY_HEIGHT, DISPLAY_WIDTH, InvalidBitmap};
+use crate::bitmap::Bitmap;
+use crate::font::{FontSize, glyph, GLYPH_HEIGHT};
+use crate::manager::Update;
+
+/// Full screen 1 bit drawing surface
+///
+/// Coordinates are signed so that shapes can be partially off screen, anything outside
+/// of the screen is clipped
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Canvas {
+    buffer: [u8; BITMAP_LEN],
+}
+
+impl Canvas {
+    pub fn new() -> Self {
+        Canvas { buffer: [0; BITMAP_LEN] }
+    }
+
+    /// Create canvas from bytes in the [Update::Bitmap] format
+    pub fn from_bytes(bytes: &[u8]) -> CommLibResult<Self> {
+        if bytes.len() != BITMAP_LEN {
+            return Err(InvalidBitmap(bytes.len()));
+        }
+        let mut canvas = Canvas::new();
+        canvas.buffer.copy_from_slice(bytes);
+        Ok(canvas)
+    }
+}
+
+impl Default for Canvas {
+    fn default() -> Self {
+        Canvas::new()
+    }
+}
+
+impl Canvas {
+    pub fn clear(&mut self) {
+        self.buffer = [0; BITMAP_LEN];
+    }
+
+    pub fn invert(&mut self) {
+        self.buffer.iter_mut().for_each(|byte| *byte = !*byte);
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.buffer
+    }
+
+    /// Create update to show this canvas on the device
+    pub fn to_update(&self) -> Update {
+        Update::Bitmap(self.buffer.to_vec())
+    }
+
+    /// Returns false for pixels outside of the screen
+    pub fn get(&self, x: isize, y: isize) -> bool {
+        match index(x, y) {
+            Some((idx, mask)) => self.buffer[idx] & mask != 0,
+            None => false
+        }
+    }
+
+    pub fn set(&mut self, x: isize, y: isize, on: bool) {
+        if let Some((idx, mask)) = index(x, y) {
+            if on {
+                self.buffer[idx] |= mask;
+            } else {
+                self.buffer[idx] &= !mask;
+            }
+        }
+    }
+
+    pub fn line(&mut self, x1: isize, y1: isize, x2: isize, y2: isize, on: bool) {
+        let dx = (x2 - x1).abs();
+        let dy = -(y2 - y1).abs();
+        let step_x = if x1 < x2 { 1 } else { -1 };
+        let step_y = if y1 < y2 { 1 } else { -1 };
+        let mut error = dx + dy;
+        let (mut x, mut y) = (x1, y1);
+        loop {
+            self.set(x, y, on);
+            if x == x2 && y == y2 {
+                break;
+            }
+            let e2 = error * 2;
+            if e2 >= dy {
+                error += dy;
+                x += step_x;
+            }
+            if e2 <= dx {
+                error += dx;
+                y += step_y;
+            }
+        }
+    }
+
+    /// Outline of rectangle, both corners are inclusive
+    pub fn rect(&mut self, x1: isize, y1: isize, x2: isize, y2: isize, on: bool) {
+        self.line(x1, y1, x2, y1, on);
+        self.line(x1, y2, x2, y2, on);
+        self.line(x1, y1, x1, y2, on);
+        self.line(x2, y1, x2, y2, on);
+    }
+
+    /// Filled rectangle, both corners are inclusive
+    pub fn fill_rect(&mut self, x1: isize, y1: isize, x2: isize, y2: isize, on: bool) {
+        for y in y1.min(y2)..=y1.max(y2) {
+            for x in x1.min(x2)..=x1.max(x2) {
+                self.set(x, y, on);
+            }
+        }
+    }
+
+    pub fn circle(&mut self, cx: isize, cy: isize, radius: isize, on: bool) {
+        let (mut x, mut y) = (radius, 0);
+        let mut error = 1 - radius;
+        while x >= y {
+            for (px, py) in [(x, y), (y, x), (-y, x), (-x, y), (-x, -y), (-y, -x), (y, -x), (x, -y)] {
+                self.set(cx + px, cy + py, on);
+            }
+            y += 1;
+            if error < 0 {
+                error += 2 * y + 1;
+            } else {
+                x -= 1;
+                error += 2 * (y - x) + 1;
+            }
+        }
+    }
+
+    pub fn fill_circle(&mut self, cx: isize, cy: isize, radius: isize, on: bool) {
+        for y in -radius..=radius {
+            for x in -radius..=radius {
+                if x * x + y * y <= radius * radius {
+                    self.set(cx + x, cy + y, on);
+                }
+            }
+        }
+    }
+
+    /// Draw `text` with its top left corner at `x`, `y`
+    /// Returns the width of the text in pixels
+    pub fn text(&mut self, x: isize, y: isize, text: &str, size: FontSize, on: bool) -> usize {
+        let scale = size.scale() as isize;
+        for (i, chr) in text.chars().enumerate() {
+            let left = x + (i * size.advance()) as isize;
+            for (col, bits) in glyph(chr).iter().enumerate() {
+                for row in 0..GLYPH_HEIGHT {
+                    if bits & (1 << row) != 0 {
+                        let px = left + col as isize * scale;
+                        let py = y + row as isize * scale;
+                        self.fill_rect(px, py, px + scale - 1, py + scale - 1, on);
+                    }
+                }
+            }
+        }
+        size.text_width(text)
+    }
+
+    /// Draw `bitmap` with its top left corner at `x`, `y`
+    /// Only on pixels are drawn unless `opaque` is true
+    pub fn draw_bitmap(&mut self, x: isize, y: isize, bitmap: &Bitmap, opaque: bool) {
+        for by in 0..bitmap.height() {
+            for bx in 0..bitmap.width() {
+                let on = bitmap.get(bx, by);
+                if on || opaque {
+                    self.set(x + bx as isize, y + by as isize, on);
+                }
+            }
+        }
+    }
+}
+
+/// Byte index and bit mask for pixel
+fn index(x: isize, y: isize) -> Option<(usize, u8)> {
+    if x < 0 || y < 0 || x >= DISPLAY_WIDTH as isize || y >= DISPLAY_HEIGHT as isize {
+        return None;
+    }
+    let (x, y) = (x as usize, y as usize);
+    Some(((y * DISPLAY_WIDTH + x) / 8, 0x80 >> (x % 8)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pixels_are_packed_by_row_msb_first() {
+        let mut canvas = Canvas::new();
+        canvas.set(0, 0, true);
+        canvas.set(9, 0, true);
+        canvas.set(127, 0, true);
+        canvas.set(0, 1, true);
+        canvas.set(127, 31, true);
+        let bytes = canvas.bytes();
+        assert_eq!(bytes.len(), BITMAP_LEN);
+        assert_eq!(bytes[0], 0x80);
+        assert_eq!(bytes[1], 0x40);
+        assert_eq!(bytes[15], 0x01);
+        assert_eq!(bytes[16], 0x80);
+        assert_eq!(bytes[BITMAP_LEN - 1], 0x01);
+        assert_eq!(bytes.iter().map(|byte| byte.count_ones()).sum::<u32>(), 5);
+
+        canvas.set(0, 0, false);
+        assert_eq!(canvas.bytes()[0], 0x00);
+    }
+
+    #[test]
+    fn bytes_round_trip() {
+        let mut canvas = Canvas::new();
+        canvas.circle(64, 16, 10, true);
+        let Update::Bitmap(bytes) = canvas.to_update() else { panic!("not a bitmap") };
+        assert_eq!(Canvas::from_bytes(&bytes).unwrap(), canvas);
+        assert!(Canvas::from_bytes(&bytes[1..]).is_err());
+    }
+
+    #[test]
+    fn shapes_are_clipped() {
+        let mut canvas = Canvas::new();
+        canvas.set(-1, 0, true);
+        canvas.set(0, 32, true);
+        canvas.line(-10, 5, 200, 5, true);
+        assert!(!canvas.get(-1, 5));
+        assert!((0..128).all(|x| canvas.get(x, 5)));
+        assert_eq!(canvas.bytes().iter().map(|byte| byte.count_ones()).sum::<u32>(), 128);
+    }
+
+    #[test]
+    fn shapes() {
+        let mut canvas = Canvas::new();
+        canvas.rect(2, 2, 5, 4, true);
+        assert!(canvas.get(2, 2) && canvas.get(5, 4) && canvas.get(2, 3));
+        assert!(!canvas.get(3, 3));
+        canvas.fill_rect(5, 4, 2, 2, true);
+        assert!(canvas.get(3, 3));
+
+        canvas.clear();
+        canvas.fill_circle(10, 10, 2, true);
+        assert!(canvas.get(10, 10) && canvas.get(12, 10) && canvas.get(10, 8));
+        assert!(!canvas.get(12, 12));
+        canvas.invert();
+        assert!(!canvas.get(10, 10));
+        assert!(canvas.get(12, 12));
+    }
+
+    #[test]
+    fn text_is_scaled() {
+        let mut canvas = Canvas::new();
+        assert_eq!(canvas.text(0, 0, "!", FontSize::Small, true), 5);
+        // '!' is the middle column with a gap above the bottom row
+        assert!((0..5).all(|y| canvas.get(2, y)));
+        assert!(!canvas.get(2, 5));
+        assert!(canvas.get(2, 6));
+        assert!(!canvas.get(1, 0));
+
+        canvas.clear();
+        assert_eq!(canvas.text(0, 0, "!!", FontSize::Medium, true), 22);
+        assert!(canvas.get(4, 0) && canvas.get(5, 1));
+        assert!(!canvas.get(4, 10) && !canvas.get(5, 11));
+        assert!(canvas.get(16, 12));
+    }
+
+    #[test]
+    fn bitmaps_are_drawn_opaque_or_not() {
+        let mut bitmap = Bitmap::new(2, 1);
+        bitmap.set(0, 0, true);
+        let mut canvas = Canvas::new();
+        canvas.fill_rect(0, 0, 3, 0, true);
+        canvas.draw_bitmap(0, 0, &bitmap, false);
+        assert!(canvas.get(1, 0));
+        canvas.draw_bitmap(0, 0, &bitmap, true);
+        assert!(canvas.get(0, 0));
+        assert!(!canvas.get(1, 0));
+    }
+}
@@ -0,0 +1,273 @@
+use std::collections::VecDeque;
+use crate::canvas::Canvas;
+use crate::font::FontSize;
+use crate::layout::ROWS;
+
+/// Characters for a partially filled text cell, from empty to full
+const CELL_LEVELS: [char; 4] = [' ', '_', '=', '#'];
+const PROGRESS_FULL: char = '#';
+const PROGRESS_EMPTY: char = '-';
+
+/// Rolling window of the most recent values
+///
+/// Unless a fixed range is set the chart scale is the min and max of the values currently
+/// in the window
+#[derive(Debug, Clone)]
+pub struct Series {
+    capacity: usize,
+    values: VecDeque<f32>,
+    range: Option<(f32, f32)>,
+}
+
+impl Series {
+    pub fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(1);
+        Series { capacity, values: VecDeque::with_capacity(capacity), range: None }
+    }
+}
+
+impl Series {
+    /// Use a fixed scale instead of auto scaling, i.e. 0-100 for percentages
+    pub fn with_range(mut self, min: f32, max: f32) -> Self {
+        self.range = Some((min.min(max), min.max(max)));
+        self
+    }
+
+    /// Add value, dropping the oldest if full
+    pub fn push(&mut self, value: f32) {
+        if self.values.len() == self.capacity {
+            self.values.pop_front();
+        }
+        self.values.push_back(value);
+    }
+
+    pub fn clear(&mut self) {
+        self.values.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    pub fn latest(&self) -> Option<f32> {
+        self.values.back().copied()
+    }
+
+    /// Values oldest first
+    pub fn values(&self) -> impl Iterator<Item=&f32> {
+        self.values.iter()
+    }
+
+    /// Scale used for charts as (min, max)
+    pub fn range(&self) -> (f32, f32) {
+        if let Some(range) = self.range {
+            return range;
+        }
+        let min = self.values.iter().copied().fold(f32::INFINITY, f32::min);
+        let max = self.values.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+        if self.values.is_empty() {
+            (0.0, 1.0)
+        } else if min == max {
+            // flat data is drawn at the middle of the chart
+            (min - 1.0, max + 1.0)
+        } else {
+            (min, max)
+        }
+    }
+
+    /// `value` mapped to 0.0 - 1.0 using [Series::range]
+    pub fn normalise(&self, value: f32) -> f32 {
+        let (min, max) = self.range();
+        ((value - min) / (max - min)).clamp(0.0, 1.0)
+    }
+
+    /// Most recent `count` values, oldest first
+    fn last(&self, count: usize) -> impl Iterator<Item=&f32> {
+        self.values.iter().skip(self.values.len().saturating_sub(count))
+    }
+}
+
+/// Vertical bar graph, one column per value, newest on the right
+///
+/// Returns `height` rows (max 4) of `width` characters, top row first
+pub fn bar_graph(series: &Series, width: usize, height: usize) -> Vec<String> {
+    let height = height.clamp(1, ROWS);
+    let steps = CELL_LEVELS.len() - 1;
+    let values = series.last(width).collect::<Vec<&f32>>();
+    let padding = width - values.len();
+    let levels = values.iter()
+        .map(|value| (series.normalise(**value) * (height * steps) as f32).round() as usize)
+        .collect::<Vec<usize>>();
+
+    (0..height)
+        .map(|row| {
+            let floor = (height - row - 1) * steps;
+            let mut line = " ".repeat(padding);
+            for level in &levels {
+                let fill = level.saturating_sub(floor).min(steps);
+                line.push(CELL_LEVELS[fill]);
+            }
+            line
+        })
+        .collect()
+}
+
+/// Horizontal bar such as `[#####-----]`, `width` includes the brackets
+pub fn progress_bar(fraction: f32, width: usize) -> String {
+    let inner = width.saturating_sub(2);
+    let filled = (fraction.clamp(0.0, 1.0) * inner as f32).round() as usize;
+    format!("[{}{}]",
+            PROGRESS_FULL.to_string().repeat(filled),
+            PROGRESS_EMPTY.to_string().repeat(inner - filled))
+}
+
+/// Progress bar followed by the percentage, i.e. `[####----]  50%`
+pub fn progress_bar_with_percent(fraction: f32, width: usize) -> String {
+    let percent = format!("{:>4}%", (fraction.clamp(0.0, 1.0) * 100.0).round() as usize);
+    let bar = progress_bar(fraction, width.saturating_sub(percent.len()));
+    format!("{}{}", bar, percent)
+}
+
+/// Line through the most recent values, newest on the right
+/// The rect is from `x`, `y` (top left) and is `width` x `height` pixels
+pub fn sparkline(canvas: &mut Canvas, x: isize, y: isize, width: usize, height: usize, series: &Series) {
+    if width == 0 || height == 0 {
+        return;
+    }
+    let values = series.last(width).copied().collect::<Vec<f32>>();
+    let bottom = y + height as isize - 1;
+    let spacing = if values.len() > 1 { (width - 1) as f32 / (values.len() - 1) as f32 } else { 0.0 };
+    let points = values.iter()
+        .enumerate()
+        .map(|(i, value)| {
+            let px = x + (i as f32 * spacing).round() as isize;
+            let py = bottom - (series.normalise(*value) * (height - 1) as f32).round() as isize;
+            (px, py)
+        })
+        .collect::<Vec<(isize, isize)>>();
+
+    match points.as_slice() {
+        [] => {}
+        [(px, py)] => canvas.set(*px, *py, true),
+        _ => {
+            for pair in points.windows(2) {
+                canvas.line(pair[0].0, pair[0].1, pair[1].0, pair[1].1, true);
+            }
+        }
+    }
+}
+
+/// Framed sparkline with the max and min of the scale drawn on the left
+pub fn line_chart(canvas: &mut Canvas, x: isize, y: isize, width: usize, height: usize, series: &Series) {
+    let (min, max) = series.range();
+    let max_label = format_axis(max);
+    let min_label = format_axis(min);
+    let font = FontSize::Small;
+    let label_width = font.text_width(&max_label).max(font.text_width(&min_label)) as isize;
+
+    canvas.text(x, y, &max_label, font, true);
+    canvas.text(x, y + height as isize - font.height() as isize, &min_label, font, true);
+
+    let left = x + label_width + 2;
+    let right = x + width as isize - 1;
+    let bottom = y + height as isize - 1;
+    if right - left < 3 || height < 3 {
+        return;
+    }
+    canvas.rect(left, y, right, bottom, true);
+    sparkline(canvas, left + 1, y + 1, (right - left - 1) as usize, height - 2, series);
+}
+
+/// Short label for chart axis, uses k/M suffixes to save space
+fn format_axis(value: f32) -> String {
+    let abs = value.abs();
+    if abs >= 1_000_000.0 {
+        format!("{:.0}M", value / 1_000_000.0)
+    } else if abs >= 1_000.0 {
+        format!("{:.0}k", value / 1_000.0)
+    } else if abs >= 10.0 || value.fract() == 0.0 {
+        format!("{:.0}", value)
+    } else {
+        format!("{:.1}", value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn series(values: &[f32]) -> Series {
+        let mut series = Series::new(values.len());
+        values.iter().for_each(|value| series.push(*value));
+        series
+    }
+
+    #[test]
+    fn window_drops_oldest() {
+        let mut series = Series::new(3);
+        (1..=5).for_each(|value| series.push(value as f32));
+        assert_eq!(series.values().copied().collect::<Vec<f32>>(), vec![3.0, 4.0, 5.0]);
+        assert_eq!(series.latest(), Some(5.0));
+        series.clear();
+        assert!(series.is_empty());
+        assert_eq!(Series::new(0).capacity, 1);
+    }
+
+    #[test]
+    fn scaling() {
+        assert_eq!(Series::new(5).range(), (0.0, 1.0));
+        assert_eq!(series(&[4.0, 4.0]).range(), (3.0, 5.0));
+        let auto = series(&[2.0, 6.0, 4.0]);
+        assert_eq!(auto.range(), (2.0, 6.0));
+        assert_eq!(auto.normalise(4.0), 0.5);
+        let fixed = series(&[2.0, 6.0]).with_range(100.0, 0.0);
+        assert_eq!(fixed.range(), (0.0, 100.0));
+        assert_eq!(fixed.normalise(50.0), 0.5);
+        assert_eq!(fixed.normalise(150.0), 1.0);
+        assert_eq!(fixed.normalise(-1.0), 0.0);
+    }
+
+    #[test]
+    fn bar_graph_levels() {
+        let series = series(&[0.0, 1.0, 2.0, 4.0]).with_range(0.0, 4.0);
+        assert_eq!(bar_graph(&series, 5, 2), vec!["    #", "  =##"]);
+        assert_eq!(bar_graph(&series, 2, 1), vec!["=#"]);
+        assert_eq!(bar_graph(&series, 4, 9).len(), ROWS);
+    }
+
+    #[test]
+    fn progress_bars() {
+        assert_eq!(progress_bar(0.5, 12), "[#####-----]");
+        assert_eq!(progress_bar(2.0, 4), "[##]");
+        assert_eq!(progress_bar(-1.0, 4), "[--]");
+        assert_eq!(progress_bar_with_percent(0.5, 15), "[####----]  50%");
+    }
+
+    #[test]
+    fn sparkline_spans_the_rect() {
+        let mut canvas = Canvas::new();
+        sparkline(&mut canvas, 10, 2, 5, 4, &series(&[0.0, 10.0]).with_range(0.0, 10.0));
+        assert!(canvas.get(10, 5));
+        assert!(canvas.get(14, 2));
+        assert!(!canvas.get(10, 2));
+        assert!(!canvas.get(15, 1));
+
+        let mut canvas = Canvas::new();
+        sparkline(&mut canvas, 0, 0, 5, 4, &series(&[7.0]));
+        assert!(canvas.get(0, 1));
+        assert_eq!(canvas.bytes().iter().map(|byte| byte.count_ones()).sum::<u32>(), 1);
+    }
+
+    #[test]
+    fn axis_labels() {
+        assert_eq!(format_axis(3_000_000.0), "3M");
+        assert_eq!(format_axis(-12_000.0), "-12k");
+        assert_eq!(format_axis(42.7), "43");
+        assert_eq!(format_axis(5.0), "5");
+        assert_eq!(format_axis(0.5), "0.5");
+    }
+}
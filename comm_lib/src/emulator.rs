@@ -0,0 +1,388 @@
+use std::collections::VecDeque;
+use std::io::{Read, Write};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+use serialport::{ClearBuffer, DataBits, FlowControl, Parity, SerialPort, StopBits};
+use crate::{BITMAP_LEN, BUTTON_COUNT, LED_BLUE, LED_GREEN, LED_RED, Port, TEXT_MAX_LEN};
+use crate::canvas::Canvas;
+use crate::layout::{Align, COLUMNS, fit, ROWS};
+use crate::manager::{COMMAND_BUTTON_PRESSED, COMMAND_BUTTON_RELEASED, COMMAND_DRAW_BITMAP, COMMAND_LED_BLUE, COMMAND_LED_GREEN, COMMAND_LED_ON, COMMAND_LED_RED, COMMAND_SET_LED, COMMAND_SET_ROW, COMMAND_SET_TEXT};
+
+const EMULATOR_NAME: &str = "emulator";
+
+/// What the emulated screen is currently showing
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Screen {
+    Text(String),
+    Bitmap(Box<Canvas>),
+}
+
+#[derive(Debug)]
+struct EmulatorState {
+    leds: [bool; 3],
+    screen: Screen,
+    outgoing: VecDeque<u8>,
+}
+
+/// In memory device that implements the same serial protocol as the hardware
+///
+/// Pass the port to [crate::manager::DeviceManager::new] and use the handle to press buttons and
+/// inspect the LEDs and screen
+pub fn emulated_device() -> (Port, EmulatorHandle) {
+    let state = Arc::new(Mutex::new(EmulatorState {
+        leds: [false; 3],
+        screen: Screen::Text(String::new()),
+        outgoing: VecDeque::new(),
+    }));
+    let port = EmulatedPort { state: state.clone(), incoming: vec![], timeout: Duration::from_millis(0) };
+    (Box::new(port), EmulatorHandle { state })
+}
+
+#[derive(Debug, Clone)]
+pub struct EmulatorHandle {
+    state: Arc<Mutex<EmulatorState>>,
+}
+
+impl EmulatorHandle {
+    pub fn press(&self, button: usize) {
+        self.send_button(COMMAND_BUTTON_PRESSED, button);
+    }
+
+    pub fn release(&self, button: usize) {
+        self.send_button(COMMAND_BUTTON_RELEASED, button);
+    }
+
+    /// LED states indexed by [LED_GREEN], [LED_BLUE] and [LED_RED]
+    pub fn leds(&self) -> [bool; 3] {
+        self.lock().leds
+    }
+
+    pub fn screen(&self) -> Screen {
+        self.lock().screen.clone()
+    }
+
+    fn send_button(&self, command: u8, button: usize) {
+        if button < BUTTON_COUNT {
+            self.lock().outgoing.extend([command, button as u8]);
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, EmulatorState> {
+        self.state.lock().expect("Emulator state poisoned")
+    }
+}
+
+struct EmulatedPort {
+    state: Arc<Mutex<EmulatorState>>,
+    incoming: Vec<u8>,
+    timeout: Duration,
+}
+
+impl EmulatedPort {
+    /// Process all complete commands in `incoming`
+    fn process(&mut self) {
+        let mut state = self.state.lock().expect("Emulator state poisoned");
+        while let Some(command) = self.incoming.first() {
+            match *command {
+                COMMAND_SET_LED => {
+                    if self.incoming.len() < 3 {
+                        return;
+                    }
+                    let led = match self.incoming[1] {
+                        COMMAND_LED_GREEN => Some(LED_GREEN),
+                        COMMAND_LED_BLUE => Some(LED_BLUE),
+                        COMMAND_LED_RED => Some(LED_RED),
+                        _ => None
+                    };
+                    if let Some(led) = led {
+                        state.leds[led] = self.incoming[2] == COMMAND_LED_ON;
+                    }
+                    self.incoming.drain(..3);
+                }
+                COMMAND_SET_TEXT => {
+                    // like the hardware, text is always the full screen
+                    if self.incoming.len() < TEXT_MAX_LEN + 1 {
+                        return;
+                    }
+                    let text = self.incoming.drain(..=TEXT_MAX_LEN).skip(1).map(|b| b as char).collect();
+                    state.screen = Screen::Text(text);
+                }
+                COMMAND_SET_ROW => {
+                    if self.incoming.len() < COLUMNS + 2 {
+                        return;
+                    }
+                    let bytes = self.incoming.drain(..COLUMNS + 2).collect::<Vec<u8>>();
+                    let row = bytes[1] as usize;
+                    if row < ROWS {
+                        // like the hardware, setting a row on a bitmap starts from a blank screen
+                        let text = match &state.screen {
+                            Screen::Text(text) => text.clone(),
+                            Screen::Bitmap(_) => String::new()
+                        };
+                        let mut chars = fit(&text, TEXT_MAX_LEN, Align::Left).chars().collect::<Vec<char>>();
+                        for (idx, byte) in bytes[2..].iter().enumerate() {
+                            chars[row * COLUMNS + idx] = *byte as char;
+                        }
+                        state.screen = Screen::Text(chars.into_iter().collect());
+                    }
+                }
+                COMMAND_DRAW_BITMAP => {
+                    if self.incoming.len() < BITMAP_LEN + 1 {
+                        return;
+                    }
+                    let bytes = self.incoming.drain(..=BITMAP_LEN).skip(1).collect::<Vec<u8>>();
+                    if let Ok(canvas) = Canvas::from_bytes(&bytes) {
+                        state.screen = Screen::Bitmap(Box::new(canvas));
+                    }
+                }
+                _ => {
+                    self.incoming.remove(0);
+                }
+            }
+        }
+    }
+}
+
+impl Read for EmulatedPort {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let mut state = self.state.lock().expect("Emulator state poisoned");
+        let count = buf.len().min(state.outgoing.len());
+        for (target, byte) in buf.iter_mut().zip(state.outgoing.drain(..count)) {
+            *target = byte;
+        }
+        Ok(count)
+    }
+}
+
+impl Write for EmulatedPort {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.incoming.extend_from_slice(buf);
+        self.process();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl SerialPort for EmulatedPort {
+    fn name(&self) -> Option<String> {
+        Some(EMULATOR_NAME.to_owned())
+    }
+
+    fn baud_rate(&self) -> serialport::Result<u32> {
+        Ok(9600)
+    }
+
+    fn data_bits(&self) -> serialport::Result<DataBits> {
+        Ok(DataBits::Eight)
+    }
+
+    fn flow_control(&self) -> serialport::Result<FlowControl> {
+        Ok(FlowControl::None)
+    }
+
+    fn parity(&self) -> serialport::Result<Parity> {
+        Ok(Parity::None)
+    }
+
+    fn stop_bits(&self) -> serialport::Result<StopBits> {
+        Ok(StopBits::One)
+    }
+
+    fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    fn set_baud_rate(&mut self, _: u32) -> serialport::Result<()> {
+        Ok(())
+    }
+
+    fn set_data_bits(&mut self, _: DataBits) -> serialport::Result<()> {
+        Ok(())
+    }
+
+    fn set_flow_control(&mut self, _: FlowControl) -> serialport::Result<()> {
+        Ok(())
+    }
+
+    fn set_parity(&mut self, _: Parity) -> serialport::Result<()> {
+        Ok(())
+    }
+
+    fn set_stop_bits(&mut self, _: StopBits) -> serialport::Result<()> {
+        Ok(())
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> serialport::Result<()> {
+        self.timeout = timeout;
+        Ok(())
+    }
+
+    fn write_request_to_send(&mut self, _: bool) -> serialport::Result<()> {
+        Ok(())
+    }
+
+    fn write_data_terminal_ready(&mut self, _: bool) -> serialport::Result<()> {
+        Ok(())
+    }
+
+    fn read_clear_to_send(&mut self) -> serialport::Result<bool> {
+        Ok(true)
+    }
+
+    fn read_data_set_ready(&mut self) -> serialport::Result<bool> {
+        Ok(true)
+    }
+
+    fn read_ring_indicator(&mut self) -> serialport::Result<bool> {
+        Ok(false)
+    }
+
+    fn read_carrier_detect(&mut self) -> serialport::Result<bool> {
+        Ok(true)
+    }
+
+    fn bytes_to_read(&self) -> serialport::Result<u32> {
+        Ok(self.state.lock().expect("Emulator state poisoned").outgoing.len() as u32)
+    }
+
+    fn bytes_to_write(&self) -> serialport::Result<u32> {
+        Ok(0)
+    }
+
+    fn clear(&self, buffer_to_clear: ClearBuffer) -> serialport::Result<()> {
+        if let ClearBuffer::Input | ClearBuffer::All = buffer_to_clear {
+            self.state.lock().expect("Emulator state poisoned").outgoing.clear();
+        }
+        Ok(())
+    }
+
+    fn try_clone(&self) -> serialport::Result<Box<dyn SerialPort>> {
+        Ok(Box::new(EmulatedPort { state: self.state.clone(), incoming: vec![], timeout: self.timeout }))
+    }
+
+    fn set_break(&self) -> serialport::Result<()> {
+        Ok(())
+    }
+
+    fn clear_break(&self) -> serialport::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layout::compose;
+    use crate::manager::{DeviceManager, Update};
+
+    #[test]
+    fn text_is_the_full_screen() {
+        let (mut port, device) = emulated_device();
+        port.write_all(&[COMMAND_SET_TEXT, b'h', b'i']).unwrap();
+        assert_eq!(device.screen(), Screen::Text(String::new()));
+
+        // the rest of the text and the next command in one write
+        let mut bytes = vec![b' '; TEXT_MAX_LEN - 2];
+        bytes.extend([COMMAND_SET_LED, COMMAND_LED_RED, COMMAND_LED_ON]);
+        port.write_all(&bytes).unwrap();
+        assert_eq!(device.screen(), Screen::Text(fit("hi", TEXT_MAX_LEN, Align::Left)));
+        assert_eq!(device.leds(), [false, false, true]);
+    }
+
+    #[test]
+    fn short_text_is_padded() {
+        let (port, device) = emulated_device();
+        let mut manager = DeviceManager::new(port);
+        manager.send(Update::Text(compose(&["one".to_owned(), "two".to_owned()]))).unwrap();
+        manager.send(Update::LED(LED_GREEN, true)).unwrap();
+        assert_eq!(device.screen(), Screen::Text(compose(&["one".to_owned(), "two".to_owned(), String::new(), String::new()])));
+        assert_eq!(device.leds(), [true, false, false]);
+    }
+
+    #[test]
+    fn leds() {
+        let (port, device) = emulated_device();
+        let mut manager = DeviceManager::new(port);
+        manager.send(Update::LED(LED_RED, true)).unwrap();
+        manager.send(Update::LED(LED_BLUE, true)).unwrap();
+        assert_eq!(device.leds(), [false, true, true]);
+        manager.send(Update::LED(LED_RED, false)).unwrap();
+        assert_eq!(device.leds(), [false, true, false]);
+    }
+
+    #[test]
+    fn rows_replace_one_row() {
+        let (port, device) = emulated_device();
+        let mut manager = DeviceManager::new(port);
+        let rows = ["a", "b", "c", "d"].map(|row| row.to_owned());
+        manager.send(Update::Text(compose(&rows))).unwrap();
+        manager.send(Update::Row(2, "changed".to_owned())).unwrap();
+        assert_eq!(device.screen(), Screen::Text(compose(&["a".to_owned(), "b".to_owned(), "changed".to_owned(), "d".to_owned()])));
+    }
+
+    #[test]
+    fn rows_are_the_index_then_the_columns() {
+        let (mut port, device) = emulated_device();
+        let mut bytes = vec![COMMAND_SET_ROW, 3];
+        bytes.extend(fit("last", COLUMNS, Align::Right).bytes());
+        port.write_all(&bytes[..COLUMNS]).unwrap();
+        assert_eq!(device.screen(), Screen::Text(String::new()));
+        port.write_all(&bytes[COLUMNS..]).unwrap();
+        let Screen::Text(text) = device.screen() else { panic!("not text") };
+        assert_eq!(&text[3 * COLUMNS..], "                 last");
+        assert_eq!(text.trim(), "last");
+
+        // rows past the screen are ignored
+        bytes[1] = ROWS as u8;
+        port.write_all(&bytes).unwrap();
+        assert_eq!(device.screen(), Screen::Text(text));
+    }
+
+    #[test]
+    fn rows_on_a_bitmap_start_blank() {
+        let (port, device) = emulated_device();
+        let mut manager = DeviceManager::new(port);
+        manager.send(Canvas::new().to_update()).unwrap();
+        manager.send(Update::Row(1, "row".to_owned())).unwrap();
+        assert_eq!(device.screen(), Screen::Text(compose(&[String::new(), "row".to_owned(), String::new(), String::new()])));
+    }
+
+    #[test]
+    fn bitmaps() {
+        let (mut port, device) = emulated_device();
+        let mut canvas = Canvas::new();
+        canvas.line(0, 0, 127, 31, true);
+        let mut bytes = vec![COMMAND_DRAW_BITMAP];
+        bytes.extend_from_slice(canvas.bytes());
+        // split like a serial write can be
+        port.write_all(&bytes[..100]).unwrap();
+        assert_eq!(device.screen(), Screen::Text(String::new()));
+        port.write_all(&bytes[100..]).unwrap();
+        assert_eq!(device.screen(), Screen::Bitmap(Box::new(canvas)));
+    }
+
+    #[test]
+    fn buttons() {
+        let (port, device) = emulated_device();
+        let mut manager = DeviceManager::new(port);
+        device.press(3);
+        device.press(BUTTON_COUNT);
+        manager.recv().unwrap();
+        assert_eq!(manager.get_button_state(), [false, false, false, true]);
+        device.release(3);
+        manager.recv().unwrap();
+        let events = manager.take_events().into_iter().map(|event| (event.button, event.pressed)).collect::<Vec<(usize, bool)>>();
+        assert_eq!(events, vec![(3, true), (3, false)]);
+        assert_eq!(manager.get_button_state(), [false; BUTTON_COUNT]);
+    }
+
+    #[test]
+    fn unknown_commands_are_skipped() {
+        let (mut port, device) = emulated_device();
+        port.write_all(&[0xFF, COMMAND_SET_LED, COMMAND_LED_GREEN, COMMAND_LED_ON]).unwrap();
+        assert_eq!(device.leds(), [true, false, false]);
+    }
+}
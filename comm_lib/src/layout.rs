@@ -0,0 +1,82 @@
+use crate::TEXT_MAX_LEN;
+
+/// Number of characters per row on the display
+pub const COLUMNS: usize = 21;
+/// Number of text rows on the display
+pub const ROWS: usize = 4;
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum Align {
+    #[default]
+    Left,
+    Center,
+    Right,
+}
+
+/// Pad or truncate `text` so that it is exactly `width` characters
+pub fn fit(text: &str, width: usize, align: Align) -> String {
+    let chars = text.chars().take(width).collect::<Vec<char>>();
+    let space = width - chars.len();
+    let (left, right) = match align {
+        Align::Left => (0, space),
+        Align::Center => (space / 2, space - (space / 2)),
+        Align::Right => (space, 0),
+    };
+    format!("{}{}{}", " ".repeat(left), chars.iter().collect::<String>(), " ".repeat(right))
+}
+
+/// Combine up to [ROWS] lines into the text for `Update::Text`
+/// Each line is padded or truncated to [COLUMNS], missing rows are left blank
+pub fn compose(rows: &[String]) -> String {
+    let mut text = String::with_capacity(TEXT_MAX_LEN);
+    for row in rows.iter().take(ROWS) {
+        text.push_str(&fit(row, COLUMNS, Align::Left));
+    }
+    text
+}
+
+/// Split device text back into its rows
+pub fn split(text: &str) -> Vec<String> {
+    let chars = text.chars().collect::<Vec<char>>();
+    (0..ROWS)
+        .map(|row| {
+            let start = (row * COLUMNS).min(chars.len());
+            let end = ((row + 1) * COLUMNS).min(chars.len());
+            chars[start..end].iter().collect()
+        })
+        .collect()
+}
+
+/// Break `text` into lines of at most `width` characters, at spaces where possible
+pub fn wrap(text: &str, width: usize) -> Vec<String> {
+    let mut lines = vec![];
+    let mut line = String::new();
+    for word in text.split(' ') {
+        let mut word = word.chars().collect::<Vec<char>>();
+        let line_len = line.chars().count();
+        if line_len > 0 && line_len + 1 + word.len() <= width {
+            line.push(' ');
+            line.extend(word);
+            continue;
+        }
+        if line_len > 0 {
+            lines.push(std::mem::take(&mut line));
+        }
+        while word.len() > width {
+            lines.push(word.drain(..width).collect());
+        }
+        line = word.into_iter().collect();
+    }
+    if !line.is_empty() || lines.is_empty() {
+        lines.push(line);
+    }
+    lines
+}
+
+/// `width` characters of `text` starting at `offset`, wrapping back round to the start after a gap
+/// Used to scroll text that is too long to fit
+pub fn marquee(text: &str, width: usize, offset: usize) -> String {
+    const GAP: &str = "   ";
+    let chars = text.chars().chain(GAP.chars()).collect::<Vec<char>>();
+    (0..width).map(|idx| chars[(offset + idx) % chars.len()]).collect()
+}
@@ -1,8 +1,18 @@
 pub mod manager;
+pub mod layout;
+pub mod text_entry;
+pub mod font;
+pub mod bitmap;
+pub mod canvas;
+pub mod emulator;
+pub mod chart;
+pub mod big_digits;
+pub mod qr;
 
 use serialport::SerialPort;
 use thiserror::Error;
-use crate::CommLibError::{InvalidLed, NoDeviceFound, NotAscii, NotSupported, SendError, TooLong};
+use tracing::debug;
+use crate::CommLibError::{InvalidBitmap, InvalidImage, InvalidLed, InvalidRow, NoDeviceFound, NotAscii, NotSupported, QrTooLong, RowTooLong, SendError, TooLong, UnsupportedChar};
 
 pub type Port = Box<dyn SerialPort>;
 pub type CommLibResult<T> = Result<T, CommLibError>;
@@ -23,11 +33,32 @@ pub enum CommLibError {
     InvalidLed(usize),
     #[error("Text is too long, max 84 chars")]
     TooLong,
+    #[error("Invalid row, must be 0 - 3, was {0}")]
+    InvalidRow(usize),
+    #[error("Row text is too long, max 21 chars")]
+    RowTooLong,
+    #[error("Bitmap must be 512 bytes, was {0}")]
+    InvalidBitmap(usize),
+    #[error("Invalid image: {0}")]
+    InvalidImage(String),
+    #[error("Character can not be drawn: {0:?}")]
+    UnsupportedChar(char),
+    #[error("Text is too long for a QR code that fits on the screen ({0} bytes), max is 32 bytes or 47 if only using A-Z, 0-9, space and $%*+-./:")]
+    QrTooLong(usize),
 }
 
 pub const LED_GREEN: usize = 0;
 pub const LED_BLUE: usize = 1;
 pub const LED_RED: usize = 2;
+pub const LED_COUNT: usize = 3;
+
+pub const BUTTON_COUNT: usize = 4;
+pub const TEXT_MAX_LEN: usize = 84;
+
+pub const DISPLAY_WIDTH: usize = 128;
+pub const DISPLAY_HEIGHT: usize = 32;
+/// Size of a full screen 1bpp bitmap, see [manager::Update::Bitmap]
+pub const BITMAP_LEN: usize = DISPLAY_WIDTH * DISPLAY_HEIGHT / 8;
 
 pub fn get_potential_devices() -> CommLibResult<Vec<String>> {
     match serialport::available_ports() {
@@ -43,6 +74,7 @@ pub fn get_potential_devices() -> CommLibResult<Vec<String>> {
 
 pub fn get_best_match_device() -> CommLibResult<Port> {
     let mut list = get_potential_devices()?;
+    debug!("Potential devices: {:?}", list);
     if list.is_empty() {
         Err(NoDeviceFound)
     } else {
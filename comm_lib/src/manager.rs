@@ -1,31 +1,38 @@
-use crate::{CommLibResult, InvalidLed, NotAscii, Port, SendError, TooLong};
+use std::collections::VecDeque;
+use std::time::Instant;
+use tracing::{trace, warn};
+use crate::{BITMAP_LEN, BUTTON_COUNT, CommLibResult, InvalidBitmap, InvalidLed, InvalidRow, NotAscii, Port, RowTooLong, SendError, TEXT_MAX_LEN, TooLong};
+use crate::layout::{Align, COLUMNS, fit, ROWS};
 use crate::CommLibError::ReadError;
 use crate::manager::Update::LED;
 use crate::LED_GREEN;
 use crate::LED_BLUE;
 use crate::LED_RED;
 
-const COMMAND_BUTTON_PRESSED: u8 = 0x01;
-const COMMAND_BUTTON_RELEASED: u8 = 0x02;
-const COMMAND_SET_LED: u8 = 0x03;
-const COMMAND_SET_TEXT: u8 = 0x04;
+pub(crate) const COMMAND_BUTTON_PRESSED: u8 = 0x01;
+pub(crate) const COMMAND_BUTTON_RELEASED: u8 = 0x02;
+pub(crate) const COMMAND_SET_LED: u8 = 0x03;
+pub(crate) const COMMAND_SET_TEXT: u8 = 0x04;
+pub(crate) const COMMAND_DRAW_BITMAP: u8 = 0x06;
+pub(crate) const COMMAND_SET_ROW: u8 = 0x07;
 
-const COMMAND_LED_BLUE: u8 = 0;
-const COMMAND_LED_RED: u8 = 1;
-const COMMAND_LED_GREEN: u8 = 2;
-const COMMAND_LED_OFF: u8 = 0;
-const COMMAND_LED_ON: u8 = 1;
+pub(crate) const COMMAND_LED_BLUE: u8 = 0;
+pub(crate) const COMMAND_LED_RED: u8 = 1;
+pub(crate) const COMMAND_LED_GREEN: u8 = 2;
+pub(crate) const COMMAND_LED_OFF: u8 = 0;
+pub(crate) const COMMAND_LED_ON: u8 = 1;
 
 /// Used to communicate with hardware device
 /// All calls are blocking
 pub struct DeviceManager {
     port: Port,
-    buttons: [bool; 4],
+    buttons: [bool; BUTTON_COUNT],
+    events: VecDeque<ButtonEvent>,
 }
 
 impl DeviceManager {
     pub fn new(port: Port) -> Self {
-        DeviceManager { port, buttons: [false, false, false, false] }
+        DeviceManager { port, buttons: [false; BUTTON_COUNT], events: VecDeque::new() }
     }
 }
 
@@ -33,18 +40,33 @@ impl DeviceManager {
     /// Send update to device
     pub fn send(&mut self, update: Update) -> CommLibResult<()> {
         update.validate()?;
+        trace!("Sending {:?}", update);
         match update {
             Update::LED(_, _) => {
-                self.port.write(&[
+                self.port.write_all(&[
                     COMMAND_SET_LED,
                     update.get_command_led(),
                     update.get_command_led_state()]
                 ).map_err(|err| SendError(err.to_string()))?;
             }
             Update::Text(str) => {
-                let mut bytes = str.chars().map(|c| c as u8).collect::<Vec<u8>>();
-                bytes.insert(0, COMMAND_SET_TEXT);
-                self.port.write(&bytes)
+                // the device always reads the full screen of text
+                let mut bytes = vec![COMMAND_SET_TEXT];
+                bytes.extend(fit(&str, TEXT_MAX_LEN, Align::Left).chars().map(|c| c as u8));
+                self.port.write_all(&bytes)
+                    .map_err(|err| SendError(err.to_string()))?;
+            }
+            Update::Row(row, str) => {
+                let mut bytes = vec![COMMAND_SET_ROW, row as u8];
+                bytes.extend(fit(&str, COLUMNS, Align::Left).chars().map(|c| c as u8));
+                self.port.write_all(&bytes)
+                    .map_err(|err| SendError(err.to_string()))?;
+            }
+            Update::Bitmap(bitmap) => {
+                let mut bytes = Vec::with_capacity(BITMAP_LEN + 1);
+                bytes.push(COMMAND_DRAW_BITMAP);
+                bytes.extend_from_slice(&bitmap);
+                self.port.write_all(&bytes)
                     .map_err(|err| SendError(err.to_string()))?;
             }
         }
@@ -55,13 +77,26 @@ impl DeviceManager {
     pub fn recv(&mut self) -> CommLibResult<()> {
         match self.port.bytes_to_read() {
             Ok(num) => {
-                if num > 1 {
+                let mut num = num;
+                while num > 1 {
                     let mut data = [0, 0];
                     self.port.read_exact(&mut data).map_err(|err| ReadError(err.to_string()))?;
+                    num -= 2;
+                    let button = data[1] as usize;
+                    if button >= BUTTON_COUNT {
+                        warn!("Unknown button: {:?}", data);
+                        continue;
+                    }
                     match data[0] {
-                        COMMAND_BUTTON_PRESSED => self.buttons[data[1] as usize] = true,
-                        COMMAND_BUTTON_RELEASED => self.buttons[data[1] as usize] = false,
-                        _ => println!("Unknown command: {:?}", data)
+                        COMMAND_BUTTON_PRESSED => {
+                            self.buttons[button] = true;
+                            self.events.push_back(ButtonEvent::new(button, true));
+                        }
+                        COMMAND_BUTTON_RELEASED => {
+                            self.buttons[button] = false;
+                            self.events.push_back(ButtonEvent::new(button, false));
+                        }
+                        _ => warn!("Unknown command: {:?}", data)
                     }
                 }
             }
@@ -70,15 +105,48 @@ impl DeviceManager {
         Ok(())
     }
 
+    /// Name of the serial port the device is connected to
+    pub fn port_name(&self) -> Option<String> {
+        self.port.name()
+    }
+
     /// Returns last known button state
-    pub fn get_button_state(&self) -> [bool; 4] {
+    pub fn get_button_state(&self) -> [bool; BUTTON_COUNT] {
         self.buttons
     }
+
+    /// Returns and clears button presses/releases received since the last call, oldest first
+    pub fn take_events(&mut self) -> Vec<ButtonEvent> {
+        self.events.drain(..).collect()
+    }
+}
+
+/// A single button press or release as reported by the device
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ButtonEvent {
+    pub button: usize,
+    pub pressed: bool,
+    /// When the event was read from the port
+    pub time: Instant,
 }
 
+impl ButtonEvent {
+    pub fn new(button: usize, pressed: bool) -> Self {
+        ButtonEvent { button, pressed, time: Instant::now() }
+    }
+}
+
+#[derive(Debug)]
 pub enum Update {
     LED(usize, bool),
+    /// Whole screen of text, rows of [COLUMNS] one after the other, padded to [TEXT_MAX_LEN]
     Text(String),
+    /// Replace one row of text (0 - 3) leaving the others as they are, padded to [COLUMNS]
+    /// Only valid while the screen is showing text
+    Row(usize, String),
+    /// Full screen 1bpp image, rows top to bottom, 16 bytes per row, most significant bit is leftmost
+    /// See [crate::canvas::Canvas]
+    Bitmap(Vec<u8>),
 }
 
 impl Update {
@@ -87,19 +155,21 @@ impl Update {
         match self {
             Update::LED(id, _) => if id > &2 { return Err(InvalidLed(*id)); }
             Update::Text(text) => {
-                let mut invalids = vec![];
-                if text.chars().count() > 84 {
+                if text.chars().count() > TEXT_MAX_LEN {
                     return Err(TooLong);
                 }
-                for (i, chr) in text.chars().enumerate() {
-                    if !chr.is_ascii_graphic() && chr as u8 != 32 {
-                        invalids.push(i);
-                    }
+                validate_ascii(text)?;
+            }
+            Update::Row(row, text) => {
+                if *row >= ROWS {
+                    return Err(InvalidRow(*row));
                 }
-                if !invalids.is_empty() {
-                    return Err(NotAscii(invalids));
+                if text.chars().count() > COLUMNS {
+                    return Err(RowTooLong);
                 }
+                validate_ascii(text)?;
             }
+            Update::Bitmap(bitmap) => if bitmap.len() != BITMAP_LEN { return Err(InvalidBitmap(bitmap.len())); }
         }
         Ok(())
     }
@@ -127,3 +197,16 @@ impl Update {
         panic!("Impossible")
     }
 }
+
+fn validate_ascii(text: &str) -> CommLibResult<()> {
+    let mut invalids = vec![];
+    for (i, chr) in text.chars().enumerate() {
+        if !chr.is_ascii_graphic() && chr as u8 != 32 {
+            invalids.push(i);
+        }
+    }
+    if !invalids.is_empty() {
+        return Err(NotAscii(invalids));
+    }
+    Ok(())
+}
@@ -0,0 +1,124 @@
+use qrcodegen::{QrCode, QrCodeEcc, QrSegment, Version};
+use crate::{CommLibResult, DISPLAY_HEIGHT, DISPLAY_WIDTH, QrTooLong};
+use crate::canvas::Canvas;
+use crate::font::FontSize;
+
+/// Largest QR version that fits on the screen with a readable module size
+pub const QR_MAX_VERSION: u8 = 2;
+/// Light border around the code in modules, the spec asks for 4 but there isn't room
+const QUIET_ZONE: usize = 2;
+const CAPTION_GAP: usize = 4;
+
+/// Draw a QR code for `text` with its top left corner (including quiet zone) at `x`, `y`
+///
+/// The screen lights "light" modules and leaves "dark" modules off, so it scans like a printed code.
+/// The largest module size that fits the screen height is used.
+/// Returns the width in pixels or [QrTooLong] if `text` needs a code larger than version 2
+pub fn draw_qr(canvas: &mut Canvas, x: isize, y: isize, text: &str) -> CommLibResult<usize> {
+    let segments = QrSegment::make_segments(text);
+    let code = QrCode::encode_segments_advanced(&segments, QrCodeEcc::Low, Version::new(1), Version::new(QR_MAX_VERSION), None, true)
+        .map_err(|_| QrTooLong(text.len()))?;
+
+    let modules = code.size() as usize + QUIET_ZONE * 2;
+    let scale = DISPLAY_HEIGHT.min(DISPLAY_WIDTH) / modules;
+    if scale == 0 {
+        return Err(QrTooLong(text.len()));
+    }
+    let width = modules * scale;
+    canvas.fill_rect(x, y, x + width as isize - 1, y + width as isize - 1, true);
+    for my in 0..code.size() {
+        for mx in 0..code.size() {
+            if code.get_module(mx, my) {
+                let px = x + ((mx as usize + QUIET_ZONE) * scale) as isize;
+                let py = y + ((my as usize + QUIET_ZONE) * scale) as isize;
+                canvas.fill_rect(px, py, px + scale as isize - 1, py + scale as isize - 1, false);
+            }
+        }
+    }
+    Ok(width)
+}
+
+/// Canvas with the QR code on the left, vertically centered, and `caption` lines to the right
+pub fn qr_with_caption(text: &str, caption: &[&str]) -> CommLibResult<Canvas> {
+    let mut canvas = Canvas::new();
+    let mut probe = Canvas::new();
+    let width = draw_qr(&mut probe, 0, 0, text)?;
+    let y = ((DISPLAY_HEIGHT - width) / 2) as isize;
+    draw_qr(&mut canvas, 0, y, text)?;
+
+    let font = FontSize::Small;
+    let line_height = font.height() + 1;
+    let caption_x = (width + CAPTION_GAP) as isize;
+    for (i, line) in caption.iter().take(DISPLAY_HEIGHT / line_height).enumerate() {
+        canvas.text(caption_x, (i * line_height) as isize, line, font, true);
+    }
+    Ok(canvas)
+}
+
+/// Wi-Fi join string understood by phone cameras
+/// `password` of `None` is for open networks
+pub fn wifi_join_text(ssid: &str, password: Option<&str>) -> String {
+    match password {
+        Some(password) => format!("WIFI:T:WPA;S:{};P:{};;", escape_wifi(ssid), escape_wifi(password)),
+        None => format!("WIFI:T:nopass;S:{};;", escape_wifi(ssid)),
+    }
+}
+
+fn escape_wifi(text: &str) -> String {
+    let mut output = String::with_capacity(text.len());
+    for chr in text.chars() {
+        if matches!(chr, '\\' | ';' | ',' | ':' | '"') {
+            output.push('\\');
+        }
+        output.push(chr);
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CommLibError;
+
+    #[test]
+    fn sizes() {
+        let mut canvas = Canvas::new();
+        // version 1 is 21 modules and version 2 is 25, plus the quiet zone
+        assert_eq!(draw_qr(&mut canvas, 0, 0, "HELLO").unwrap(), 25);
+        assert_eq!(draw_qr(&mut canvas, 0, 0, &"a".repeat(32)).unwrap(), 29);
+        assert_eq!(draw_qr(&mut canvas, 0, 0, &"A".repeat(47)).unwrap(), 29);
+        assert!(matches!(draw_qr(&mut canvas, 0, 0, &"a".repeat(33)), Err(CommLibError::QrTooLong(33))));
+        assert!(matches!(draw_qr(&mut canvas, 0, 0, &"A".repeat(48)), Err(CommLibError::QrTooLong(48))));
+    }
+
+    #[test]
+    fn dark_modules_are_off() {
+        let mut canvas = Canvas::new();
+        draw_qr(&mut canvas, 1, 1, "HELLO").unwrap();
+        assert!(!canvas.get(0, 0));
+        // quiet zone, then the finder pattern's dark corner and light ring
+        assert!(canvas.get(1, 1) && canvas.get(2, 2));
+        assert!(!canvas.get(3, 3));
+        assert!(canvas.get(4, 4));
+        assert!(canvas.get(25, 25));
+        assert!(!canvas.get(26, 26));
+    }
+
+    #[test]
+    fn caption_is_right_of_the_code() {
+        let canvas = qr_with_caption("HELLO", &["Scan me"]).unwrap();
+        // 25 pixels tall, centered on the 32 pixel screen
+        assert!(!canvas.get(0, 2));
+        assert!(canvas.get(0, 3) && canvas.get(0, 27));
+        assert!(!canvas.get(0, 28));
+        let caption_x = 25 + CAPTION_GAP as isize;
+        assert!((0..32).all(|y| (25..caption_x).all(|x| !canvas.get(x, y))));
+        assert!((0..7).any(|y| (caption_x..128).any(|x| canvas.get(x, y))));
+    }
+
+    #[test]
+    fn wifi_text_is_escaped() {
+        assert_eq!(wifi_join_text("Home;Net", Some(r"p:a\ss")), r"WIFI:T:WPA;S:Home\;Net;P:p\:a\\ss;;");
+        assert_eq!(wifi_join_text("Cafe", None), "WIFI:T:nopass;S:Cafe;;");
+    }
+}
@@ -0,0 +1,295 @@
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+use crate::{CommLibResult, TEXT_MAX_LEN};
+use crate::layout::{Align, COLUMNS, compose, fit};
+use crate::manager::{ButtonEvent, DeviceManager, Update};
+
+const BUTTON_PREV: usize = 0;
+const BUTTON_NEXT: usize = 1;
+const BUTTON_CURSOR: usize = 2;
+const BUTTON_CONFIRM: usize = 3;
+
+const EMPTY_SLOT: char = '_';
+const CURSOR: char = '^';
+const HINT: &str = "PREV NEXT >/DEL OK";
+const DEFAULT_MAX_LEN: usize = 20;
+const DEFAULT_LONG_PRESS: Duration = Duration::from_millis(600);
+const POLL_RATE: Duration = Duration::from_millis(10);
+
+/// Characters that can be selected with the wheel
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Charset {
+    /// 0-9
+    Digits,
+    /// Space and A-Z
+    Uppercase,
+    /// Space, A-Z and 0-9
+    Alphanumeric,
+    /// All printable ASCII
+    Printable,
+}
+
+impl Charset {
+    pub fn chars(&self) -> Vec<char> {
+        match self {
+            Charset::Digits => ('0'..='9').collect(),
+            Charset::Uppercase => std::iter::once(' ').chain('A'..='Z').collect(),
+            Charset::Alphanumeric => std::iter::once(' ').chain('A'..='Z').chain('0'..='9').collect(),
+            Charset::Printable => (' '..='~').collect(),
+        }
+    }
+}
+
+/// Enter a short string using only the device buttons
+///
+/// * Button 0 - previous character
+/// * Button 1 - next character
+/// * Button 2 - move cursor right (wraps), hold for backspace
+/// * Button 3 - confirm
+pub struct TextEntry {
+    prompt: String,
+    charset: Vec<char>,
+    max_len: usize,
+    long_press: Duration,
+    text: Vec<char>,
+    cursor: usize,
+    cursor_pressed_at: Option<Instant>,
+}
+
+impl TextEntry {
+    pub fn new(prompt: &str, charset: Charset) -> Self {
+        TextEntry {
+            prompt: prompt.to_owned(),
+            charset: charset.chars(),
+            max_len: DEFAULT_MAX_LEN,
+            long_press: DEFAULT_LONG_PRESS,
+            text: vec![],
+            cursor: 0,
+            cursor_pressed_at: None,
+        }
+    }
+}
+
+impl TextEntry {
+    /// Max number of characters that can be entered, between 1 and 84
+    pub fn with_max_len(mut self, max_len: usize) -> Self {
+        self.max_len = max_len.clamp(1, TEXT_MAX_LEN);
+        self.text.truncate(self.max_len);
+        self.cursor = self.cursor.min(self.cursor_limit());
+        self
+    }
+
+    /// Text to start with, characters not in the charset are dropped
+    pub fn with_initial(mut self, text: &str) -> Self {
+        self.text = text.chars()
+            .filter(|chr| self.charset.contains(chr))
+            .take(self.max_len)
+            .collect();
+        self.cursor = self.cursor_limit();
+        self
+    }
+
+    /// How long the cursor button must be held to backspace
+    pub fn with_long_press(mut self, long_press: Duration) -> Self {
+        self.long_press = long_press;
+        self
+    }
+
+    pub fn text(&self) -> String {
+        self.text.iter().collect()
+    }
+
+    /// Update state with button event
+    /// Returns true if the user has confirmed the text
+    pub fn handle(&mut self, event: &ButtonEvent) -> bool {
+        match (event.button, event.pressed) {
+            (BUTTON_PREV, true) => self.cycle(-1),
+            (BUTTON_NEXT, true) => self.cycle(1),
+            (BUTTON_CURSOR, true) => self.cursor_pressed_at = Some(event.time),
+            (BUTTON_CURSOR, false) => {
+                if let Some(pressed_at) = self.cursor_pressed_at.take() {
+                    if event.time.duration_since(pressed_at) >= self.long_press {
+                        self.backspace();
+                    } else {
+                        self.move_cursor();
+                    }
+                }
+            }
+            (BUTTON_CONFIRM, true) => return true,
+            _ => {}
+        }
+        false
+    }
+
+    /// Text to display on the device, see [crate::layout::compose]
+    pub fn render(&self) -> String {
+        let start = self.cursor.saturating_sub(COLUMNS - 1);
+        let mut line = self.text.iter()
+            .skip(start)
+            .take(COLUMNS)
+            .collect::<String>();
+        if self.cursor == self.text.len() {
+            line.push(EMPTY_SLOT);
+        }
+        let caret = format!("{}{}", " ".repeat(self.cursor - start), CURSOR);
+
+        compose(&[
+            self.prompt.clone(),
+            line,
+            caret,
+            fit(HINT, COLUMNS, Align::Center),
+        ])
+    }
+
+    /// Show the entry on the device and block until the user confirms
+    pub fn run(mut self, manager: &mut DeviceManager) -> CommLibResult<String> {
+        manager.take_events();
+        manager.send(Update::Text(self.render()))?;
+        loop {
+            manager.recv()?;
+            let events = manager.take_events();
+            for event in &events {
+                if self.handle(event) {
+                    return Ok(self.text());
+                }
+            }
+            if !events.is_empty() {
+                manager.send(Update::Text(self.render()))?;
+            }
+            sleep(POLL_RATE);
+        }
+    }
+
+    /// Last position the cursor can be at, one past the text if there is room for more
+    fn cursor_limit(&self) -> usize {
+        self.text.len().min(self.max_len - 1)
+    }
+
+    fn cycle(&mut self, step: isize) {
+        let len = self.charset.len() as isize;
+        if self.cursor == self.text.len() {
+            if self.text.len() >= self.max_len {
+                return;
+            }
+            let chr = if step > 0 { self.charset[0] } else { self.charset[(len - 1) as usize] };
+            self.text.push(chr);
+        } else {
+            let current = self.charset.iter()
+                .position(|chr| chr == &self.text[self.cursor])
+                .unwrap_or(0) as isize;
+            self.text[self.cursor] = self.charset[(current + step).rem_euclid(len) as usize];
+        }
+    }
+
+    fn move_cursor(&mut self) {
+        self.cursor += 1;
+        if self.cursor > self.cursor_limit() {
+            self.cursor = 0;
+        }
+    }
+
+    fn backspace(&mut self) {
+        if self.cursor > 0 {
+            self.cursor -= 1;
+            self.text.remove(self.cursor);
+        } else if !self.text.is_empty() {
+            self.text.remove(0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::{emulated_device, Screen};
+    use crate::layout::split;
+
+    fn tap(entry: &mut TextEntry, button: usize) -> bool {
+        let start = Instant::now();
+        let pressed = entry.handle(&ButtonEvent { button, pressed: true, time: start });
+        let released = entry.handle(&ButtonEvent { button, pressed: false, time: start + Duration::from_millis(10) });
+        pressed || released
+    }
+
+    fn hold(entry: &mut TextEntry, button: usize) {
+        let start = Instant::now();
+        entry.handle(&ButtonEvent { button, pressed: true, time: start });
+        entry.handle(&ButtonEvent { button, pressed: false, time: start + DEFAULT_LONG_PRESS });
+    }
+
+    #[test]
+    fn charset_wraps() {
+        let mut entry = TextEntry::new("PIN", Charset::Digits);
+        tap(&mut entry, BUTTON_PREV);
+        assert_eq!(entry.text(), "9");
+        tap(&mut entry, BUTTON_NEXT);
+        assert_eq!(entry.text(), "0");
+        tap(&mut entry, BUTTON_PREV);
+        tap(&mut entry, BUTTON_PREV);
+        assert_eq!(entry.text(), "8");
+
+        let mut entry = TextEntry::new("Name", Charset::Uppercase);
+        tap(&mut entry, BUTTON_NEXT);
+        assert_eq!(entry.text(), " ");
+        tap(&mut entry, BUTTON_PREV);
+        assert_eq!(entry.text(), "Z");
+    }
+
+    #[test]
+    fn cursor_wraps_and_backspaces() {
+        let mut entry = TextEntry::new("PIN", Charset::Digits).with_max_len(3).with_initial("1a2");
+        assert_eq!(entry.text(), "12");
+        assert_eq!(entry.cursor, 2);
+        tap(&mut entry, BUTTON_NEXT);
+        assert_eq!(entry.text(), "120");
+        tap(&mut entry, BUTTON_CURSOR);
+        assert_eq!(entry.cursor, 0);
+        tap(&mut entry, BUTTON_NEXT);
+        assert_eq!(entry.text(), "220");
+        tap(&mut entry, BUTTON_CURSOR);
+        tap(&mut entry, BUTTON_CURSOR);
+        hold(&mut entry, BUTTON_CURSOR);
+        assert_eq!(entry.text(), "20");
+        assert_eq!(entry.cursor, 1);
+
+        hold(&mut entry, BUTTON_CURSOR);
+        hold(&mut entry, BUTTON_CURSOR);
+        assert_eq!(entry.text(), "");
+        hold(&mut entry, BUTTON_CURSOR);
+        assert_eq!(entry.cursor, 0);
+    }
+
+    #[test]
+    fn max_len_stops_new_characters() {
+        let mut entry = TextEntry::new("PIN", Charset::Digits).with_max_len(2).with_initial("123");
+        assert_eq!(entry.text(), "12");
+        assert_eq!(entry.cursor, 1);
+        tap(&mut entry, BUTTON_NEXT);
+        assert_eq!(entry.text(), "13");
+        assert!(tap(&mut entry, BUTTON_CONFIRM));
+    }
+
+    #[test]
+    fn render_scrolls_to_the_cursor() {
+        let entry = TextEntry::new("Label", Charset::Alphanumeric).with_max_len(30).with_initial("ABCDEFGHIJKLMNOPQRSTUVWXY");
+        let rows = split(&entry.render());
+        assert_eq!(rows[0], fit("Label", COLUMNS, Align::Left));
+        assert_eq!(rows[1], "FGHIJKLMNOPQRSTUVWXY_");
+        assert_eq!(rows[2], "                    ^");
+        assert_eq!(rows[3], fit(HINT, COLUMNS, Align::Center));
+    }
+
+    #[test]
+    fn run_returns_confirmed_text() {
+        let (port, device) = emulated_device();
+        let mut manager = DeviceManager::new(port);
+        for button in [BUTTON_NEXT, BUTTON_NEXT, BUTTON_CURSOR, BUTTON_PREV, BUTTON_CONFIRM] {
+            device.press(button);
+            device.release(button);
+        }
+        let text = TextEntry::new("PIN", Charset::Digits).run(&mut manager).unwrap();
+        assert_eq!(text, "19");
+        // the presses are all read at once, so the confirm comes before the screen is updated
+        assert_eq!(device.screen(), Screen::Text(TextEntry::new("PIN", Charset::Digits).render()));
+    }
+}
@@ -0,0 +1,64 @@
+use std::time::{Duration, Instant};
+use comm_lib::BUTTON_COUNT;
+use comm_lib::manager::ButtonEvent;
+
+/// What a button did, matched against rule gestures
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Activity {
+    Press {
+        button: usize,
+        time: Instant,
+    },
+    Release {
+        button: usize,
+        time: Instant,
+    },
+    /// Button is still down, reported on every poll
+    Held {
+        button: usize,
+        /// When the button was pressed, identifies the hold
+        since: Instant,
+        held: Duration,
+    },
+}
+
+impl Activity {
+    pub fn button(&self) -> usize {
+        match self {
+            Activity::Press { button, .. } | Activity::Release { button, .. } | Activity::Held { button, .. } => *button,
+        }
+    }
+}
+
+/// Tracks button state to report presses, releases and holds
+#[derive(Default)]
+pub struct GestureDetector {
+    /// When each button was pressed, if it's down
+    pressed_at: [Option<Instant>; BUTTON_COUNT],
+}
+
+impl GestureDetector {
+    /// Activity for `events` followed by any buttons still held at `now`
+    pub fn handle(&mut self, events: &[ButtonEvent], now: Instant) -> Vec<Activity> {
+        let mut activity = vec![];
+        for event in events {
+            let pressed_at = &mut self.pressed_at[event.button];
+            if event.pressed {
+                if pressed_at.is_some() {
+                    // missed the release, treat as a new press
+                    activity.push(Activity::Release { button: event.button, time: event.time });
+                }
+                activity.push(Activity::Press { button: event.button, time: event.time });
+                *pressed_at = Some(event.time);
+            } else if pressed_at.take().is_some() {
+                activity.push(Activity::Release { button: event.button, time: event.time });
+            }
+        }
+        for (button, pressed_at) in self.pressed_at.iter().enumerate() {
+            if let Some(since) = *pressed_at {
+                activity.push(Activity::Held { button, since, held: now.saturating_duration_since(since) });
+            }
+        }
+        activity
+    }
+}
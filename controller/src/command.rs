@@ -0,0 +1,110 @@
+use std::collections::HashMap;
+use std::io;
+use std::io::Write;
+use std::process::{Child, Command, Stdio};
+use std::thread;
+
+/// Button number (0-3) for button scripts
+pub const VAR_BUTTON_ID: &str = "BUTTON_ID";
+/// Serial port of the device
+pub const VAR_DEVICE_SERIAL: &str = "DEVICE_SERIAL";
+/// What caused the script to run, see `TRIGGER_*`
+pub const VAR_TRIGGER: &str = "TRIGGER";
+/// Name of the rule that ran the script
+pub const VAR_RULE: &str = "RULE";
+/// Path of the changed file for file change triggers
+pub const VAR_FILE: &str = "FILE";
+/// Message received for socket triggers
+pub const VAR_MESSAGE: &str = "MESSAGE";
+
+pub const TRIGGER_PRESS: &str = "press";
+pub const TRIGGER_RELEASE: &str = "release";
+pub const TRIGGER_LONG_PRESS: &str = "long_press";
+pub const TRIGGER_DOUBLE_PRESS: &str = "double_press";
+pub const TRIGGER_SCHEDULE: &str = "schedule";
+pub const TRIGGER_FILE_CHANGE: &str = "file_change";
+pub const TRIGGER_STARTUP: &str = "startup";
+pub const TRIGGER_SOCKET: &str = "socket";
+pub const TRIGGER_CHORD: &str = "chord";
+
+/// Variables set by the controller, these override any in `env`
+pub type Vars<'a> = [(&'a str, String)];
+
+/// Everything needed to start a configured script
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ScriptCommand {
+    pub script: String,
+    pub args: Vec<String>,
+    pub env: HashMap<String, String>,
+    pub cwd: Option<String>,
+    /// Run `script` as a shell command line, `args` are available as `$1`, `$2`, etc
+    pub shell: bool,
+    /// Written to the script's stdin, stdin is closed if not set
+    pub stdin: Option<String>,
+}
+
+impl ScriptCommand {
+    pub fn new(script: String, args: Vec<String>, env: HashMap<String, String>, cwd: Option<String>, shell: bool, stdin: Option<String>) -> Self {
+        ScriptCommand { script, args, env, cwd, shell, stdin }
+    }
+}
+
+impl ScriptCommand {
+    /// Start script, its stdout and stderr go to the controller's
+    pub fn spawn(&self, vars: &Vars) -> io::Result<Child> {
+        self.start(vars, false)
+    }
+
+    /// Start script with stdout piped
+    pub fn spawn_captured(&self, vars: &Vars) -> io::Result<Child> {
+        self.start(vars, true)
+    }
+
+    fn start(&self, vars: &Vars, capture: bool) -> io::Result<Child> {
+        let mut command = self.command();
+        command.envs(&self.env);
+        for (key, value) in vars {
+            command.env(key, value);
+        }
+        if let Some(cwd) = &self.cwd {
+            command.current_dir(cwd);
+        }
+        if capture {
+            command.stdout(Stdio::piped());
+        }
+        command.stdin(if self.stdin.is_some() { Stdio::piped() } else { Stdio::null() });
+        #[cfg(unix)]
+        {
+            // in its own process group so anything the script starts can be killed with it
+            use std::os::unix::process::CommandExt;
+            command.process_group(0);
+        }
+
+        let mut child = command.spawn()?;
+        if let (Some(input), Some(mut pipe)) = (self.stdin.clone(), child.stdin.take()) {
+            // written on another thread so a script that doesn't read stdin can't block us
+            thread::spawn(move || {
+                let _ = pipe.write_all(input.as_bytes());
+            });
+        }
+        Ok(child)
+    }
+
+    fn command(&self) -> Command {
+        if self.shell {
+            let (shell, flag) = if cfg!(windows) { ("cmd", "/C") } else { ("/bin/sh", "-c") };
+            let mut command = Command::new(shell);
+            command.arg(flag).arg(&self.script);
+            if !cfg!(windows) {
+                // $0 for sh, so args start at $1
+                command.arg("sh");
+            }
+            command.args(&self.args);
+            command
+        } else {
+            let mut command = Command::new(&self.script);
+            command.args(&self.args);
+            command
+        }
+    }
+}
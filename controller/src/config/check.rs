@@ -0,0 +1,257 @@
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::path::{Path, PathBuf};
+use comm_lib::{LED_BLUE, LED_COUNT, LED_GREEN, LED_RED};
+use comm_lib::layout::ROWS;
+use crate::command::ScriptCommand;
+use crate::config::config::{ActionConfig, Config, RotationConfig, TargetConfig, TriggerConfig};
+use crate::config::format::Format;
+use crate::config::include::{load, SourceError};
+use crate::config::rules::{Action, Condition, Rule, Rules, Target, Trigger};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Severity {
+    /// The controller won't start with this config
+    Error,
+    /// The config works but probably doesn't do what was meant
+    Warning,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+/// A problem found by [check_config]
+#[derive(Debug)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    /// Included file the problem is in, `None` if it's in the config file or could be in any of them
+    pub file: Option<PathBuf>,
+    /// Line and column, starting at 1, only known for parse errors
+    pub position: Option<(usize, usize)>,
+}
+
+impl Diagnostic {
+    pub fn new(severity: Severity, message: String, file: Option<PathBuf>, position: Option<(usize, usize)>) -> Self {
+        Diagnostic { severity, message, file, position }
+    }
+}
+
+/// Everything wrong with the config at `path`, errors first.
+/// Parsing stops at the first mistake, otherwise every error and warning is found
+pub fn check_config(path: &str, format: Format) -> Vec<Diagnostic> {
+    let config = match load(Path::new(path), format) {
+        Ok((config, _)) => config,
+        Err(SourceError::Parse(file, err)) => return vec![Diagnostic::new(Severity::Error, err.message, included(path, file), err.position)],
+        Err(SourceError::Read(file, err)) => return vec![Diagnostic::new(Severity::Error, format!("Unable to read file: {}", err), included(path, file), None)],
+        Err(SourceError::Cycle(file)) => return vec![Diagnostic::new(Severity::Error, "Includes itself".to_owned(), included(path, file), None)],
+        Err(SourceError::Var(file, err)) => return vec![Diagnostic::new(Severity::Error, err, included(path, file), None)],
+    };
+    let (errors, mut warnings) = match config.build_rules(Path::new(path)) {
+        Ok(rules) => (vec![], rule_warnings(&rules)),
+        Err(errors) => (errors, vec![])
+    };
+    warnings.splice(0..0, config.unused_sections());
+    errors.into_iter().map(|error| Diagnostic::new(Severity::Error, error, None, None))
+        .chain(warnings.into_iter().map(|warning| Diagnostic::new(Severity::Warning, warning, None, None)))
+        .collect()
+}
+
+/// Exit code for the `check` command, 1 if there are errors, or warnings and `deny_warnings` is set
+pub fn exit_code(diagnostics: &[Diagnostic], deny_warnings: bool) -> i32 {
+    let failed = diagnostics.iter().any(|diagnostic| diagnostic.severity == Severity::Error || deny_warnings);
+    if failed { 1 } else { 0 }
+}
+
+/// `None` if `file` is the config file
+fn included(path: &str, file: PathBuf) -> Option<PathBuf> {
+    if file == Path::new(path) { None } else { Some(file) }
+}
+
+impl Config {
+    /// Sections that are set but don't do anything
+    fn unused_sections(&self) -> Vec<String> {
+        let mut warnings = vec![];
+        let mut rules = self.legacy_rules();
+        rules.extend(self.rules.iter().flatten().cloned());
+        for profile in self.profiles.iter().flat_map(|profiles| &profiles.list) {
+            rules.extend(profile.legacy_rules());
+            rules.extend(profile.rules.iter().flatten().cloned());
+        }
+
+        if self.socket.is_some() && !rules.iter().any(|rule| matches!(rule.trigger, TriggerConfig::Socket(_))) {
+            warnings.push("socket isn't used, no rules have a socket trigger".to_owned());
+        }
+        for region in self.regions.iter().flatten() {
+            let used = rules.iter()
+                .flat_map(|rule| &rule.actions)
+                .any(|action| match action {
+                    ActionConfig::RunScript(script) => matches!(&script.target, Some(TargetConfig::Region(name)) if name == &region.name),
+                    ActionConfig::ShowTemplate(template) => template.region.as_ref() == Some(&region.name),
+                    _ => false
+                });
+            if !used {
+                warnings.push(format!("Region {} isn't used, it has no source and no rules show output or templates in it", region.name));
+            }
+        }
+        if let (Some(_), Some(profiles)) = (&self.pages, &self.profiles) {
+            if !profiles.list.is_empty() && profiles.list.iter().all(|profile| profile.pages.is_some()) {
+                warnings.push("pages isn't used, every profile has its own pages".to_owned());
+            }
+        }
+        if let Some(file) = self.log.as_ref().and_then(|log| log.file.as_ref()) {
+            if file.keep.is_some() && matches!(file.rotation, RotationConfig::Never) {
+                warnings.push("log file keep isn't used, the file isn't rotated".to_owned());
+            }
+        }
+        warnings
+    }
+}
+
+fn rule_warnings(rules: &Rules) -> Vec<String> {
+    let mut warnings = vec![];
+    for rule in &rules.rules {
+        for command in scripts(rule) {
+            if let Some(problem) = world_writable(command) {
+                warnings.push(format!("Script for {} {}", rule.name, problem));
+            }
+        }
+    }
+
+    let scheduled = rules.rules.iter()
+        .filter(|rule| matches!(rule.trigger, Trigger::Schedule(_)))
+        .collect::<Vec<&Rule>>();
+    for (idx, first) in scheduled.iter().enumerate() {
+        for second in &scheduled[idx + 1..] {
+            let other_profile = first.profile.is_some() && second.profile.is_some() && first.profile != second.profile;
+            let other_page = first.page.is_some() && second.page.is_some() && first.page != second.page;
+            if other_profile || other_page {
+                continue;
+            }
+            let (first_leds, first_rows) = outputs(first);
+            let (second_leds, second_rows) = outputs(second);
+            for (led, name) in [(LED_GREEN, "green"), (LED_BLUE, "blue"), (LED_RED, "red")] {
+                if first_leds[led] && second_leds[led] {
+                    warnings.push(format!("{} and {} are both scheduled to update the {} LED", first.name, second.name, name));
+                }
+            }
+            let rows = (0..ROWS).filter(|row| first_rows[*row] && second_rows[*row]).map(|row| row.to_string()).collect::<Vec<String>>();
+            if !rows.is_empty() {
+                warnings.push(format!("{} and {} are both scheduled to update display rows {}", first.name, second.name, rows.join(", ")));
+            }
+        }
+    }
+    warnings
+}
+
+/// Scripts run by the rule's conditions and actions
+fn scripts(rule: &Rule) -> impl Iterator<Item=&ScriptCommand> {
+    let conditions = rule.conditions.iter().filter_map(|condition| match condition {
+        Condition::ScriptSucceeds(command) => Some(command),
+        _ => None
+    });
+    let actions = rule.actions.iter().filter_map(|action| match action {
+        Action::RunScript(command, _) => Some(command.as_ref()),
+        _ => None
+    });
+    conditions.chain(actions)
+}
+
+/// LEDs and display rows the rule's actions change
+fn outputs(rule: &Rule) -> ([bool; LED_COUNT], [bool; ROWS]) {
+    let mut leds = [false; LED_COUNT];
+    let mut rows = [false; ROWS];
+    for action in &rule.actions {
+        match action {
+            Action::SetLed(led, _) | Action::RunScript(_, Target::Led { led, .. }) => leds[*led] = true,
+            Action::RunScript(_, Target::TrafficLight(_)) => leds = [true; LED_COUNT],
+            Action::ShowText(_) | Action::RunScript(_, Target::Display) => rows = [true; ROWS],
+            Action::ShowTemplate(_, layout) | Action::RunScript(_, Target::Lines(layout)) | Action::RunScript(_, Target::Json(layout)) => {
+                for row in layout.rows.clone() {
+                    rows[row] = true;
+                }
+            }
+            _ => {}
+        }
+    }
+    (leds, rows)
+}
+
+/// Anyone could change what the script does
+#[cfg(unix)]
+fn world_writable(command: &ScriptCommand) -> Option<String> {
+    use std::os::unix::fs::PermissionsExt;
+
+    if command.shell {
+        return None;
+    }
+    let writable = |path: &Path| fs::metadata(path).map(|meta| meta.permissions().mode() & 0o002 != 0).unwrap_or_default();
+    let path = fs::canonicalize(&command.script).ok()?;
+    if writable(&path) {
+        return Some("is world writable".to_owned());
+    }
+    let dir = path.parent()?;
+    if writable(dir) {
+        return Some(format!("is in a world writable directory, {}", dir.display()));
+    }
+    None
+}
+
+#[cfg(not(unix))]
+fn world_writable(_command: &ScriptCommand) -> Option<String> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Diagnostics for a config file `name` with `contents`
+    fn check(name: &str, contents: &str) -> Vec<Diagnostic> {
+        let dir = std::env::temp_dir().join(format!("controller-check-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        fs::write(&path, contents).unwrap();
+        let diagnostics = check_config(path.to_str().unwrap(), Format::from_path(&path));
+        fs::remove_dir_all(&dir).unwrap();
+        diagnostics
+    }
+
+    fn messages(diagnostics: &[Diagnostic]) -> Vec<(Severity, &str)> {
+        diagnostics.iter().map(|diagnostic| (diagnostic.severity, diagnostic.message.as_str())).collect()
+    }
+
+    #[test]
+    fn yaml_type_errors_have_positions() {
+        let diagnostics = check("config.yaml", "output: none\nrules:\n  - name: Green\n    trigger: startup\n    actions:\n      - show_text: hi\n      - set_led: {led: green, on: maybe}\n");
+        assert_eq!(diagnostics.len(), 1);
+        assert!(diagnostics[0].message.starts_with("rules[0].actions[1].set_led.on: invalid type"), "{}", diagnostics[0].message);
+        assert_eq!(diagnostics[0].position, Some((7, 35)));
+        assert_eq!(exit_code(&diagnostics, false), 1);
+    }
+
+    #[test]
+    fn warnings_only_fail_when_denied() {
+        let rule = |name: &str| format!(r#"{{"name": "{}", "trigger": {{"schedule": {{"cron": "0 * * * *"}}}}, "actions": [{{"set_led": {{"led": "green", "on": true}}}}]}}"#, name);
+        let diagnostics = check("config.json", &format!(r#"{{"output": "none", "socket": "/tmp/controller.sock", "rules": [{}, {}]}}"#, rule("First"), rule("Second")));
+        assert_eq!(messages(&diagnostics), vec![
+            (Severity::Warning, "socket isn't used, no rules have a socket trigger"),
+            (Severity::Warning, "First and Second are both scheduled to update the green LED"),
+        ]);
+        assert_eq!(exit_code(&diagnostics, false), 0);
+        assert_eq!(exit_code(&diagnostics, true), 1);
+    }
+
+    #[test]
+    fn valid_configs_pass() {
+        let diagnostics = check("config.toml", "output = \"none\"\n\n[[rules]]\nname = \"Hi\"\ntrigger = \"startup\"\nactions = [{show_text = \"hi\"}]\n");
+        assert_eq!(messages(&diagnostics), vec![]);
+        assert_eq!(exit_code(&diagnostics, true), 0);
+    }
+}
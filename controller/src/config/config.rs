@@ -1,51 +1,136 @@
-use std::path::Path;
+use std::collections::HashMap;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
 use is_executable::is_executable;
+use schemars::JsonSchema;
 use serde::Deserialize;
-use crate::config::rules::{AutoScriptRules, ExecuteScriptRules, Frequency, Rules, Unit};
+use std::time::Duration;
+use tracing_appender::rolling::Rotation;
+use tracing_subscriber::EnvFilter;
+use comm_lib::{BUTTON_COUNT, LED_BLUE, LED_COUNT, LED_GREEN, LED_RED};
+use comm_lib::layout::{Align, ROWS};
+use crate::command::ScriptCommand;
+use crate::config::duration::parse_duration;
+use crate::config::rules::{Action, Bounds, Condition, Gesture, Layout, LedState, LogFile, Logging, OutcomeMap, Overflow, Overlap, Page, Pages, Profile, Rule, Rules, Target, Trigger};
+use crate::scheduler::{Job, Schedule};
+use crate::scheduler::cron::CronExpr;
+use crate::scheduler::window::{parse_days, parse_hours, Window};
+use crate::sources::Source;
+use crate::template::{Field, Template};
 
-#[derive(Debug, Deserialize)]
+/// Length of each step of an LED pattern if `step` isn't set
+const DEFAULT_PATTERN_STEP: Duration = Duration::from_millis(250);
+/// How often scrolling rows move if `scroll_step` isn't set
+const DEFAULT_SCROLL_STEP: Duration = Duration::from_millis(300);
+
+#[derive(Debug, Deserialize, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct Config {
+    /// Path or URL of the config's JSON Schema, only used by editors
+    #[serde(rename = "$schema")]
+    #[allow(dead_code)]
+    pub schema: Option<String>,
+    /// Config files this one is merged over, relative to this file, see [crate::config::include]
+    #[allow(dead_code)]
+    pub include: Option<Vec<String>>,
+    /// Values for `${name}` in strings, environment variables are used for names that aren't set
+    #[allow(dead_code)]
+    pub vars: Option<HashMap<String, String>>,
+    /// Log level, `log.filter` is used instead if set
     #[serde(default = "Output::default")]
     pub output: Output,
+    pub log: Option<LogConfig>,
     pub leds: Option<Leds>,
     pub display: Option<Script>,
     pub buttons: Option<Vec<Button>>,
+    pub rules: Option<Vec<RuleConfig>>,
+    /// Parts of the display that are updated separately
+    pub regions: Option<Vec<RegionConfig>>,
+    pub pages: Option<PagesConfig>,
+    pub profiles: Option<ProfilesConfig>,
+    /// Path of the unix socket for `socket` triggers
+    pub socket: Option<String>,
+    /// Max number of rules running scripts at once
+    #[serde(default = "default_max_concurrency")]
+    pub max_concurrency: usize,
+    /// Directory `proc`, `sys` and disk mounts are read from for built-in sources, `/` by default
+    pub system_root: Option<String>,
 }
 
 impl Config {
-    pub fn build_rules(&self) -> Result<Rules, Vec<String>> {
+    /// `path` is the config file, the profile state file is kept next to it by default
+    pub fn build_rules(&self, path: &Path) -> Result<Rules, Vec<String>> {
         let mut errors = vec![];
 
-        if let Some(leds) = &self.leds {
-            validate_led("Green", &leds.green, &mut errors);
-            validate_led("Blue", &leds.blue, &mut errors);
-            validate_led("Red", &leds.red, &mut errors);
-        }
-
         if let Some(buttons) = &self.buttons {
-            if buttons.len() > 4 {
+            if buttons.len() > BUTTON_COUNT {
                 errors.push("Too many button scripts, max of 4 is supported".to_owned());
             }
+        }
+
+        if self.max_concurrency == 0 {
+            errors.push("max_concurrency is 0, min is 1".to_owned());
+        }
+
+        if let Some(log) = &self.log {
+            validate_log(log, &mut errors);
+        }
+
+        if self.system_root.as_ref().is_some_and(|root| root.trim().is_empty()) {
+            errors.push("system_root is empty".to_owned());
+        }
 
-            validate_button("0", &buttons.get(0), &mut errors);
-            validate_button("1", &buttons.get(1), &mut errors);
-            validate_button("2", &buttons.get(2), &mut errors);
-            validate_button("3", &buttons.get(3), &mut errors);
+        let regions = validate_regions(self.regions.iter().flatten(), &mut errors);
+        if let Some(config) = &self.pages {
+            validate_pages("pages", config, &mut errors);
         }
+        let profiles = self.profiles.as_ref().map(|profiles| profiles.list.as_slice()).unwrap_or_default();
+        if let Some(config) = &self.profiles {
+            validate_profiles(config, &mut errors);
+        }
+        let profile_names: Vec<String> = profiles.iter().map(|profile| profile.name.clone()).collect();
 
-        validate_display(&self.display, &mut errors);
+        // rules outside of profiles can use any page
+        let mut all_pages = page_names(self.pages.as_ref());
+        for profile in profiles {
+            all_pages.extend(page_names(profile.pages.as_ref()));
+        }
+        // with the index of the profile they belong to
+        let mut configs = vec![];
+        for rule in with_names(self.legacy_rules(), self.rules.iter().flatten(), "") {
+            validate_rule(&rule.name.clone().unwrap_or_default(), &rule, self.socket.is_some(), &regions, &all_pages, &profile_names, &mut errors);
+            configs.push((rule, None));
+        }
+        for (idx, profile) in profiles.iter().enumerate() {
+            let pages = page_names(profile.pages.as_ref().or(self.pages.as_ref()));
+            for rule in with_names(profile.legacy_rules(), profile.rules.iter().flatten(), &format!("{} ", profile.name)) {
+                validate_rule(&rule.name.clone().unwrap_or_default(), &rule, self.socket.is_some(), &regions, &pages, &profile_names, &mut errors);
+                configs.push((rule, Some(idx)));
+            }
+        }
+        // rules are only made from valid configs, e.g. an unknown region can't be looked up
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        let mut rules = configs.iter().map(|(rule, profile)| {
+            let mut rule = make_rule(rule, &regions);
+            rule.profile = *profile;
+            rule
+        }).collect::<Vec<Rule>>();
+        link_outputs(&mut rules, &mut errors);
 
         if errors.is_empty() {
             Ok(Rules::new(
-                make_script_rules(&self.leds.as_ref().and_then(|leds| leds.green.as_ref())),
-                make_script_rules(&self.leds.as_ref().and_then(|leds| leds.blue.as_ref())),
-                make_script_rules(&self.leds.as_ref().and_then(|leds| leds.red.as_ref())),
-                make_script_rules(&self.display.as_ref()),
-                make_button_rules(&self.buttons.as_ref().and_then(|buttons| buttons.get(0))),
-                make_button_rules(&self.buttons.as_ref().and_then(|buttons| buttons.get(1))),
-                make_button_rules(&self.buttons.as_ref().and_then(|buttons| buttons.get(2))),
-                make_button_rules(&self.buttons.as_ref().and_then(|buttons| buttons.get(3))),
+                rules,
+                self.socket.clone(),
+                self.max_concurrency,
+                make_profiles(&self.profiles, &self.pages),
+                self.profiles.as_ref().map(|profiles| match &profiles.state_file {
+                    Some(file) => PathBuf::from(file),
+                    None => path.with_extension("profile"),
+                }),
+                make_logging(self.output, &self.log),
+                PathBuf::from(self.system_root.as_deref().unwrap_or("/")),
             ))
         } else {
             Err(errors)
@@ -53,57 +138,664 @@ impl Config {
     }
 }
 
-fn make_script_rules(script: &Option<&Script>) -> Option<AutoScriptRules> {
-    script.map(|script| {
-        AutoScriptRules::new(
-            script.script.clone(),
-            script.args.clone().unwrap_or_default(),
-            Frequency::new(script.freq_amount, script.freq_unit.into())
-        )
-    })
+/// Legacy rules followed by `rules`, rules without a name are named by their position and `prefix`
+fn with_names<'a>(legacy: Vec<RuleConfig>, rules: impl Iterator<Item=&'a RuleConfig>, prefix: &str) -> Vec<RuleConfig> {
+    let mut named = legacy;
+    named.extend(rules.enumerate().map(|(idx, rule)| {
+        let mut rule = rule.clone();
+        rule.name.get_or_insert_with(|| format!("{}Rule {}", prefix, idx));
+        rule
+    }));
+    named
+}
+
+fn page_names(pages: Option<&PagesConfig>) -> Vec<String> {
+    pages.map(|pages| pages.list.iter().map(|page| page.name.clone()).collect()).unwrap_or_default()
+}
+
+fn make_rule(rule: &RuleConfig, regions: &HashMap<String, Range<usize>>) -> Rule {
+    Rule::new(
+        rule.name.clone().unwrap_or_default(),
+        make_trigger(&rule.trigger),
+        rule.conditions.iter().flatten().map(make_condition).collect(),
+        rule.actions.iter().map(|action| make_action(action, regions)).collect(),
+        rule.overlap.into(),
+        rule.timeout.as_deref().and_then(|text| parse_duration(text).ok()),
+        rule.page.clone(),
+    )
+}
+
+/// Check the scripts used by templates exist and keep their output
+fn link_outputs(rules: &mut [Rule], errors: &mut Vec<String>) {
+    let mut used = vec![];
+    for rule in rules.iter() {
+        for action in &rule.actions {
+            if let Action::ShowTemplate(template, _) = action {
+                for field in template.fields() {
+                    if let Field::Script { rule: script, .. } = field {
+                        used.push((rule.name.clone(), script.clone()));
+                    }
+                }
+            }
+        }
+    }
+    for (name, script) in used {
+        let mut found = false;
+        for rule in rules.iter_mut().filter(|rule| rule.name == script && rule.actions.iter().any(|action| matches!(action, Action::RunScript(..)))) {
+            rule.keep_output = true;
+            found = true;
+        }
+        if !found {
+            errors.push(format!("{} show_template uses script.{} but there's no rule named {} that runs a script", name, script, script));
+        }
+    }
+}
+
+fn make_logging(output: Output, log: &Option<LogConfig>) -> Logging {
+    let filter = log.as_ref().and_then(|log| log.filter.clone()).unwrap_or_else(|| match output {
+        Output::All => "info",
+        Output::Debug => "debug",
+        Output::None => "off",
+    }.to_owned());
+    match log {
+        Some(log) => Logging::new(
+            filter,
+            log.format == LogFormat::Json,
+            log.stderr,
+            log.file.as_ref().map(|file| LogFile::new(PathBuf::from(&file.path), file.rotation.into(), file.keep)),
+            log.journald,
+        ),
+        None => Logging::new(filter, false, true, None, false)
+    }
+}
+
+/// Profiles without `pages` use the top level pages
+fn make_profiles(profiles: &Option<ProfilesConfig>, pages: &Option<PagesConfig>) -> Vec<Profile> {
+    match profiles {
+        Some(profiles) => profiles.list.iter()
+            .map(|profile| Profile::new(profile.name.clone(), make_pages(profile.pages.as_ref().or(pages.as_ref()))))
+            .collect(),
+        None => vec![Profile::new(String::new(), make_pages(pages.as_ref()))]
+    }
+}
+
+fn make_pages(pages: Option<&PagesConfig>) -> Pages {
+    match pages {
+        Some(pages) => Pages::new(
+            pages.list.iter().map(|page| Page::new(
+                page.name.clone(),
+                page.leds.iter().flatten().map(|(led, state)| ((*led).into(), state.clone().into())).collect(),
+            )).collect(),
+            pages.rotate.as_deref().and_then(|text| parse_duration(text).ok()),
+            pages.next_button,
+            pages.previous_button,
+            pages.indicator,
+        ),
+        None => Pages::new(vec![], None, None, None, false)
+    }
+}
+
+fn make_trigger(trigger: &TriggerConfig) -> Trigger {
+    match trigger {
+        TriggerConfig::Schedule(timing) => Trigger::Schedule(make_job(timing)),
+        TriggerConfig::Button(button) => Trigger::Button {
+            button: button.button,
+            gesture: match button.gesture {
+                ButtonGesture::Press => Gesture::Press,
+                ButtonGesture::Release => Gesture::Release,
+                ButtonGesture::LongPress => Gesture::LongPress(Duration::from_millis(button.hold_ms)),
+                ButtonGesture::DoublePress => Gesture::DoublePress(Duration::from_millis(button.double_ms)),
+            },
+            debounce: Duration::from_millis(button.debounce_ms),
+        },
+        TriggerConfig::FileChange(file) => Trigger::FileChange(PathBuf::from(&file.path)),
+        TriggerConfig::Startup => Trigger::Startup,
+        TriggerConfig::Socket(socket) => Trigger::Socket(socket.message.clone()),
+        TriggerConfig::Chord(chord) => Trigger::Chord(chord.buttons.clone()),
+    }
+}
+
+fn make_job(timing: &Timing) -> Job {
+    Job::new(
+        match &timing.cron {
+            Some(cron) => Schedule::Cron(CronExpr::parse(cron).unwrap()),
+            None => Schedule::Interval(timing.freq_unit.unwrap_or_default().to_duration(timing.freq_amount.unwrap_or(1) as u64))
+        },
+        timing.offset.as_deref().and_then(|text| parse_duration(text).ok()).unwrap_or_default(),
+        timing.jitter.as_deref().and_then(|text| parse_duration(text).ok()).unwrap_or_default(),
+        timing.run_at_startup,
+        make_window(&timing.active_hours, &timing.days),
+    )
+}
+
+fn make_window(active_hours: &Option<String>, days: &Option<Vec<String>>) -> Window {
+    Window::new(
+        active_hours.as_deref().and_then(|text| parse_hours(text).ok()),
+        days.as_deref().and_then(|days| parse_days(days).ok()).unwrap_or([true; 7]),
+    )
+}
+
+fn make_command(script: &ScriptConfig) -> ScriptCommand {
+    ScriptCommand::new(
+        script.script.clone(),
+        script.args.clone().unwrap_or_default(),
+        script.env.clone().unwrap_or_default(),
+        script.cwd.clone(),
+        script.shell,
+        script.stdin.clone(),
+    )
+}
+
+fn make_condition(condition: &ConditionConfig) -> Condition {
+    match condition {
+        ConditionConfig::ScriptSucceeds(script) => Condition::ScriptSucceeds(make_command(script)),
+        ConditionConfig::FileExists(path) => Condition::FileExists(PathBuf::from(path)),
+        ConditionConfig::Time(time) => Condition::Time(make_window(&time.active_hours, &time.days)),
+        ConditionConfig::Led(led) => Condition::Led(led.led.into(), led.on),
+        ConditionConfig::Page(page) => Condition::Page(page.clone()),
+        ConditionConfig::Profile(profile) => Condition::Profile(profile.clone()),
+        // checked by validate_rule
+        ConditionConfig::Source(source) => Condition::Source(Source::parse(&source.source).unwrap_or(Source::Time), Bounds::new(source.above, source.below)),
+    }
+}
+
+fn make_action(action: &ActionConfig, regions: &HashMap<String, Range<usize>>) -> Action {
+    match action {
+        ActionConfig::SetLed(led) => Action::SetLed(led.led.into(), match (&led.state, led.on) {
+            (Some(state), _) => state.clone().into(),
+            (None, Some(true)) => LedState::On,
+            (None, _) => LedState::Off,
+        }),
+        ActionConfig::ShowText(text) => Action::ShowText(text.clone()),
+        ActionConfig::ShowTemplate(template) => Action::ShowTemplate(
+            Template::parse(&template.template).unwrap_or_default(),
+            make_layout(&template.layout, match &template.region {
+                Some(region) => regions.get(region).cloned().unwrap_or(0..0),
+                None => 0..ROWS
+            }),
+        ),
+        ActionConfig::RunScript(script) => Action::RunScript(
+            Box::new(make_command(script)),
+            match &script.target {
+                Some(TargetConfig::Led(led)) => Target::Led {
+                    led: (*led).into(),
+                    states: make_led_states(&script.states),
+                },
+                Some(TargetConfig::TrafficLight) => Target::TrafficLight(Box::new(make_lights(&script.lights))),
+                Some(TargetConfig::Region(region)) if script.format == OutputFormat::Json => Target::Json(make_layout(&script.layout, regions[region].clone())),
+                Some(TargetConfig::Region(region)) => Target::Lines(make_layout(&script.layout, regions[region].clone())),
+                Some(TargetConfig::Display) | None if script.format == OutputFormat::Json => Target::Json(make_layout(&script.layout, 0..ROWS)),
+                Some(TargetConfig::Display) | None if script.format == OutputFormat::Lines => Target::Lines(make_layout(&script.layout, 0..ROWS)),
+                Some(TargetConfig::Display) => Target::Display,
+                None => Target::None
+            }
+        ),
+        ActionConfig::SwitchPage(page) => Action::SwitchPage(page.clone()),
+        ActionConfig::NextPage => Action::NextPage,
+        ActionConfig::PreviousPage => Action::PreviousPage,
+        ActionConfig::SwitchProfile(profile) => Action::SwitchProfile(profile.clone()),
+        ActionConfig::NextProfile => Action::NextProfile,
+    }
+}
+
+fn make_layout(layout: &Option<LayoutConfig>, rows: Range<usize>) -> Layout {
+    let layout = layout.clone().unwrap_or_default();
+    let overflow = match layout.overflow {
+        OverflowConfig::Truncate => Overflow::Truncate,
+        OverflowConfig::Wrap => Overflow::Wrap,
+        OverflowConfig::Scroll => Overflow::Scroll(
+            layout.scroll_step.and_then(|step| parse_duration(&step).ok()).unwrap_or(DEFAULT_SCROLL_STEP)
+        ),
+    };
+    Layout::new(rows, overflow, layout.align.into(), layout.tail)
+}
+
+/// Exit code 0 is on, anything else is off and the LED is left as it is if the script can't be started
+fn make_led_states(states: &Option<HashMap<String, LedStateConfig>>) -> OutcomeMap<LedState> {
+    let mut map = OutcomeMap::new(HashMap::from([(0, LedState::On)]), LedState::Off, LedState::Off, LedState::Unchanged);
+    for (key, state) in states.iter().flatten() {
+        set_outcome(&mut map, key, state.clone().into());
+    }
+    map
+}
+
+/// Exit code 0 is green, 1 blue, 2 red and anything else blinks red
+/// LEDs not listed for an outcome are turned off
+fn make_lights(lights: &Option<HashMap<String, HashMap<LedName, LedStateConfig>>>) -> OutcomeMap<[LedState; LED_COUNT]> {
+    let only = |led: usize, state: LedState| {
+        let mut states: [LedState; LED_COUNT] = Default::default();
+        states[led] = state;
+        states
+    };
+    let mut map = OutcomeMap::new(
+        HashMap::from([(0, only(LED_GREEN, LedState::On)), (1, only(LED_BLUE, LedState::On)), (2, only(LED_RED, LedState::On))]),
+        only(LED_RED, LedState::Blink),
+        only(LED_RED, LedState::Blink),
+        only(LED_RED, LedState::Blink),
+    );
+    for (key, leds) in lights.iter().flatten() {
+        let mut states: [LedState; LED_COUNT] = Default::default();
+        for (led, state) in leds {
+            let led: usize = (*led).into();
+            states[led] = state.clone().into();
+        }
+        set_outcome(&mut map, key, states);
+    }
+    map
+}
+
+fn set_outcome<T>(map: &mut OutcomeMap<T>, key: &str, value: T) {
+    match parse_outcome_key(key) {
+        Ok(OutcomeKey::Code(code)) => {
+            map.codes.insert(code, value);
+        }
+        Ok(OutcomeKey::Other) => map.other = value,
+        Ok(OutcomeKey::Timeout) => map.timeout = value,
+        Ok(OutcomeKey::Error) => map.error = value,
+        Err(_) => {}
+    }
+}
+
+enum OutcomeKey {
+    Code(i32),
+    Other,
+    Timeout,
+    Error,
+}
+
+/// Keys of `states` and `lights`, an exit code, `other`, `timeout` or `error`
+fn parse_outcome_key(key: &str) -> Result<OutcomeKey, String> {
+    match key {
+        "other" => Ok(OutcomeKey::Other),
+        "timeout" => Ok(OutcomeKey::Timeout),
+        "error" => Ok(OutcomeKey::Error),
+        _ => key.parse().map(OutcomeKey::Code)
+            .map_err(|_| format!("'{}' is not an exit code, other, timeout or error", key))
+    }
+}
+
+/// Returns the rows of each region, regions with invalid rows have no rows
+fn validate_regions<'a>(regions: impl Iterator<Item=&'a RegionConfig>, errors: &mut Vec<String>) -> HashMap<String, Range<usize>> {
+    let mut valid: Vec<(&str, Range<usize>)> = vec![];
+    let mut names = HashMap::new();
+    for region in regions {
+        if region.name.trim().is_empty() {
+            errors.push("Region name is empty".to_owned());
+        }
+        if names.contains_key(&region.name) {
+            errors.push(format!("Region {} is used more than once", region.name));
+        }
+        match parse_rows(&region.rows) {
+            Ok(rows) => {
+                for (other, other_rows) in &valid {
+                    if rows.start < other_rows.end && other_rows.start < rows.end {
+                        errors.push(format!("Regions {} and {} overlap", other, region.name));
+                    }
+                }
+                valid.push((&region.name, rows.clone()));
+                names.insert(region.name.clone(), rows);
+            }
+            Err(err) => {
+                errors.push(format!("Region {} rows: {}", region.name, err));
+                names.insert(region.name.clone(), 0..0);
+            }
+        }
+    }
+    names
+}
+
+/// `N` or `N-M` (inclusive) to the range of display rows
+fn parse_rows(rows: &str) -> Result<Range<usize>, String> {
+    let (start, end) = rows.split_once('-').unwrap_or((rows, rows));
+    let parse = |row: &str| row.trim().parse::<usize>().map_err(|_| format!("'{}' is not a row number", row.trim()));
+    let (start, end) = (parse(start)?, parse(end)?);
+    if end >= ROWS {
+        return Err(format!("row {} is out of range, max is {}", end, ROWS - 1));
+    }
+    if start > end {
+        return Err(format!("'{}' starts after it ends", rows));
+    }
+    Ok(start..end + 1)
 }
 
-fn make_button_rules(button: &Option<&Button>) -> Option<ExecuteScriptRules> {
-    button.map(|button| {
-        ExecuteScriptRules::new(
-            button.script.clone(),
-            button.args.clone().unwrap_or_default()
-        )
-    })
+/// `name` is `pages` or the profile the pages are in
+fn validate_pages(name: &str, pages: &PagesConfig, errors: &mut Vec<String>) {
+    if pages.list.is_empty() {
+        errors.push(format!("{} has no pages", name));
+    }
+    for (idx, page) in pages.list.iter().enumerate() {
+        if page.name.trim().is_empty() {
+            errors.push("Page name is empty".to_owned());
+        }
+        if pages.list[..idx].iter().any(|other| other.name == page.name) {
+            errors.push(format!("Page {} is used more than once", page.name));
+        }
+        for state in page.leds.iter().flat_map(|leds| leds.values()) {
+            validate_led_state(&format!("Page {} leds", page.name), state, errors);
+        }
+    }
+    if let Some(rotate) = &pages.rotate {
+        match parse_duration(rotate) {
+            Ok(rotate) if rotate.is_zero() => errors.push(format!("{} rotate is 0", name)),
+            Ok(_) => {}
+            Err(err) => errors.push(format!("{} rotate: {}", name, err))
+        }
+    }
+    for (field, button) in [("next_button", pages.next_button), ("previous_button", pages.previous_button)] {
+        if let Some(button) = button {
+            if button >= BUTTON_COUNT {
+                errors.push(format!("{} {} is {}, max is {}", name, field, button, BUTTON_COUNT - 1));
+            }
+        }
+    }
+    if pages.next_button.is_some() && pages.next_button == pages.previous_button {
+        errors.push(format!("{} next_button and previous_button are the same", name));
+    }
 }
 
-fn validate_display(display: &Option<Script>, errors: &mut Vec<String>) {
-    if let Some(display) = display {
-        if display.freq_amount == 0 {
-            errors.push("Display freq_amount is 0, min is 1".to_owned());
+fn validate_log(log: &LogConfig, errors: &mut Vec<String>) {
+    if let Some(filter) = &log.filter {
+        if let Err(err) = EnvFilter::try_new(filter) {
+            errors.push(format!("log filter '{}' is invalid: {}", filter, err));
         }
-        if display.freq_amount > 59 {
-            errors.push(format!("Display freq_amount is {}, max is 59", display.freq_amount));
+    }
+    if let Some(file) = &log.file {
+        if file.path.trim().is_empty() {
+            errors.push("log file path is empty".to_owned());
+        } else if Path::new(&file.path).is_dir() {
+            errors.push(format!("log file {} is a directory", file.path));
+        }
+        if file.keep == Some(0) {
+            errors.push("log file keep is 0, min is 1".to_owned());
         }
-        validate_script("Display", &display.script, errors);
     }
 }
 
-fn validate_led(name: &str, led: &Option<Script>, errors: &mut Vec<String>) {
-    if let Some(led) = led {
-        if led.freq_amount == 0 {
-            errors.push(format!("{} LED freq_amount is 0, min is 1", name));
+fn validate_profiles(profiles: &ProfilesConfig, errors: &mut Vec<String>) {
+    if profiles.list.is_empty() {
+        errors.push("profiles has no profiles".to_owned());
+    }
+    for (idx, profile) in profiles.list.iter().enumerate() {
+        if profile.name.trim().is_empty() {
+            errors.push("Profile name is empty".to_owned());
         }
-        if led.freq_amount > 59 {
-            errors.push(format!("{} LED freq_amount is {}, max is 59", name, led.freq_amount));
+        if profiles.list[..idx].iter().any(|other| other.name == profile.name) {
+            errors.push(format!("Profile {} is used more than once", profile.name));
+        }
+        if profile.buttons.as_ref().map(|buttons| buttons.len() > BUTTON_COUNT).unwrap_or_default() {
+            errors.push(format!("Profile {} has too many button scripts, max of 4 is supported", profile.name));
+        }
+        if let Some(pages) = &profile.pages {
+            validate_pages(&format!("Profile {} pages", profile.name), pages, errors);
+        }
+    }
+    if let Some(file) = &profiles.state_file {
+        if file.trim().is_empty() {
+            errors.push("profiles state_file is empty".to_owned());
         }
-        validate_script(&format!("{} LED", name), &led.script, errors);
     }
 }
 
-fn validate_button(name: &str, button: &Option<&Button>, errors: &mut Vec<String>) {
-    if let Some(button) = button {
-        validate_script(&format!("Button {}", name), &button.script, errors);
+fn validate_rule(name: &str, rule: &RuleConfig, has_socket: bool, regions: &HashMap<String, Range<usize>>, pages: &[String], profiles: &[String], errors: &mut Vec<String>) {
+    if let Some(page) = &rule.page {
+        if !pages.contains(page) {
+            errors.push(format!("{} page {} does not exist", name, page));
+        }
+    }
+    if let Some(timeout) = &rule.timeout {
+        match parse_duration(timeout) {
+            Ok(timeout) if timeout.is_zero() => errors.push(format!("{} timeout is 0", name)),
+            Ok(_) => {}
+            Err(err) => errors.push(format!("{} timeout: {}", name, err))
+        }
+    }
+    match &rule.trigger {
+        TriggerConfig::Schedule(timing) => validate_timing(name, timing, errors),
+        TriggerConfig::Button(button) => {
+            if button.button >= BUTTON_COUNT {
+                errors.push(format!("{} button is {}, max is {}", name, button.button, BUTTON_COUNT - 1));
+            }
+        }
+        TriggerConfig::FileChange(file) => {
+            if file.path.trim().is_empty() {
+                errors.push(format!("{} file_change path is empty", name));
+            }
+        }
+        TriggerConfig::Startup => {}
+        TriggerConfig::Socket(_) => {
+            if !has_socket {
+                errors.push(format!("{} has a socket trigger but no socket path is set", name));
+            }
+        }
+        TriggerConfig::Chord(chord) => {
+            if chord.buttons.len() < 2 {
+                errors.push(format!("{} chord needs at least 2 buttons", name));
+            }
+            for (idx, button) in chord.buttons.iter().enumerate() {
+                if *button >= BUTTON_COUNT {
+                    errors.push(format!("{} chord button is {}, max is {}", name, button, BUTTON_COUNT - 1));
+                }
+                if chord.buttons[..idx].contains(button) {
+                    errors.push(format!("{} chord has button {} more than once", name, button));
+                }
+            }
+        }
+    }
+    for condition in rule.conditions.iter().flatten() {
+        match condition {
+            ConditionConfig::ScriptSucceeds(script) => {
+                if script.target.is_some() || script.states.is_some() || script.lights.is_some() {
+                    errors.push(format!("{} script_succeeds condition can't have a target, states or lights", name));
+                }
+                validate_script(name, script, errors);
+            }
+            ConditionConfig::FileExists(_) | ConditionConfig::Led(_) => {}
+            ConditionConfig::Page(page) => {
+                if !pages.contains(page) {
+                    errors.push(format!("{} page condition page {} does not exist", name, page));
+                }
+            }
+            ConditionConfig::Profile(profile) => {
+                if !profiles.contains(profile) {
+                    errors.push(format!("{} profile condition profile {} does not exist", name, profile));
+                }
+            }
+            ConditionConfig::Time(time) => validate_window(name, &time.active_hours, &time.days, errors),
+            ConditionConfig::Source(source) => validate_source_condition(name, source, errors),
+        }
+    }
+    if rule.actions.is_empty() {
+        errors.push(format!("{} has no actions", name));
+    }
+    for action in &rule.actions {
+        match action {
+            ActionConfig::RunScript(script) => {
+                if script.states.is_some() && !matches!(script.target, Some(TargetConfig::Led(_))) {
+                    errors.push(format!("{} states only applies to scripts with an LED target", name));
+                }
+                if script.format != OutputFormat::Text && matches!(script.target, Some(TargetConfig::Led(_)) | Some(TargetConfig::TrafficLight)) {
+                    errors.push(format!("{} {} format can only be used with a display target or no target", name, script.format));
+                }
+                if let Some(TargetConfig::Region(region)) = &script.target {
+                    if !regions.contains_key(region) {
+                        errors.push(format!("{} target region {} does not exist", name, region));
+                    }
+                }
+                if let Some(layout) = &script.layout {
+                    if script.format == OutputFormat::Text && !matches!(script.target, Some(TargetConfig::Region(_))) {
+                        errors.push(format!("{} layout only applies to lines or json format or a region target", name));
+                    }
+                    validate_layout(name, layout, errors);
+                }
+                if script.lights.is_some() && !matches!(script.target, Some(TargetConfig::TrafficLight)) {
+                    errors.push(format!("{} lights only applies to scripts with a traffic_light target", name));
+                }
+                for (key, state) in script.states.iter().flatten() {
+                    validate_outcome(name, key, state, errors);
+                }
+                for (key, leds) in script.lights.iter().flatten() {
+                    if let Err(err) = parse_outcome_key(key) {
+                        errors.push(format!("{} lights: {}", name, err));
+                    }
+                    for state in leds.values() {
+                        validate_led_state(name, state, errors);
+                    }
+                }
+                validate_script(name, script, errors)
+            }
+            ActionConfig::SwitchPage(page) => {
+                if page.trim().is_empty() {
+                    errors.push(format!("{} switch_page name is empty", name));
+                } else if !pages.contains(page) {
+                    errors.push(format!("{} switch_page page {} does not exist", name, page));
+                }
+            }
+            ActionConfig::NextPage | ActionConfig::PreviousPage => {
+                if pages.is_empty() {
+                    errors.push(format!("{} changes page but there are no pages", name));
+                }
+            }
+            ActionConfig::SwitchProfile(profile) => {
+                if !profiles.contains(profile) {
+                    errors.push(format!("{} switch_profile profile {} does not exist", name, profile));
+                }
+            }
+            ActionConfig::NextProfile => {
+                if profiles.is_empty() {
+                    errors.push(format!("{} changes profile but there are no profiles", name));
+                }
+            }
+            ActionConfig::SetLed(led) => {
+                match (&led.state, led.on) {
+                    (Some(state), None) => validate_led_state(name, state, errors),
+                    (None, Some(_)) => {}
+                    _ => errors.push(format!("{} set_led needs one of on or state", name)),
+                }
+            }
+            ActionConfig::ShowText(_) => {}
+            ActionConfig::ShowTemplate(template) => {
+                if let Err(err) = Template::parse(&template.template) {
+                    errors.push(format!("{} show_template: {}", name, err));
+                }
+                if let Some(region) = &template.region {
+                    if !regions.contains_key(region) {
+                        errors.push(format!("{} show_template region {} does not exist", name, region));
+                    }
+                }
+                if let Some(layout) = &template.layout {
+                    validate_layout(name, layout, errors);
+                }
+            }
+        }
+    }
+}
+
+fn validate_layout(name: &str, layout: &LayoutConfig, errors: &mut Vec<String>) {
+    if layout.tail == Some(0) {
+        errors.push(format!("{} tail is 0, min is 1", name));
+    }
+    if let Some(step) = &layout.scroll_step {
+        if layout.overflow != OverflowConfig::Scroll {
+            errors.push(format!("{} scroll_step only applies to scroll overflow", name));
+        }
+        match parse_duration(step) {
+            Ok(step) if step.is_zero() => errors.push(format!("{} scroll_step is 0", name)),
+            Ok(_) => {}
+            Err(err) => errors.push(format!("{} scroll_step: {}", name, err))
+        }
+    }
+}
+
+fn validate_outcome(name: &str, key: &str, state: &LedStateConfig, errors: &mut Vec<String>) {
+    if let Err(err) = parse_outcome_key(key) {
+        errors.push(format!("{} states: {}", name, err));
+    }
+    validate_led_state(name, state, errors);
+}
+
+pub(crate) fn validate_led_state(name: &str, state: &LedStateConfig, errors: &mut Vec<String>) {
+    if let LedStateConfig::Pattern(pattern) = state {
+        if pattern.steps.is_empty() || pattern.steps.chars().any(|step| step != '0' && step != '1') {
+            errors.push(format!("{} pattern '{}' must be a list of 0s and 1s", name, pattern.steps));
+        }
+        if let Some(step) = &pattern.step {
+            match parse_duration(step) {
+                Ok(step) if step.is_zero() => errors.push(format!("{} pattern step is 0", name)),
+                Ok(_) => {}
+                Err(err) => errors.push(format!("{} pattern step: {}", name, err))
+            }
+        }
+    }
+}
+
+fn validate_timing(name: &str, timing: &Timing, errors: &mut Vec<String>) {
+    if timing.freq_amount == Some(0) {
+        errors.push(format!("{} freq_amount is 0, min is 1", name));
+    }
+    if let Some(cron) = &timing.cron {
+        if timing.freq_amount.is_some() || timing.freq_unit.is_some() {
+            errors.push(format!("{} has both a cron schedule and freq_amount/freq_unit, only one can be used", name));
+        }
+        match CronExpr::parse(cron) {
+            Ok(expr) => if expr.next_after(chrono::Local::now().naive_local()).is_none() {
+                errors.push(format!("{} schedule '{}' never matches a date", name, cron));
+            },
+            Err(err) => errors.push(format!("{} schedule '{}' is invalid: {}", name, cron, err))
+        }
+    }
+    validate_window(name, &timing.active_hours, &timing.days, errors);
+    if let Some(offset) = &timing.offset {
+        if let Err(err) = parse_duration(offset) {
+            errors.push(format!("{} offset: {}", name, err));
+        }
+    }
+    if let Some(jitter) = &timing.jitter {
+        if let Err(err) = parse_duration(jitter) {
+            errors.push(format!("{} jitter: {}", name, err));
+        }
     }
 }
 
-fn validate_script(name: &str, path: &str, errors: &mut Vec<String>) {
-    if !Path::new(path).exists() {
+fn validate_source_condition(name: &str, condition: &SourceConditionConfig, errors: &mut Vec<String>) {
+    match Source::parse(&condition.source) {
+        Ok(source) if source.is_time() => errors.push(format!("{} source condition can't compare {}, use a time condition", name, source)),
+        Ok(_) => {}
+        Err(err) => errors.push(format!("{} source condition: {}", name, err)),
+    }
+    match (condition.above, condition.below) {
+        (None, None) => errors.push(format!("{} source condition needs above or below", name)),
+        (Some(above), Some(below)) if above >= below => {
+            errors.push(format!("{} source condition can never pass, above ({}) isn't less than below ({})", name, above, below))
+        }
+        _ => {}
+    }
+}
+
+fn validate_window(name: &str, active_hours: &Option<String>, days: &Option<Vec<String>>, errors: &mut Vec<String>) {
+    if let Some(active_hours) = active_hours {
+        if let Err(err) = parse_hours(active_hours) {
+            errors.push(format!("{} active_hours: {}", name, err));
+        }
+    }
+    if let Some(days) = days {
+        if let Err(err) = parse_days(days) {
+            errors.push(format!("{} days: {}", name, err));
+        }
+    }
+}
+
+fn validate_script(name: &str, script: &ScriptConfig, errors: &mut Vec<String>) {
+    let path = &script.script;
+    if let Some(cwd) = &script.cwd {
+        if !Path::new(cwd).is_dir() {
+            errors.push(format!("cwd for {} is not a directory", name));
+        }
+    }
+    if script.shell {
+        if path.trim().is_empty() {
+            errors.push(format!("Script for {} is empty", name));
+        }
+    } else if !Path::new(path).exists() {
         errors.push(format!("Script for {} does not exist", name));
     } else if !Path::new(path).is_file() {
         errors.push(format!("Script for {} is not a file", name));
@@ -112,22 +804,80 @@ fn validate_script(name: &str, path: &str, errors: &mut Vec<String>) {
     }
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Copy, Clone, Debug, Default, Deserialize, JsonSchema)]
 #[serde(rename_all = "lowercase")]
 pub enum Output {
+    /// Info and above
+    #[default]
     All,
     Debug,
     None,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct LogConfig {
+    /// Filter directives, e.g. `info,controller::engine=debug`, used instead of `output`
+    pub filter: Option<String>,
+    #[serde(default)]
+    pub format: LogFormat,
+    /// Log to stderr as well as the file and journald
+    #[serde(default = "yes")]
+    pub stderr: bool,
+    pub file: Option<LogFileConfig>,
+    #[serde(default)]
+    pub journald: bool,
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Text,
+    /// One JSON object per line
+    Json,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct LogFileConfig {
+    pub path: String,
+    #[serde(default)]
+    pub rotation: RotationConfig,
+    /// Max number of old files to keep, all are kept if not set
+    pub keep: Option<usize>,
+}
+
+#[derive(Copy, Clone, Debug, Default, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum RotationConfig {
+    Never,
+    Minutely,
+    Hourly,
+    #[default]
+    Daily,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct Button {
     pub script: String,
     pub args: Option<Vec<String>>,
+    pub env: Option<HashMap<String, String>>,
+    pub cwd: Option<String>,
+    #[serde(default)]
+    pub shell: bool,
+    pub stdin: Option<String>,
+    #[serde(default)]
+    pub trigger: ButtonGesture,
+    #[serde(default = "default_debounce_ms")]
+    pub debounce_ms: u64,
+    #[serde(default)]
+    pub overlap: ButtonOverlap,
+    pub timeout: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct Leds {
     pub green: Option<Script>,
@@ -135,44 +885,501 @@ pub struct Leds {
     pub red: Option<Script>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct Script {
     pub script: String,
     pub args: Option<Vec<String>>,
-    #[serde(default = "one")]
-    pub freq_amount: usize,
-    pub freq_unit: FreqUnit,
+    pub env: Option<HashMap<String, String>>,
+    pub cwd: Option<String>,
+    #[serde(default)]
+    pub shell: bool,
+    pub stdin: Option<String>,
+    pub freq_amount: Option<usize>,
+    pub freq_unit: Option<FreqUnit>,
+    /// Cron expression, used instead of `freq_amount` and `freq_unit`
+    pub schedule: Option<String>,
+    /// `HH:MM-HH:MM`
+    pub active_hours: Option<String>,
+    pub days: Option<Vec<String>>,
+    pub offset: Option<String>,
+    pub jitter: Option<String>,
+    #[serde(default = "yes")]
+    pub run_at_startup: bool,
+    pub timeout: Option<String>,
+    /// LED state by exit code, `other`, `timeout` or `error`, LEDs only
+    pub states: Option<HashMap<String, LedStateConfig>>,
+    #[serde(default)]
+    pub format: OutputFormat,
+    /// How lines are fitted to the display, lines and json formats only
+    pub layout: Option<LayoutConfig>,
+}
+
+/// Entry in `rules`
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct RuleConfig {
+    pub name: Option<String>,
+    pub trigger: TriggerConfig,
+    /// All must pass for the actions to run
+    pub conditions: Option<Vec<ConditionConfig>>,
+    pub actions: Vec<ActionConfig>,
+    /// What to do if the rule triggers while its scripts are still running
+    #[serde(default)]
+    pub overlap: ButtonOverlap,
+    /// Scripts are killed if they run for longer than this
+    pub timeout: Option<String>,
+    /// Display output goes to this page instead of the page showing
+    pub page: Option<String>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct PagesConfig {
+    pub list: Vec<PageConfig>,
+    /// Show the next page after this long on a page
+    pub rotate: Option<String>,
+    pub next_button: Option<usize>,
+    pub previous_button: Option<usize>,
+    /// Show the page number in the bottom right corner
+    #[serde(default = "yes")]
+    pub indicator: bool,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ProfilesConfig {
+    pub list: Vec<ProfileConfig>,
+    /// File the active profile is saved to, defaults to the config file with a `.profile` extension
+    pub state_file: Option<String>,
+}
+
+/// Rules, pages and buttons only used while the profile is active, as well as the top level ones
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ProfileConfig {
+    pub name: String,
+    pub rules: Option<Vec<RuleConfig>>,
+    /// Used instead of the top level pages
+    pub pages: Option<PagesConfig>,
+    pub buttons: Option<Vec<Button>>,
 }
 
-#[derive(Copy, Clone, Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct PageConfig {
+    pub name: String,
+    /// Script that fills the page, same as `display`
+    pub source: Option<Script>,
+    /// LED states used instead of the normal ones while the page is showing
+    pub leds: Option<HashMap<LedName, LedStateConfig>>,
+}
+
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum TriggerConfig {
+    Schedule(Timing),
+    Button(ButtonTriggerConfig),
+    FileChange(FileTriggerConfig),
+    Startup,
+    Socket(SocketTriggerConfig),
+    Chord(ChordTriggerConfig),
+}
+
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Timing {
+    pub freq_amount: Option<usize>,
+    pub freq_unit: Option<FreqUnit>,
+    pub cron: Option<String>,
+    pub active_hours: Option<String>,
+    pub days: Option<Vec<String>>,
+    pub offset: Option<String>,
+    pub jitter: Option<String>,
+    #[serde(default = "yes")]
+    pub run_at_startup: bool,
+}
+
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ButtonTriggerConfig {
+    pub button: usize,
+    #[serde(default)]
+    pub gesture: ButtonGesture,
+    #[serde(default = "default_debounce_ms")]
+    pub debounce_ms: u64,
+    /// How long the button must be held for `long_press`
+    #[serde(default = "default_hold_ms")]
+    pub hold_ms: u64,
+    /// Max time between presses for `double_press`
+    #[serde(default = "default_double_ms")]
+    pub double_ms: u64,
+}
+
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct FileTriggerConfig {
+    pub path: String,
+}
+
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct SocketTriggerConfig {
+    /// Only trigger for this message, any message triggers if not set
+    pub message: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ChordTriggerConfig {
+    /// Triggers when all of these are held down at once
+    pub buttons: Vec<usize>,
+}
+
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ConditionConfig {
+    ScriptSucceeds(Box<ScriptConfig>),
+    FileExists(String),
+    Time(TimeConditionConfig),
+    Led(LedConfig),
+    Page(String),
+    Profile(String),
+    Source(SourceConditionConfig),
+}
+
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct SourceConditionConfig {
+    /// Built-in source, e.g. `cpu`, `disk[/home]` or `net_rx[eth0]`
+    pub source: String,
+    /// Passes if the reading is more than this
+    pub above: Option<f64>,
+    /// Passes if the reading is less than this
+    pub below: Option<f64>,
+}
+
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct TimeConditionConfig {
+    pub active_hours: Option<String>,
+    pub days: Option<Vec<String>>,
+}
+
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ActionConfig {
+    SetLed(SetLedConfig),
+    ShowText(String),
+    ShowTemplate(TemplateConfig),
+    RunScript(Box<ScriptConfig>),
+    SwitchPage(String),
+    NextPage,
+    PreviousPage,
+    SwitchProfile(String),
+    NextProfile,
+}
+
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct TemplateConfig {
+    /// Text with `{field}`s filled in from sources, script output, the page and profile, each line is a row
+    pub template: String,
+    /// Region to show it in, otherwise it fills the display
+    pub region: Option<String>,
+    /// How lines are fitted to the display
+    pub layout: Option<LayoutConfig>,
+}
+
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct LedConfig {
+    pub led: LedName,
+    pub on: bool,
+}
+
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct SetLedConfig {
+    pub led: LedName,
+    pub on: Option<bool>,
+    /// Used instead of `on` to blink or show a pattern
+    pub state: Option<LedStateConfig>,
+}
+
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ScriptConfig {
+    pub script: String,
+    pub args: Option<Vec<String>>,
+    pub env: Option<HashMap<String, String>>,
+    pub cwd: Option<String>,
+    #[serde(default)]
+    pub shell: bool,
+    pub stdin: Option<String>,
+    /// Where the result is shown, if not set the script is run in the background
+    pub target: Option<TargetConfig>,
+    /// LED state by exit code, `other`, `timeout` or `error`, LED targets only
+    pub states: Option<HashMap<String, LedStateConfig>>,
+    /// State of each LED by exit code, `other`, `timeout` or `error`, traffic light targets only
+    pub lights: Option<HashMap<String, HashMap<LedName, LedStateConfig>>>,
+    /// How the script's output is read
+    #[serde(default)]
+    pub format: OutputFormat,
+    /// How lines are fitted to the display, lines and json formats only
+    pub layout: Option<LayoutConfig>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct LayoutConfig {
+    #[serde(default)]
+    pub overflow: OverflowConfig,
+    #[serde(default)]
+    pub align: AlignConfig,
+    /// Only keep this many lines from the end of the output
+    pub tail: Option<usize>,
+    /// How often scrolling rows move
+    pub scroll_step: Option<String>,
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum OverflowConfig {
+    #[default]
+    Truncate,
+    Wrap,
+    Scroll,
+}
+
+#[derive(Copy, Clone, Debug, Default, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum AlignConfig {
+    #[default]
+    Left,
+    Center,
+    Right,
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    /// Shown as is
+    #[default]
+    Text,
+    /// Each line is shown on its own row
+    Lines,
+    /// JSON document that can set the display, LEDs and page, see [crate::output::Document]
+    Json,
+}
+
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum TargetConfig {
+    /// LED is on if the script succeeds
+    Led(LedName),
+    /// Script's exit code picks which LEDs are on
+    TrafficLight,
+    /// Script's output is shown on the display
+    Display,
+    /// Script's output lines are shown in the named region
+    Region(String),
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct RegionConfig {
+    pub name: String,
+    /// `N` or `N-M`, e.g. `1-2` is the middle two rows
+    pub rows: String,
+    /// Script that fills the region, it can also be set by rules with a region target
+    pub source: Option<Script>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum LedName {
+    Green,
+    Blue,
+    Red,
+}
+
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum LedStateConfig {
+    On,
+    Off,
+    Blink,
+    Pattern(PatternConfig),
+    Unchanged,
+}
+
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct PatternConfig {
+    /// `1` for on and `0` for off, e.g. `1010000`
+    pub steps: String,
+    /// How long each step lasts, defaults to 250ms
+    pub step: Option<String>,
+}
+
+#[derive(Copy, Clone, Debug, Default, Deserialize, JsonSchema)]
 #[serde(rename_all = "lowercase")]
 pub enum FreqUnit {
+    Milliseconds,
     Seconds,
+    #[default]
     Minutes,
+    Hours,
+    Days,
+}
+
+#[derive(Copy, Clone, Debug, Default, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ButtonGesture {
+    #[default]
+    Press,
+    Release,
+    LongPress,
+    DoublePress,
+}
+
+#[derive(Copy, Clone, Debug, Default, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ButtonOverlap {
+    #[default]
+    Ignore,
+    Queue,
+    Restart,
+}
+
+fn yes() -> bool {
+    true
+}
+
+pub(super) fn default_debounce_ms() -> u64 {
+    50
+}
+
+fn default_max_concurrency() -> usize {
+    4
 }
 
-impl Default for FreqUnit {
-    fn default() -> Self {
-        FreqUnit::Minutes
+pub(super) fn default_hold_ms() -> u64 {
+    800
+}
+
+pub(super) fn default_double_ms() -> u64 {
+    400
+}
+
+impl FreqUnit {
+    pub fn to_duration(self, amount: u64) -> Duration {
+        match self {
+            FreqUnit::Milliseconds => Duration::from_millis(amount),
+            FreqUnit::Seconds => Duration::from_secs(amount),
+            FreqUnit::Minutes => Duration::from_secs(amount * 60),
+            FreqUnit::Hours => Duration::from_secs(amount * 60 * 60),
+            FreqUnit::Days => Duration::from_secs(amount * 60 * 60 * 24)
+        }
+    }
+}
+
+impl From<LedName> for usize {
+    fn from(led: LedName) -> Self {
+        match led {
+            LedName::Green => LED_GREEN,
+            LedName::Blue => LED_BLUE,
+            LedName::Red => LED_RED
+        }
+    }
+}
+
+impl From<LedStateConfig> for LedState {
+    fn from(state: LedStateConfig) -> Self {
+        match state {
+            LedStateConfig::On => LedState::On,
+            LedStateConfig::Off => LedState::Off,
+            LedStateConfig::Blink => LedState::Blink,
+            LedStateConfig::Pattern(pattern) => LedState::Pattern(
+                pattern.steps.chars().map(|step| step == '1').collect(),
+                pattern.step.and_then(|step| parse_duration(&step).ok()).unwrap_or(DEFAULT_PATTERN_STEP),
+            ),
+            LedStateConfig::Unchanged => LedState::Unchanged
+        }
     }
 }
 
-impl Default for Output {
-    fn default() -> Self {
-        Output::All
+impl From<AlignConfig> for Align {
+    fn from(align: AlignConfig) -> Self {
+        match align {
+            AlignConfig::Left => Align::Left,
+            AlignConfig::Center => Align::Center,
+            AlignConfig::Right => Align::Right
+        }
     }
 }
 
-fn one() -> usize {
-    1
+impl From<RotationConfig> for Rotation {
+    fn from(rotation: RotationConfig) -> Self {
+        match rotation {
+            RotationConfig::Never => Rotation::NEVER,
+            RotationConfig::Minutely => Rotation::MINUTELY,
+            RotationConfig::Hourly => Rotation::HOURLY,
+            RotationConfig::Daily => Rotation::DAILY
+        }
+    }
 }
 
-impl Into<Unit> for FreqUnit {
-    fn into(self) -> Unit {
+impl std::fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            FreqUnit::Seconds => Unit::Seconds,
-            FreqUnit::Minutes => Unit::Minutes
+            OutputFormat::Text => write!(f, "text"),
+            OutputFormat::Lines => write!(f, "lines"),
+            OutputFormat::Json => write!(f, "json")
+        }
+    }
+}
+
+impl From<ButtonOverlap> for Overlap {
+    fn from(overlap: ButtonOverlap) -> Self {
+        match overlap {
+            ButtonOverlap::Ignore => Overlap::Ignore,
+            ButtonOverlap::Queue => Overlap::Queue,
+            ButtonOverlap::Restart => Overlap::Restart
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build(json: &str) -> Result<Rules, Vec<String>> {
+        serde_json::from_str::<Config>(json).unwrap().build_rules(Path::new("config.json"))
+    }
+
+    #[test]
+    fn invalid_cron_is_an_error() {
+        let errors = build(r#"{"output": "none", "rules": [{"name": "Cron", "trigger": {"schedule": {"cron": "not a cron"}}, "actions": [{"show_text": "hi"}]}]}"#).err().unwrap();
+        assert_eq!(errors.len(), 1, "{:?}", errors);
+        assert!(errors[0].starts_with("Cron"), "{:?}", errors);
+    }
+
+    #[test]
+    fn unknown_pages_are_errors() {
+        let rule = r#"{"name": "Page", "trigger": "startup", "conditions": [{"page": "other"}], "actions": [{"switch_page": "other"}]}"#;
+        for pages in ["", r#""pages": {"list": [{"name": "main"}]}, "#] {
+            let errors = build(&format!(r#"{{"output": "none", {}"rules": [{}]}}"#, pages, rule)).err().unwrap();
+            assert_eq!(errors, vec!["Page page condition page other does not exist", "Page switch_page page other does not exist"]);
+        }
+        let rule = rule.replace("other", "main");
+        assert!(build(&format!(r#"{{"output": "none", "pages": {{"list": [{{"name": "main"}}]}}, "rules": [{}]}}"#, rule)).is_ok());
+    }
+
+    #[test]
+    fn unknown_region_is_an_error() {
+        let errors = build(r#"{"output": "none", "rules": [{"name": "Region", "trigger": "startup", "actions": [{"run_script": {"script": "echo", "shell": true, "target": {"region": "missing"}}}]}]}"#).err().unwrap();
+        assert_eq!(errors, vec!["Region target region missing does not exist".to_owned()]);
+    }
+}
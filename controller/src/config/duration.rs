@@ -0,0 +1,22 @@
+use std::time::Duration;
+
+/// Parse durations such as `500ms`, `30s`, `5m`, `2h` or `1d`
+/// A number without a unit is seconds
+pub fn parse_duration(text: &str) -> Result<Duration, String> {
+    let text = text.trim();
+    let split = text.find(|chr: char| !chr.is_ascii_digit()).unwrap_or(text.len());
+    let (amount, unit) = text.split_at(split);
+    let amount = amount.parse::<u64>()
+        .map_err(|_| format!("'{}' is not a valid duration, expected a number followed by ms, s, m, h or d", text))?;
+    let millis = match unit.trim() {
+        "ms" => 1,
+        "" | "s" => 1000,
+        "m" => 60 * 1000,
+        "h" => 60 * 60 * 1000,
+        "d" => 24 * 60 * 60 * 1000,
+        other => return Err(format!("'{}' is not a valid duration unit, expected ms, s, m, h or d", other))
+    };
+    amount.checked_mul(millis)
+        .map(Duration::from_millis)
+        .ok_or_else(|| format!("'{}' is too long", text))
+}
@@ -0,0 +1,221 @@
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::path::Path;
+use serde::de::{DeserializeOwned, DeserializeSeed, Deserializer, Error, IgnoredAny, MapAccess, SeqAccess, Visitor};
+use serde_json::Value;
+use serde_path_to_error::Segment;
+use crate::config::config::Config;
+use crate::config::ConfigError;
+
+/// File formats the config can be written in, they all use the same fields
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    Toml,
+    Yaml,
+}
+
+impl Format {
+    /// From the file extension, JSON if it isn't `toml`, `yaml` or `yml`
+    pub fn from_path(path: &Path) -> Format {
+        path.extension()
+            .and_then(|ext| ext.to_str())
+            .and_then(Format::from_name)
+            .unwrap_or(Format::Json)
+    }
+
+    pub fn from_name(name: &str) -> Option<Format> {
+        match name.to_ascii_lowercase().as_str() {
+            "json" => Some(Format::Json),
+            "toml" => Some(Format::Toml),
+            "yaml" | "yml" => Some(Format::Yaml),
+            _ => None
+        }
+    }
+}
+
+/// Why a config couldn't be parsed
+#[derive(Debug)]
+pub struct ParseError {
+    pub message: String,
+    /// Line and column, starting at 1
+    pub position: Option<(usize, usize)>,
+}
+
+impl ParseError {
+    pub fn new(message: String, position: Option<(usize, usize)>) -> Self {
+        ParseError { message, position }
+    }
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.position {
+            Some((line, column)) => write!(f, "{} at line {} column {}", self.message, line, column),
+            None => write!(f, "{}", self.message)
+        }
+    }
+}
+
+impl Format {
+    pub(super) fn parse<T: DeserializeOwned>(&self, contents: &str) -> Result<T, ParseError> {
+        match self {
+            Format::Json => serde_json::from_str(contents).map_err(|err| {
+                let position = (err.line() > 0).then(|| (err.line(), err.column().max(1)));
+                let message = err.to_string();
+                let message = message.split(" at line ").next().unwrap_or_default().to_owned();
+                ParseError::new(message, position)
+            }),
+            Format::Toml => toml::from_str(contents).map_err(|err| {
+                let position = err.span().map(|span| line_column(contents, span.start));
+                ParseError::new(err.message().to_owned(), position)
+            }),
+            // serde_yaml only reads enums written as YAML tags (`!schedule`), going through JSON
+            // means they're written the same way as in the other formats. The position of mistakes
+            // is lost that way, so it's found again from the path to the field
+            Format::Yaml => {
+                let value = serde_yaml::from_str::<Value>(contents).map_err(|err| {
+                    let position = err.location().map(|location| (location.line(), location.column()));
+                    let message = err.to_string();
+                    let message = message.split(" at line ").next().unwrap_or_default().to_owned();
+                    ParseError::new(message, position)
+                })?;
+                serde_path_to_error::deserialize(value).map_err(|err| {
+                    let message = match err.path().to_string().as_str() {
+                        "." => err.inner().to_string(),
+                        path => format!("{}: {}", path, err.inner())
+                    };
+                    let path = err.path().iter().collect::<Vec<&Segment>>();
+                    let position = Locate(&path).deserialize(serde_yaml::Deserializer::from_str(contents)).err()
+                        .and_then(|err| err.location())
+                        .map(|location| (location.line(), location.column()));
+                    ParseError::new(message, position)
+                })
+            }
+        }
+    }
+
+    fn write(&self, value: &Value) -> Result<String, String> {
+        match self {
+            Format::Json => serde_json::to_string_pretty(value).map_err(|err| err.to_string()),
+            // TOML doesn't have null, it's the same as leaving the field out
+            Format::Toml => toml::to_string_pretty(&without_nulls(value.clone())).map_err(|err| err.to_string()),
+            Format::Yaml => serde_yaml::to_string(value).map_err(|err| err.to_string()),
+        }
+    }
+}
+
+/// Rewrite the config at `input` in another format, the config must be in the expected format but isn't validated.
+/// Comments aren't kept
+pub fn convert_config(input: &str, from: Format, output: &str, to: Format) -> Result<(), ConfigError> {
+    let contents = fs::read_to_string(input).map_err(|err| ConfigError::Read(format!("Unable to read config file: {}", err)))?;
+    from.parse::<Config>(&contents).map_err(|err| ConfigError::Read(format!("Unable to parse config file: {}", err)))?;
+    let value = from.parse::<Value>(&contents).map_err(|err| ConfigError::Read(format!("Unable to parse config file: {}", err)))?;
+    let converted = to.write(&value).map_err(|err| ConfigError::Read(format!("Unable to convert config: {}", err)))?;
+    fs::write(output, converted).map_err(|err| ConfigError::Read(format!("Unable to write {}: {}", output, err)))
+}
+
+/// Finds a YAML node by its path, by failing when it gets to the node as serde_yaml adds the position
+/// of the node to errors. Fails at the closest node if the path isn't there
+struct Locate<'a>(&'a [&'a Segment]);
+
+impl<'de> DeserializeSeed<'de> for Locate<'_> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de> Visitor<'de> for Locate<'_> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "a map or list")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<(), A::Error> {
+        if let Some((Segment::Map { key } | Segment::Enum { variant: key }, rest)) = self.0.split_first().map(|(first, rest)| (*first, rest)) {
+            while let Some(name) = map.next_key::<serde_yaml::Value>()? {
+                if name.as_str() == Some(key) {
+                    return map.next_value_seed(Locate(rest));
+                }
+                map.next_value::<IgnoredAny>()?;
+            }
+        }
+        Err(A::Error::custom("found"))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+        if let Some((Segment::Seq { index }, rest)) = self.0.split_first().map(|(first, rest)| (*first, rest)) {
+            for _ in 0..*index {
+                seq.next_element::<IgnoredAny>()?;
+            }
+            seq.next_element_seed(Locate(rest))?;
+        }
+        Err(A::Error::custom("found"))
+    }
+}
+
+/// Line and column, starting at 1, of the byte `offset`
+fn line_column(contents: &str, offset: usize) -> (usize, usize) {
+    let before = &contents[..offset.min(contents.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before.rsplit('\n').next().unwrap_or_default().chars().count() + 1;
+    (line, column)
+}
+
+fn without_nulls(value: Value) -> Value {
+    match value {
+        Value::Object(map) => Value::Object(map.into_iter()
+            .filter(|(_, value)| !value.is_null())
+            .map(|(key, value)| (key, without_nulls(value)))
+            .collect()),
+        Value::Array(list) => Value::Array(list.into_iter().map(without_nulls).collect()),
+        value => value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converting_keeps_the_config() {
+        let dir = std::env::temp_dir().join(format!("controller-convert-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let original = r#"{
+            "output": "none",
+            "socket": null,
+            "pages": {"list": [{"name": "main"}], "next_button": 3},
+            "rules": [
+                {"name": "Tick", "trigger": {"schedule": {"cron": "0 * * * *"}}, "actions": [{"show_text": "tick"}, "next_page"]},
+                {"trigger": {"button": {"button": 0, "gesture": "long_press"}}, "actions": [{"run_script": {"script": "echo ${HOME}", "shell": true, "args": []}}]}
+            ]
+        }"#;
+        let path = |name: &str| dir.join(name).display().to_string();
+        fs::write(path("config.json"), original).unwrap();
+        convert_config(&path("config.json"), Format::Json, &path("config.toml"), Format::Toml).ok().unwrap();
+        convert_config(&path("config.toml"), Format::Toml, &path("config.yaml"), Format::Yaml).ok().unwrap();
+        convert_config(&path("config.yaml"), Format::Yaml, &path("converted.json"), Format::Json).ok().unwrap();
+
+        let converted = Format::Json.parse::<Value>(&fs::read_to_string(path("converted.json")).unwrap()).unwrap();
+        // TOML has no null, so `socket` is left out
+        assert_eq!(converted, without_nulls(Format::Json.parse::<Value>(original).unwrap()));
+        assert!(Format::Yaml.parse::<Config>(&fs::read_to_string(path("config.yaml")).unwrap()).is_ok());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn invalid_configs_are_not_converted() {
+        let dir = std::env::temp_dir().join(format!("controller-convert-invalid-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let input = dir.join("config.toml");
+        let output = dir.join("config.json");
+        fs::write(&input, "output = \"none\"\nmax_concurrency = \"two\"\n").unwrap();
+        let err = convert_config(input.to_str().unwrap(), Format::Toml, output.to_str().unwrap(), Format::Json).err().unwrap();
+        assert!(err.lines()[0].contains("at line 2 column 19"), "{:?}", err.lines());
+        assert!(!output.exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
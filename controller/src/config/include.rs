@@ -0,0 +1,366 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use serde_json::{Map, Value};
+use crate::config::config::Config;
+use crate::config::format::{Format, ParseError};
+
+/// Why the config files couldn't be read
+#[derive(Debug)]
+pub enum SourceError {
+    Read(PathBuf, io::Error),
+    Parse(PathBuf, ParseError),
+    /// File includes itself, directly or through other files
+    Cycle(PathBuf),
+    /// A `${name}` that isn't a var or environment variable
+    Var(PathBuf, String),
+}
+
+impl Display for SourceError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SourceError::Read(file, err) => write!(f, "Unable to read {}: {}", file.display(), err),
+            SourceError::Parse(file, err) => write!(f, "Unable to parse {}: {}", file.display(), err),
+            SourceError::Cycle(file) => write!(f, "{} includes itself", file.display()),
+            SourceError::Var(file, err) => write!(f, "{}: {}", file.display(), err),
+        }
+    }
+}
+
+/// One config file, with its includes removed
+struct Layer {
+    file: PathBuf,
+    /// Directory the file is in, relative paths in the file are from here
+    dir: Option<PathBuf>,
+    value: Value,
+}
+
+/// Read the config at `path` and everything it includes, also returns all the files that were read.
+///
+/// Included files are read first and the including file is merged over them: objects are merged
+/// field by field, `rules` lists are joined and any other value replaces the included one.
+/// `${name}` in script paths, args and env is replaced with the var or environment variable and
+/// relative paths are resolved against the directory of the file they're in
+pub fn load(path: &Path, format: Format) -> Result<(Config, Vec<PathBuf>), SourceError> {
+    let mut layers = vec![];
+    let mut files = vec![];
+    read_layers(path, format, &mut vec![], &mut layers, &mut files)?;
+
+    let mut vars = HashMap::new();
+    for layer in &layers {
+        for (name, value) in layer.value.get("vars").and_then(Value::as_object).into_iter().flatten() {
+            if let Value::String(value) = value {
+                // vars can use environment variables but not other vars
+                let value = interpolate(value, &HashMap::new())
+                    .map_err(|err| SourceError::Var(layer.file.clone(), format!("vars.{}: {}", name, err)))?;
+                vars.insert(name.clone(), value);
+            }
+        }
+    }
+
+    let mut merged = Value::Object(Map::new());
+    for Layer { file, dir, mut value } in layers {
+        if let Value::Object(map) = &mut value {
+            map.remove("vars");
+        }
+        interpolate_scripts(&mut value, &vars, "").map_err(|err| SourceError::Var(file.clone(), err))?;
+        if let Some(dir) = dir {
+            resolve_paths(&mut value, &dir, true);
+        }
+        merge(&mut merged, value, None);
+    }
+    // files can be partial, i.e. only override one field, so mistakes in them are only found once they're merged
+    let config = serde_path_to_error::deserialize(merged).map_err(|err| {
+        SourceError::Parse(path.to_owned(), ParseError::new(format!("{}: {} after merging included files", err.path(), err.inner()), None))
+    })?;
+    Ok((config, files))
+}
+
+/// Add the layers of `path`, the files it includes first
+fn read_layers(path: &Path, format: Format, including: &mut Vec<PathBuf>, layers: &mut Vec<Layer>, files: &mut Vec<PathBuf>) -> Result<(), SourceError> {
+    let contents = fs::read_to_string(path).map_err(|err| SourceError::Read(path.to_owned(), err))?;
+    let canonical = fs::canonicalize(path).map_err(|err| SourceError::Read(path.to_owned(), err))?;
+    if including.contains(&canonical) {
+        return Err(SourceError::Cycle(path.to_owned()));
+    }
+    files.push(path.to_owned());
+    let mut value = format.parse::<Value>(&contents).map_err(|err| SourceError::Parse(path.to_owned(), err))?;
+
+    let includes = match &mut value {
+        Value::Object(map) => map.remove("include"),
+        _ => None
+    };
+    if including.is_empty() && includes.is_none() {
+        // the whole config is in this file, parsed into the config first so mistakes are reported with their position
+        format.parse::<Config>(&contents).map_err(|err| SourceError::Parse(path.to_owned(), err))?;
+    }
+    let dir = canonical.parent().map(Path::to_path_buf);
+    including.push(canonical);
+    for include in includes.as_ref().and_then(Value::as_array).into_iter().flatten().filter_map(Value::as_str) {
+        let include = interpolate(include, &HashMap::new()).map_err(|err| SourceError::Var(path.to_owned(), format!("include: {}", err)))?;
+        let include = match &dir {
+            Some(dir) => dir.join(include),
+            None => PathBuf::from(include)
+        };
+        read_layers(&include, Format::from_path(&include), including, layers, files)?;
+    }
+    including.pop();
+    layers.push(Layer { file: path.to_owned(), dir, value });
+    Ok(())
+}
+
+/// `top` over `base`, see [load]
+fn merge(base: &mut Value, top: Value, key: Option<&str>) {
+    match (base, top) {
+        (Value::Object(base), Value::Object(top)) => {
+            for (name, value) in top {
+                match base.get_mut(&name) {
+                    Some(existing) => merge(existing, value, Some(&name)),
+                    None => {
+                        base.insert(name, value);
+                    }
+                }
+            }
+        }
+        (Value::Array(base), Value::Array(top)) if key == Some("rules") => base.extend(top),
+        (base, top) => *base = top
+    }
+}
+
+/// Interpolate the `script`, `args` and `env` of every script in `value`, `path` is used in errors.
+/// Shell command lines are left alone as the shell has its own `${VAR}`
+fn interpolate_scripts(value: &mut Value, vars: &HashMap<String, String>, path: &str) -> Result<(), String> {
+    match value {
+        Value::Array(list) => {
+            for (idx, value) in list.iter_mut().enumerate() {
+                interpolate_scripts(value, vars, &format!("{}[{}]", path, idx))?;
+            }
+        }
+        Value::Object(map) => {
+            let is_script = map.get("script").is_some_and(Value::is_string);
+            let shell = map.get("shell").and_then(Value::as_bool).unwrap_or_default();
+            for (name, value) in map.iter_mut() {
+                let path = if path.is_empty() { name.clone() } else { format!("{}.{}", path, name) };
+                match (name.as_str(), value) {
+                    ("script", Value::String(text)) if is_script && !shell => interpolate_value(text, vars, &path)?,
+                    ("args", Value::Array(args)) if is_script => {
+                        for (idx, arg) in args.iter_mut().enumerate() {
+                            if let Value::String(text) = arg {
+                                interpolate_value(text, vars, &format!("{}[{}]", path, idx))?;
+                            }
+                        }
+                    }
+                    ("env", Value::Object(env)) if is_script => {
+                        for (key, value) in env.iter_mut() {
+                            if let Value::String(text) = value {
+                                interpolate_value(text, vars, &format!("{}.{}", path, key))?;
+                            }
+                        }
+                    }
+                    (_, value) => interpolate_scripts(value, vars, &path)?
+                }
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn interpolate_value(text: &mut String, vars: &HashMap<String, String>, path: &str) -> Result<(), String> {
+    *text = interpolate(text, vars).map_err(|err| format!("{}: {}", path, err))?;
+    Ok(())
+}
+
+/// Replace `${name}` with the var, or the environment variable if there's no var.
+/// `$${` is kept as `${`
+fn interpolate(text: &str, vars: &HashMap<String, String>) -> Result<String, String> {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("${") {
+        if rest[..start].ends_with('$') {
+            result.push_str(&rest[..start - 1]);
+            result.push_str("${");
+            rest = &rest[start + 2..];
+            continue;
+        }
+        result.push_str(&rest[..start]);
+        let end = rest[start..].find('}').ok_or_else(|| format!("${{ isn't closed in '{}', use $${{ for a literal ${{", text))?;
+        let name = &rest[start + 2..start + end];
+        match vars.get(name).cloned().or_else(|| std::env::var(name).ok()) {
+            Some(value) => result.push_str(&value),
+            None => return Err(format!("${{{}}} isn't a var or environment variable, use $${{{}}} for a literal ${{{}}}", name, name, name))
+        }
+        rest = &rest[start + end + 1..];
+    }
+    result.push_str(rest);
+    Ok(result)
+}
+
+/// Make relative paths in `value` absolute using `dir`, except for shell commands. `top` is set for the
+/// whole config, where `socket` and `system_root` are
+fn resolve_paths(value: &mut Value, dir: &Path, top: bool) {
+    match value {
+        Value::Array(list) => {
+            for value in list {
+                resolve_paths(value, dir, false);
+            }
+        }
+        Value::Object(map) => {
+            let shell = map.get("shell").and_then(Value::as_bool).unwrap_or_default();
+            for (name, value) in map.iter_mut() {
+                match (name.as_str(), value) {
+                    ("script", Value::String(path)) if !shell => resolve_path(path, dir),
+                    ("cwd" | "path" | "file_exists" | "state_file", Value::String(path)) => resolve_path(path, dir),
+                    ("socket" | "system_root", Value::String(path)) if top => resolve_path(path, dir),
+                    (_, value) => resolve_paths(value, dir, false)
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+fn resolve_path(path: &mut String, dir: &Path) {
+    if !path.is_empty() && Path::new(path).is_relative() {
+        *path = dir.join(path.trim_start_matches("./")).display().to_string();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::config::{ActionConfig, ConditionConfig, TriggerConfig};
+    use super::*;
+
+    /// Directory of config files, removed when dropped
+    struct Files(PathBuf);
+
+    impl Files {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("controller-include-{}-{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+            Files(dir)
+        }
+
+        fn write(&self, name: &str, contents: &str) -> PathBuf {
+            let path = self.0.join(name);
+            fs::write(&path, contents).unwrap();
+            path
+        }
+    }
+
+    impl Drop for Files {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn rule_names(config: &Config) -> Vec<String> {
+        config.rules.iter().flatten().map(|rule| rule.name.clone().unwrap_or_default()).collect()
+    }
+
+    /// Script and args of the first action of each rule
+    fn scripts(config: &Config) -> Vec<(String, Vec<String>)> {
+        config.rules.iter().flatten().filter_map(|rule| match rule.actions.first() {
+            Some(ActionConfig::RunScript(script)) => Some((script.script.clone(), script.args.clone().unwrap_or_default())),
+            _ => None
+        }).collect()
+    }
+
+    #[test]
+    fn including_file_is_merged_over_includes() {
+        let files = Files::new("merge");
+        files.write("base.json", r#"{"max_concurrency": 2, "socket": "/tmp/base.sock", "rules": [{"name": "Base", "trigger": "startup", "actions": ["next_page"]}]}"#);
+        let machine = files.write("machine.yaml", "include: [base.json]\nmax_concurrency: 3\nrules:\n  - {name: Machine, trigger: startup, actions: [next_page]}\n");
+
+        let (config, read) = load(&machine, Format::Yaml).unwrap();
+        assert_eq!(config.max_concurrency, 3);
+        assert_eq!(config.socket.as_deref(), Some("/tmp/base.sock"));
+        assert_eq!(rule_names(&config), vec!["Base", "Machine"]);
+        assert_eq!(read, vec![machine, files.0.join("base.json")]);
+    }
+
+    #[test]
+    fn includes_can_override_one_nested_field() {
+        let files = Files::new("partial");
+        files.write("base.json", r#"{"pages": {"list": [{"name": "main"}, {"name": "ci"}], "next_button": 2}}"#);
+        let path = files.write("config.json", r#"{"include": ["base.json"], "pages": {"next_button": 3}}"#);
+
+        let (config, _) = load(&path, Format::Json).unwrap();
+        let pages = config.pages.unwrap();
+        assert_eq!(pages.list.iter().map(|page| page.name.as_str()).collect::<Vec<&str>>(), vec!["main", "ci"]);
+        assert_eq!(pages.next_button, Some(3));
+
+        let path = files.write("config.json", r#"{"include": ["base.json"], "pages": {"next_button": "three"}}"#);
+        assert!(matches!(load(&path, Format::Json), Err(SourceError::Parse(_, err)) if err.to_string().starts_with("pages.next_button: ")));
+    }
+
+    #[test]
+    fn relative_paths_are_from_the_file_they_are_in() {
+        let files = Files::new("paths");
+        fs::create_dir_all(files.0.join("shared")).unwrap();
+        files.write("shared/base.json", r#"{
+            "socket": "controller.sock",
+            "rules": [{"name": "Base", "trigger": {"file_change": {"path": "status"}}, "conditions": [{"file_exists": "./flag"}], "actions": [{"run_script": {"script": "make", "shell": true, "cwd": "build"}}]}]
+        }"#);
+        let path = files.write("config.json", r#"{"include": ["shared/base.json"], "log": {"file": {"path": "/var/log/controller.log"}}}"#);
+
+        let (config, _) = load(&path, Format::Json).unwrap();
+        let shared = fs::canonicalize(files.0.join("shared")).unwrap();
+        let resolved = |name: &str| shared.join(name).display().to_string();
+        assert_eq!(config.socket, Some(resolved("controller.sock")));
+        assert_eq!(config.log.unwrap().file.unwrap().path, "/var/log/controller.log");
+        let rule = &config.rules.unwrap()[0];
+        assert!(matches!(&rule.trigger, TriggerConfig::FileChange(file) if file.path == resolved("status")));
+        assert!(matches!(&rule.conditions.as_deref().unwrap()[0], ConditionConfig::FileExists(file) if *file == resolved("flag")));
+        assert!(matches!(&rule.actions[0], ActionConfig::RunScript(script) if script.script == "make" && script.cwd == Some(resolved("build"))));
+    }
+
+    #[test]
+    fn include_cycles_are_errors() {
+        let files = Files::new("cycle");
+        let first = files.write("first.json", r#"{"include": ["second.json"]}"#);
+        files.write("second.json", r#"{"include": ["first.json"]}"#);
+        assert!(matches!(load(&first, Format::Json), Err(SourceError::Cycle(file)) if file == files.0.join("first.json")));
+    }
+
+    #[test]
+    fn vars_are_used_before_environment_variables() {
+        std::env::set_var("CONTROLLER_INCLUDE_TEST_HOST", "env-host");
+        std::env::set_var("CONTROLLER_INCLUDE_TEST_PORT", "8080");
+        let files = Files::new("vars");
+        let path = files.write("config.json", r#"{
+            "vars": {"CONTROLLER_INCLUDE_TEST_HOST": "var-host"},
+            "rules": [{"name": "Ping", "trigger": "startup", "actions": [{"run_script": {"script": "/bin/ping", "args": ["${CONTROLLER_INCLUDE_TEST_HOST}:${CONTROLLER_INCLUDE_TEST_PORT}"]}}]}]
+        }"#);
+
+        let (config, _) = load(&path, Format::Json).unwrap();
+        assert_eq!(scripts(&config), vec![("/bin/ping".to_owned(), vec!["var-host:8080".to_owned()])]);
+    }
+
+    #[test]
+    fn escapes_and_shell_commands_are_kept() {
+        let files = Files::new("escape");
+        let path = files.write("config.json", r#"{
+            "rules": [
+                {"name": "Escaped", "trigger": "startup", "actions": [{"run_script": {"script": "/bin/echo", "args": ["$${HOME}"]}}]},
+                {"name": "Shell", "trigger": "startup", "actions": [{"run_script": {"script": "echo ${1} ${CONTROLLER_INCLUDE_TEST_UNSET}", "shell": true}}]}
+            ]
+        }"#);
+
+        let (config, _) = load(&path, Format::Json).unwrap();
+        assert_eq!(scripts(&config), vec![
+            ("/bin/echo".to_owned(), vec!["${HOME}".to_owned()]),
+            ("echo ${1} ${CONTROLLER_INCLUDE_TEST_UNSET}".to_owned(), vec![]),
+        ]);
+    }
+
+    #[test]
+    fn unknown_names_are_errors() {
+        let files = Files::new("unknown");
+        let path = files.write("config.json", r#"{"rules": [{"name": "Echo", "trigger": "startup", "actions": [{"run_script": {"script": "/bin/echo", "args": ["${CONTROLLER_INCLUDE_TEST_UNSET}"]}}]}]}"#);
+        assert!(matches!(load(&path, Format::Json), Err(SourceError::Var(..))));
+    }
+}
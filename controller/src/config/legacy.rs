@@ -0,0 +1,143 @@
+use crate::config::config::{ActionConfig, Button, ButtonTriggerConfig, ConditionConfig, Config, default_double_ms, default_hold_ms, LedName, PagesConfig, ProfileConfig, RuleConfig, Script, ScriptConfig, TargetConfig, Timing, TriggerConfig};
+
+impl Config {
+    /// Convert the `leds`, `display`, `regions`, `pages` and `buttons` sections into the equivalent rules
+    pub fn legacy_rules(&self) -> Vec<RuleConfig> {
+        let mut rules = vec![];
+        if let Some(leds) = &self.leds {
+            for (name, led, script) in [("Green LED", LedName::Green, &leds.green), ("Blue LED", LedName::Blue, &leds.blue), ("Red LED", LedName::Red, &leds.red)] {
+                if let Some(script) = script {
+                    rules.push(scheduled_rule(name, script, TargetConfig::Led(led)));
+                }
+            }
+        }
+        if let Some(display) = &self.display {
+            rules.push(scheduled_rule("Display", display, TargetConfig::Display));
+        }
+        for region in self.regions.iter().flatten() {
+            if let Some(source) = &region.source {
+                rules.push(scheduled_rule(&format!("{} region", region.name), source, TargetConfig::Region(region.name.clone())));
+            }
+        }
+        rules.extend(page_rules(&self.pages, ""));
+        rules.extend(self.buttons.iter().flatten().enumerate().map(|(idx, button)| button_rule(idx, button, "")));
+        rules
+    }
+}
+
+impl ProfileConfig {
+    /// Convert the `pages` and `buttons` sections into the equivalent rules, named after the profile and only
+    /// running while it's active
+    pub fn legacy_rules(&self) -> Vec<RuleConfig> {
+        let prefix = format!("{} ", self.name);
+        let mut rules = page_rules(&self.pages, &prefix);
+        rules.extend(self.buttons.iter().flatten().enumerate().map(|(idx, button)| button_rule(idx, button, &prefix)));
+        for rule in &mut rules {
+            rule.conditions = Some(vec![ConditionConfig::Profile(self.name.clone())]);
+        }
+        rules
+    }
+}
+
+/// `prefix` is added to the names of the rules
+fn page_rules(pages: &Option<PagesConfig>, prefix: &str) -> Vec<RuleConfig> {
+    let mut rules = vec![];
+    for page in pages.iter().flat_map(|pages| &pages.list) {
+        if let Some(source) = &page.source {
+            let mut rule = scheduled_rule(&format!("{}{} page", prefix, page.name), source, TargetConfig::Display);
+            rule.page = Some(page.name.clone());
+            rules.push(rule);
+        }
+    }
+    rules
+}
+
+fn scheduled_rule(name: &str, script: &Script, target: TargetConfig) -> RuleConfig {
+    RuleConfig {
+        name: Some(name.to_owned()),
+        trigger: TriggerConfig::Schedule(Timing {
+            freq_amount: script.freq_amount,
+            freq_unit: script.freq_unit,
+            cron: script.schedule.clone(),
+            active_hours: script.active_hours.clone(),
+            days: script.days.clone(),
+            offset: script.offset.clone(),
+            jitter: script.jitter.clone(),
+            run_at_startup: script.run_at_startup,
+        }),
+        conditions: None,
+        actions: vec![ActionConfig::RunScript(Box::new(ScriptConfig {
+            script: script.script.clone(),
+            args: script.args.clone(),
+            env: script.env.clone(),
+            cwd: script.cwd.clone(),
+            shell: script.shell,
+            stdin: script.stdin.clone(),
+            target: Some(target),
+            states: script.states.clone(),
+            lights: None,
+            format: script.format,
+            layout: script.layout.clone(),
+        }))],
+        overlap: Default::default(),
+        timeout: script.timeout.clone(),
+        page: None,
+    }
+}
+
+fn button_rule(idx: usize, button: &Button, prefix: &str) -> RuleConfig {
+    RuleConfig {
+        name: Some(format!("{}Button {}", prefix, idx)),
+        trigger: TriggerConfig::Button(ButtonTriggerConfig {
+            button: idx,
+            gesture: button.trigger,
+            debounce_ms: button.debounce_ms,
+            hold_ms: default_hold_ms(),
+            double_ms: default_double_ms(),
+        }),
+        conditions: None,
+        actions: vec![ActionConfig::RunScript(Box::new(ScriptConfig {
+            script: button.script.clone(),
+            args: button.args.clone(),
+            env: button.env.clone(),
+            cwd: button.cwd.clone(),
+            shell: button.shell,
+            stdin: button.stdin.clone(),
+            target: None,
+            states: None,
+            lights: None,
+            format: Default::default(),
+            layout: None,
+        }))],
+        overlap: button.overlap,
+        timeout: button.timeout.clone(),
+        page: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    fn names(rules: &[RuleConfig]) -> Vec<String> {
+        rules.iter().map(|rule| rule.name.clone().unwrap_or_default()).collect()
+    }
+
+    #[test]
+    fn profile_buttons_are_named_after_their_profile() {
+        let config: Config = serde_json::from_str(r#"{
+            "output": "none",
+            "buttons": [{"script": "echo top", "shell": true}],
+            "profiles": {"list": [{"name": "work", "buttons": [{"script": "echo work", "shell": true}]}, {"name": "focus"}]}
+        }"#).unwrap();
+        assert_eq!(names(&config.legacy_rules()), vec!["Button 0"]);
+        let rules = config.profiles.as_ref().unwrap().list[0].legacy_rules();
+        assert_eq!(names(&rules), vec!["work Button 0"]);
+        assert!(matches!(rules[0].conditions.as_deref(), Some([ConditionConfig::Profile(profile)]) if profile == "work"));
+
+        let rules = config.build_rules(Path::new("config.json")).unwrap();
+        let built = rules.rules.iter().map(|rule| (rule.name.as_str(), rule.profile)).collect::<Vec<(&str, Option<usize>)>>();
+        assert_eq!(built, vec![("Button 0", None), ("work Button 0", Some(0))]);
+    }
+}
@@ -1,30 +1,67 @@
+mod check;
+#[allow(clippy::module_inception)]
 mod config;
+mod duration;
+mod format;
+mod include;
+mod legacy;
+mod schema;
 pub mod rules;
 
-use std::fs;
+pub(crate) use config::{validate_led_state, LedName, LedStateConfig};
+pub use check::{check_config, exit_code, Severity};
+pub use format::{convert_config, Format};
+pub use schema::config_schema;
+
+use std::path::Path;
 use color_eyre::eyre::eyre;
 use color_eyre::Result;
-use crate::config::config::Config;
+use crate::config::include::load;
 use crate::config::rules::Rules;
 
-pub fn load_config(path: &str) -> Result<Rules> {
-    match fs::read_to_string(path) {
-        Ok(contents) => match serde_json::from_str::<Config>(&contents) {
-            Ok(config) => {
-                return match config.build_rules() {
-                    Ok(rules) => Ok(rules),
-                    Err(e) => {
-                        eprintln!("Config errors:");
-                        for line in e {
-                            eprintln!("{}", line);
-                        }
-                        Err(eyre!(""))
-                    }
-                }
-            },
-            Err(err) => eprintln!("Unable to parse config file: {}", err)
-        },
-        Err(err) => eprintln!("Unable to read config file: {}", err)
+/// Prints any errors
+pub fn load_config(path: &str, format: Format) -> Result<Rules> {
+    match read_config(path, format) {
+        Ok(rules) => Ok(rules),
+        Err(ConfigError::Read(err)) => {
+            eprintln!("{}", err);
+            Err(eyre!(""))
+        }
+        Err(ConfigError::Invalid(errors)) => {
+            eprintln!("Config errors:");
+            for line in errors {
+                eprintln!("{}", line);
+            }
+            Err(eyre!(""))
+        }
+    }
+}
+
+pub enum ConfigError {
+    /// File couldn't be read, parsed or written
+    Read(String),
+    /// Config doesn't pass validation
+    Invalid(Vec<String>),
+}
+
+impl ConfigError {
+    pub fn lines(&self) -> Vec<String> {
+        match self {
+            ConfigError::Read(err) => vec![err.clone()],
+            ConfigError::Invalid(errors) => errors.clone()
+        }
     }
-    Err(eyre!(""))
+}
+
+/// Rules from a JSON config that must be valid
+#[cfg(test)]
+pub fn test_rules(json: &str) -> Rules {
+    serde_json::from_str::<config::Config>(json).unwrap().build_rules(Path::new("config.json")).unwrap()
+}
+
+pub fn read_config(path: &str, format: Format) -> Result<Rules, ConfigError> {
+    let (config, files) = load(Path::new(path), format).map_err(|err| ConfigError::Read(err.to_string()))?;
+    let mut rules = config.build_rules(Path::new(path)).map_err(ConfigError::Invalid)?;
+    rules.files = files;
+    Ok(rules)
 }
\ No newline at end of file
@@ -1,117 +1,330 @@
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::collections::HashMap;
+use std::ops::Range;
+use std::path::PathBuf;
+use std::time::Duration;
+use tracing_appender::rolling::Rotation;
+use comm_lib::LED_COUNT;
+use comm_lib::layout::Align;
+use crate::command::ScriptCommand;
+use crate::pool::Outcome;
+use crate::scheduler::Job;
+use crate::scheduler::window::Window;
+use crate::sources::Source;
+use crate::template::Template;
 
-pub struct NextExecution {
-    led_blue: SystemTime,
-    led_red: SystemTime,
-    led_green: SystemTime,
-    display: SystemTime,
+pub struct Rules {
+    pub rules: Vec<Rule>,
+    /// Path of the unix socket for `Trigger::Socket`
+    pub socket: Option<String>,
+    /// Max number of rules running scripts at once
+    pub max_concurrency: usize,
+    /// Always at least one, the first is active unless another was saved in `state_file`
+    pub profiles: Vec<Profile>,
+    /// Where the active profile is saved so it's kept across restarts
+    pub state_file: Option<PathBuf>,
+    pub logging: Logging,
+    /// Directory `proc`, `sys` and disk mounts are read from for built-in sources
+    pub system_root: PathBuf,
+    /// Config file and the files it includes, to reload when they change
+    pub files: Vec<PathBuf>,
 }
 
-impl NextExecution {
-    pub fn new() -> Self {
-        NextExecution { led_blue: UNIX_EPOCH, led_red: UNIX_EPOCH, led_green: UNIX_EPOCH, display: UNIX_EPOCH }
+impl Rules {
+    pub fn new(rules: Vec<Rule>, socket: Option<String>, max_concurrency: usize, profiles: Vec<Profile>, state_file: Option<PathBuf>, logging: Logging, system_root: PathBuf) -> Self {
+        Rules { rules, socket, max_concurrency, profiles, state_file, logging, system_root, files: vec![] }
     }
 }
 
-impl NextExecution {
-    pub fn is_red_led_ready(&self) -> bool {
-        self.led_red < SystemTime::now()
-    }
+/// Where log messages go, see [crate::logging]
+#[derive(Clone)]
+pub struct Logging {
+    /// Filter directives, e.g. `info,controller::engine=debug`
+    pub filter: String,
+    /// Write JSON lines instead of plain text, doesn't apply to journald
+    pub json: bool,
+    pub stderr: bool,
+    pub file: Option<LogFile>,
+    pub journald: bool,
+}
 
-    pub fn reset_red_led(&mut self, seconds: u64) {
-        self.led_red = SystemTime::now();
-        self.led_red.checked_add(Duration::from_secs(seconds)).unwrap();
+impl Logging {
+    pub fn new(filter: String, json: bool, stderr: bool, file: Option<LogFile>, journald: bool) -> Self {
+        Logging { filter, json, stderr, file, journald }
     }
+}
 
-    pub fn is_blue_led_ready(&self) -> bool {
-        self.led_blue < SystemTime::now()
+#[derive(Clone, PartialEq)]
+pub struct LogFile {
+    pub path: PathBuf,
+    /// How often a new file is started, the time is added to the file name unless this is `NEVER`
+    pub rotation: Rotation,
+    /// Max number of old files to keep
+    pub keep: Option<usize>,
+}
+
+impl LogFile {
+    pub fn new(path: PathBuf, rotation: Rotation, keep: Option<usize>) -> Self {
+        LogFile { path, rotation, keep }
     }
+}
+
+/// Set of rules and pages that can be switched to, without `profiles` in the config there is one unnamed profile
+pub struct Profile {
+    pub name: String,
+    pub pages: Pages,
+}
 
-    pub fn reset_blue_led(&mut self, seconds: u64) {
-        self.led_blue = SystemTime::now();
-        self.led_blue.checked_add(Duration::from_secs(seconds)).unwrap();
+impl Profile {
+    pub fn new(name: String, pages: Pages) -> Self {
+        Profile { name, pages }
     }
+}
+
+/// Named screens that can be switched between, the first is shown at startup
+pub struct Pages {
+    pub pages: Vec<Page>,
+    /// Show the next page after this long on a page
+    pub rotate: Option<Duration>,
+    pub next_button: Option<usize>,
+    pub previous_button: Option<usize>,
+    /// Show the page number in the bottom right corner
+    pub indicator: bool,
+}
 
-    pub fn is_green_led_ready(&self) -> bool {
-        self.led_green < SystemTime::now()
+impl Pages {
+    pub fn new(pages: Vec<Page>, rotate: Option<Duration>, next_button: Option<usize>, previous_button: Option<usize>, indicator: bool) -> Self {
+        Pages { pages, rotate, next_button, previous_button, indicator }
     }
+}
+
+pub struct Page {
+    pub name: String,
+    /// LED states used instead of the normal ones while the page is showing
+    pub leds: Vec<(usize, LedState)>,
+}
 
-    pub fn reset_green_led(&mut self, seconds: u64) {
-        self.led_green = SystemTime::now();
-        self.led_green.checked_add(Duration::from_secs(seconds)).unwrap();
+impl Page {
+    pub fn new(name: String, leds: Vec<(usize, LedState)>) -> Self {
+        Page { name, leds }
     }
+}
 
-    pub fn is_display_ready(&self) -> bool {
-        self.display < SystemTime::now()
+pub struct Rule {
+    pub name: String,
+    pub trigger: Trigger,
+    pub conditions: Vec<Condition>,
+    pub actions: Vec<Action>,
+    pub overlap: Overlap,
+    /// Scripts are killed if they run for longer than this
+    pub timeout: Option<Duration>,
+    /// Page display output goes to, otherwise it goes to the page showing
+    pub page: Option<String>,
+    /// Index of the profile the rule belongs to, it only runs while that profile is active.
+    /// `None` runs in every profile
+    pub profile: Option<usize>,
+    /// A template shows the output of the rule's scripts, so it's kept
+    pub keep_output: bool,
+}
+
+impl Rule {
+    pub fn new(name: String, trigger: Trigger, conditions: Vec<Condition>, actions: Vec<Action>, overlap: Overlap, timeout: Option<Duration>, page: Option<String>) -> Self {
+        Rule { name, trigger, conditions, actions, overlap, timeout, page, profile: None, keep_output: false }
     }
+}
 
-    pub fn reset_display(&mut self, seconds: u64) {
-        self.display = SystemTime::now();
-        self.display.checked_add(Duration::from_secs(seconds)).unwrap();
+impl Rule {
+    /// Whether the rules do the same thing, even if they're named differently.
+    /// Used to keep the state of unchanged rules when reloading
+    pub fn same_as(&self, other: &Rule) -> bool {
+        let Rule { name: _, trigger, conditions, actions, overlap, timeout, page, profile, keep_output } = self;
+        *trigger == other.trigger && *conditions == other.conditions && *actions == other.actions && *overlap == other.overlap
+            && *timeout == other.timeout && *page == other.page && *profile == other.profile && *keep_output == other.keep_output
     }
 }
 
-pub struct Rules {
-    pub led_green: Option<AutoScriptRules>,
-    pub led_blue: Option<AutoScriptRules>,
-    pub led_red: Option<AutoScriptRules>,
-    pub display: Option<AutoScriptRules>,
-    pub button0: Option<ExecuteScriptRules>,
-    pub button1: Option<ExecuteScriptRules>,
-    pub button2: Option<ExecuteScriptRules>,
-    pub button3: Option<ExecuteScriptRules>,
+#[derive(PartialEq, Eq)]
+pub enum Trigger {
+    Schedule(Job),
+    Button {
+        button: usize,
+        gesture: Gesture,
+        /// Triggers within this time of the last one are ignored
+        debounce: Duration,
+    },
+    /// File is created, modified or deleted
+    FileChange(PathBuf),
+    /// Once when the controller starts
+    Startup,
+    /// Message received on the socket, `None` matches any message
+    Socket(Option<String>),
+    /// All of the buttons are held down at once
+    Chord(Vec<usize>),
 }
 
-impl Rules {
-    pub fn new(led_green: Option<AutoScriptRules>, led_blue: Option<AutoScriptRules>, led_red: Option<AutoScriptRules>, display: Option<AutoScriptRules>, button0: Option<ExecuteScriptRules>, button1: Option<ExecuteScriptRules>, button2: Option<ExecuteScriptRules>, button3: Option<ExecuteScriptRules>) -> Self {
-        Rules { led_green, led_blue, led_red, display, button0, button1, button2, button3 }
-    }
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Gesture {
+    Press,
+    Release,
+    /// Held for at least this long, triggers while the button is still down
+    LongPress(Duration),
+    /// Pressed twice within this time
+    DoublePress(Duration),
+}
+
+#[derive(PartialEq, Eq)]
+pub enum Condition {
+    ScriptSucceeds(ScriptCommand),
+    FileExists(PathBuf),
+    /// Current time is inside the window
+    Time(Window),
+    /// LED is on (`true`) or off
+    Led(usize, bool),
+    /// Named page is showing
+    Page(String),
+    /// Named profile is active
+    Profile(String),
+    /// Built-in source reading is inside the range
+    Source(Source, Bounds),
 }
 
-pub struct ExecuteScriptRules {
-    pub script: String,
-    pub args: Vec<String>,
+/// Numbers a reading must be above and below, either can be left out
+#[derive(Clone, Debug)]
+pub struct Bounds {
+    pub above: Option<f64>,
+    pub below: Option<f64>,
+}
+
+impl Bounds {
+    pub fn new(above: Option<f64>, below: Option<f64>) -> Self {
+        Bounds { above, below }
+    }
+
+    pub fn contains(&self, value: f64) -> bool {
+        self.above.is_none_or(|above| value > above) && self.below.is_none_or(|below| value < below)
+    }
 }
 
-impl ExecuteScriptRules {
-    pub fn new(script: String, args: Vec<String>) -> Self {
-        ExecuteScriptRules { script, args }
+// compared by bits so rules are only seen as unchanged on reload if the numbers are exactly the same
+impl PartialEq for Bounds {
+    fn eq(&self, other: &Self) -> bool {
+        self.above.map(f64::to_bits) == other.above.map(f64::to_bits) && self.below.map(f64::to_bits) == other.below.map(f64::to_bits)
     }
 }
 
-pub struct AutoScriptRules {
-    pub script: String,
-    pub args: Vec<String>,
-    pub freq: Frequency
+impl Eq for Bounds {}
+
+#[derive(PartialEq, Eq)]
+pub enum Action {
+    SetLed(usize, LedState),
+    ShowText(String),
+    /// Filled in when it's shown, one row per line
+    ShowTemplate(Template, Layout),
+    RunScript(Box<ScriptCommand>, Target),
+    SwitchPage(String),
+    NextPage,
+    PreviousPage,
+    SwitchProfile(String),
+    NextProfile,
+}
+
+/// Where a script's result goes
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Target {
+    /// Run in the background, the result is ignored
+    None,
+    /// LED state is picked from the script's outcome
+    Led {
+        led: usize,
+        states: OutcomeMap<LedState>,
+    },
+    /// State of every LED is picked from the script's outcome
+    TrafficLight(Box<OutcomeMap<[LedState; LED_COUNT]>>),
+    /// Output is shown on the display
+    Display,
+    /// Each line of output is shown on its own row
+    Lines(Layout),
+    /// Output is a JSON document, see [crate::output::Document], its lines are laid out like `Lines`
+    Json(Layout),
+}
+
+/// How lines are fitted to the display rows
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Layout {
+    /// Rows the lines are shown on, other rows are left as they are
+    pub rows: Range<usize>,
+    pub overflow: Overflow,
+    pub align: Align,
+    /// Only keep this many lines from the end, otherwise lines are kept from the start
+    pub tail: Option<usize>,
 }
 
-impl AutoScriptRules {
-    pub fn new(script: String, args: Vec<String>, freq: Frequency) -> Self {
-        AutoScriptRules { script, args, freq }
+impl Layout {
+    pub fn new(rows: Range<usize>, overflow: Overflow, align: Align, tail: Option<usize>) -> Self {
+        Layout { rows, overflow, align, tail }
     }
 }
 
-pub struct Frequency {
-    amount: usize,
-    unit: Unit
+/// What to do with lines that are longer than a row
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Overflow {
+    Truncate,
+    /// Carry on onto the next row
+    Wrap,
+    /// Scroll the row by one character this often
+    Scroll(Duration),
 }
 
-impl Frequency {
-    pub fn new(amount: usize, unit: Unit) -> Self {
-        Frequency { amount, unit }
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum LedState {
+    On,
+    #[default]
+    Off,
+    /// On and off every half second
+    Blink,
+    /// Loops through the steps, on for `true`, each step lasts for the duration
+    Pattern(Vec<bool>, Duration),
+    /// Leave the LED as it is
+    Unchanged,
+}
+
+/// Picks a value based on how a script finished
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OutcomeMap<T> {
+    /// By exit code
+    pub codes: HashMap<i32, T>,
+    /// Exit codes not in `codes`, also used if the script was killed by a signal
+    pub other: T,
+    pub timeout: T,
+    /// Script couldn't be started
+    pub error: T,
+}
+
+impl<T> OutcomeMap<T> {
+    pub fn new(codes: HashMap<i32, T>, other: T, timeout: T, error: T) -> Self {
+        OutcomeMap { codes, other, timeout, error }
     }
 }
 
-impl Frequency {
-    pub fn to_seconds(&self) -> u64 {
-        (match self.unit {
-            Unit::Seconds => self.amount,
-            Unit::Minutes => self.amount * 60
-        }) as u64
+impl<T> OutcomeMap<T> {
+    /// `None` if the script was cancelled
+    pub fn get(&self, outcome: &Outcome) -> Option<&T> {
+        match outcome {
+            Outcome::Exited { code: Some(code), .. } => Some(self.codes.get(code).unwrap_or(&self.other)),
+            Outcome::Exited { code: None, .. } => Some(&self.other),
+            Outcome::TimedOut => Some(&self.timeout),
+            Outcome::Failed(_) => Some(&self.error),
+            Outcome::Cancelled => None,
+        }
     }
 }
 
-pub enum Unit {
-    Seconds,
-    Minutes,
-}
\ No newline at end of file
+/// What to do if a rule is triggered while its scripts are still running
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Overlap {
+    /// Don't run the rule again
+    Ignore,
+    /// Run the rule again once the current run has finished
+    Queue,
+    /// Kill the current run and start a new one
+    Restart,
+}
@@ -0,0 +1,45 @@
+use schemars::schema_for;
+use crate::config::config::Config;
+
+/// JSON Schema of the config, TOML and YAML configs have the same fields
+pub fn config_schema() -> serde_json::Result<String> {
+    serde_json::to_string_pretty(&schema_for!(Config))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+    use super::*;
+
+    const SCHEMA_FILE: &str = include_str!("../../config.schema.json");
+
+    #[test]
+    fn schema_file_is_up_to_date() {
+        assert_eq!(SCHEMA_FILE.trim_end(), config_schema().unwrap(), "config.schema.json is out of date, run `controller schema > config.schema.json`");
+    }
+
+    #[test]
+    fn unknown_fields_are_denied() {
+        fn check(value: &Value, path: &str) {
+            match value {
+                Value::Object(map) => {
+                    if map.contains_key("properties") {
+                        assert_eq!(map.get("additionalProperties"), Some(&Value::Bool(false)), "{} allows unknown fields, add #[serde(deny_unknown_fields)]", path);
+                    }
+                    for (key, value) in map {
+                        check(value, &format!("{}/{}", path, key));
+                    }
+                }
+                Value::Array(list) => {
+                    for (idx, value) in list.iter().enumerate() {
+                        check(value, &format!("{}/{}", path, idx));
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let schema = serde_json::to_value(schema_for!(Config)).unwrap();
+        check(&schema, "#");
+    }
+}
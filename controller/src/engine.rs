@@ -0,0 +1,902 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::ops::Range;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use chrono::Local;
+use tracing::{debug, error, info, trace, warn};
+use comm_lib::{BUTTON_COUNT, LED_COUNT, TEXT_MAX_LEN};
+use comm_lib::layout::{Align, COLUMNS, ROWS, wrap};
+use comm_lib::manager::{ButtonEvent, DeviceManager, Update};
+use crate::buttons::{Activity, GestureDetector};
+use crate::command::{TRIGGER_CHORD, TRIGGER_DOUBLE_PRESS, TRIGGER_FILE_CHANGE, TRIGGER_LONG_PRESS, TRIGGER_PRESS, TRIGGER_RELEASE, TRIGGER_SCHEDULE, TRIGGER_SOCKET, TRIGGER_STARTUP, VAR_BUTTON_ID, VAR_DEVICE_SERIAL, VAR_FILE, VAR_MESSAGE, VAR_RULE, VAR_TRIGGER};
+use crate::config::rules::{Action, Condition, Gesture, Layout, LedState, Overflow, Pages, Profile, Rule, Rules, Target, Trigger};
+use crate::leds::LedDriver;
+use crate::output::Document;
+use crate::pool::{Outcome, Worker, WorkerPool};
+use crate::screen::Screen;
+use crate::scheduler::clock::SystemClock;
+use crate::scheduler::Scheduler;
+use crate::socket::SocketListener;
+use crate::sources::Sources;
+use crate::template::{Field, Template, Value};
+use crate::watch::FileWatcher;
+
+type OwnedVars = Vec<(&'static str, String)>;
+
+/// How long the profile name is shown for after switching
+const PROFILE_BANNER: Duration = Duration::from_secs(2);
+/// How long a config error is shown for when reloading fails
+const RELOAD_BANNER: Duration = Duration::from_secs(10);
+
+/// Results of running a rule, applied by the main loop, display effects go to the rule's page
+enum Effect {
+    Led(usize, LedState),
+    /// Replaces the whole screen
+    Text(String),
+    /// Filled in and laid out as [Effect::Rows] when it's applied
+    Template(Template, Layout),
+    /// Stdout of a script whose output is kept for templates
+    Output(String),
+    /// Lines for the rows in `region`, see [Screen::set_rows]
+    Rows {
+        region: Range<usize>,
+        lines: Vec<String>,
+        align: Align,
+        scroll: Option<Duration>,
+    },
+    Page(String),
+    NextPage,
+    PreviousPage,
+    Profile(String),
+    NextProfile,
+    /// Clear the rows and LEDs after a time unless they are updated again
+    Expire {
+        after: Duration,
+        rows: Range<usize>,
+        leds: Vec<usize>,
+    },
+}
+
+/// Runs rules when their triggers fire
+pub struct Engine {
+    rules: Vec<Arc<Rule>>,
+    device_serial: String,
+    pool: WorkerPool<usize, Effect>,
+    scheduler: Scheduler<usize>,
+    gestures: GestureDetector,
+    files: FileWatcher<usize>,
+    socket: Option<SocketListener>,
+    /// Indexed the same as `rules`
+    states: Vec<RuleState>,
+    leds: LedDriver,
+    profiles: Vec<Profile>,
+    /// Index of the active profile
+    profile: usize,
+    state_file: Option<PathBuf>,
+    /// By profile then page, a profile without pages has a single screen
+    screens: Vec<Vec<Screen>>,
+    /// Index of the page showing in the active profile
+    shown: usize,
+    /// When the page showing was switched to, for rotating pages
+    shown_since: Instant,
+    page: Option<String>,
+    /// Shown instead of the pages until the time, i.e. the profile name after switching profile
+    banner: Option<(Screen, Instant)>,
+    led_expires: [Option<Instant>; LED_COUNT],
+    sources: Sources,
+    /// Last script output of rules with `keep_output`, by rule name
+    outputs: HashMap<String, String>,
+    started: bool,
+}
+
+#[derive(Default)]
+struct RuleState {
+    last_trigger: Option<Instant>,
+    /// Start of the hold that last triggered a long press, so each hold only triggers once
+    long_press: Option<Instant>,
+    /// First press of a possible double press
+    first_press: Option<Instant>,
+    /// Last button press of the chord that last triggered, so each chord only triggers once
+    chord: Option<Instant>,
+}
+
+impl Engine {
+    pub fn new(rules: Rules, device_serial: String) -> Self {
+        let mut scheduler = Scheduler::new(Box::new(SystemClock));
+        let mut files = vec![];
+        for (idx, rule) in rules.rules.iter().enumerate() {
+            match &rule.trigger {
+                Trigger::Schedule(job) => scheduler.add(idx, job.clone()),
+                Trigger::FileChange(path) => files.push((idx, path.clone())),
+                _ => {}
+            }
+        }
+        let socket = rules.socket.as_deref().and_then(bind_socket);
+        let states = rules.rules.iter().map(|_| RuleState::default()).collect();
+        let screens = rules.profiles.iter().map(|profile| page_screens(&profile.pages)).collect();
+        let profile = rules.state_file.as_ref()
+            .and_then(|file| fs::read_to_string(file).ok())
+            .and_then(|saved| rules.profiles.iter().position(|profile| profile.name == saved.trim()))
+            .unwrap_or_default();
+        Engine {
+            pool: WorkerPool::new(rules.max_concurrency),
+            rules: rules.rules.into_iter().map(Arc::new).collect(),
+            device_serial,
+            scheduler,
+            gestures: GestureDetector::default(),
+            files: FileWatcher::new(files),
+            socket,
+            states,
+            leds: LedDriver::new(),
+            profiles: rules.profiles,
+            profile,
+            state_file: rules.state_file,
+            screens,
+            shown: 0,
+            shown_since: Instant::now(),
+            page: None,
+            banner: None,
+            led_expires: [None; LED_COUNT],
+            sources: Sources::new(rules.system_root),
+            outputs: HashMap::new(),
+            started: false,
+        }
+    }
+}
+
+impl Engine {
+    /// Run any rules that have triggered, returns the time until a scheduled rule is next due
+    pub fn tick(&mut self, manager: &mut DeviceManager) -> Option<Duration> {
+        if !self.started {
+            self.started = true;
+            if self.profiles.len() > 1 {
+                self.show_profile_banner(Instant::now());
+            }
+            if !self.pages().pages.is_empty() {
+                self.show_page(0, Instant::now());
+            }
+            for idx in 0..self.rules.len() {
+                match self.rules[idx].trigger {
+                    Trigger::Startup => self.trigger(idx, vec![(VAR_TRIGGER, TRIGGER_STARTUP.to_owned())]),
+                    Trigger::Schedule(_) => self.log_next_run(idx),
+                    _ => {}
+                }
+            }
+        }
+
+        for idx in self.scheduler.due() {
+            self.trigger(idx, vec![(VAR_TRIGGER, TRIGGER_SCHEDULE.to_owned())]);
+            self.log_next_run(idx);
+        }
+
+        for (idx, vars) in self.button_triggers(&manager.take_events(), Instant::now()) {
+            self.trigger(idx, vars);
+        }
+
+        for (idx, path) in self.files.changed() {
+            self.trigger(idx, vec![(VAR_TRIGGER, TRIGGER_FILE_CHANGE.to_owned()), (VAR_FILE, path.display().to_string())]);
+        }
+
+        let messages = self.socket.as_mut().map(|socket| socket.messages()).unwrap_or_default();
+        for message in messages {
+            for idx in 0..self.rules.len() {
+                if let Trigger::Socket(expected) = &self.rules[idx].trigger {
+                    if expected.as_ref().map(|expected| expected == &message).unwrap_or(true) {
+                        self.trigger(idx, vec![(VAR_TRIGGER, TRIGGER_SOCKET.to_owned()), (VAR_MESSAGE, message.clone())]);
+                    }
+                }
+            }
+        }
+
+        let now = Instant::now();
+        for (idx, effect) in self.pool.poll() {
+            let rule = self.rules[idx].clone();
+            // still running when its profile was switched away from
+            if !self.is_active(&rule) {
+                continue;
+            }
+            let effect = match effect {
+                Effect::Template(template, layout) => lay_out(&self.render(&template), &layout),
+                effect => effect
+            };
+            match effect {
+                Effect::Led(led, state) => {
+                    self.leds.set(led, state, now);
+                    self.led_expires[led] = None;
+                }
+                Effect::Text(text) => if let Some(screen) = self.screen(&rule.page) {
+                    screen.set_text(text);
+                },
+                Effect::Rows { region, lines, align, scroll } => if let Some(screen) = self.screen(&rule.page) {
+                    screen.set_rows(region, lines, align, scroll, now);
+                },
+                // laid out above
+                Effect::Template(..) => {}
+                Effect::Output(stdout) => {
+                    self.outputs.insert(rule.name.clone(), stdout);
+                }
+                Effect::Page(page) => self.switch_page(page, now),
+                Effect::NextPage => self.step_page(true, now),
+                Effect::PreviousPage => self.step_page(false, now),
+                Effect::Profile(profile) => match self.profiles.iter().position(|known| known.name == profile) {
+                    Some(idx) => self.show_profile(idx, now),
+                    None => warn!("Unknown profile {}", profile)
+                },
+                Effect::NextProfile => self.show_profile((self.profile + 1) % self.profiles.len(), now),
+                Effect::Expire { after, rows, leds } => {
+                    if let Some(screen) = self.screen(&rule.page) {
+                        screen.expire(rows, now + after);
+                    }
+                    for led in leds {
+                        self.led_expires[led] = Some(now + after);
+                    }
+                }
+            }
+        }
+
+        if let Some(rotate) = self.pages().rotate {
+            if now.saturating_duration_since(self.shown_since) >= rotate {
+                self.step_page(true, now);
+            }
+        }
+        for led in 0..LED_COUNT {
+            if self.led_expires[led].map(|expires| expires <= now).unwrap_or_default() {
+                self.led_expires[led] = None;
+                self.leds.set(led, LedState::Off, now);
+            }
+        }
+
+        if self.banner.as_ref().map(|(_, until)| *until <= now).unwrap_or_default() {
+            self.banner = None;
+            self.screens[self.profile][self.shown].invalidate();
+        }
+        let screen = match &mut self.banner {
+            Some((banner, _)) => banner,
+            None => &mut self.screens[self.profile][self.shown]
+        };
+        for update in screen.update(now) {
+            send(manager, update);
+        }
+        for (led, on) in self.leds.update(now) {
+            send(manager, Update::LED(led, on));
+        }
+
+        self.scheduler.time_until_next()
+    }
+
+    /// Use a changed config, keeping the state of rules, pages and the profile that are still in it.
+    /// Scripts that are running are stopped and their results are ignored
+    pub fn reload(&mut self, rules: Rules) {
+        let now = Instant::now();
+        let mut old_states = std::mem::take(&mut self.states).into_iter().map(Some).collect::<Vec<Option<RuleState>>>();
+        let mut moved = HashMap::new();
+        for (idx, rule) in rules.rules.iter().enumerate() {
+            let old = (0..self.rules.len()).find(|old| old_states[*old].is_some() && self.rules[*old].same_as(rule));
+            let state = match old {
+                Some(old) => {
+                    moved.insert(old, idx);
+                    old_states[old].take()
+                }
+                None => None
+            };
+            self.states.push(state.unwrap_or_default());
+        }
+        let unchanged = moved.values().copied().collect::<HashSet<usize>>();
+
+        for old in (0..self.rules.len()).filter(|old| !moved.contains_key(old)) {
+            if self.scheduler.remove(&old) {
+                debug!("Stopped schedule for {}", self.rules[old].name);
+            }
+        }
+        self.scheduler.rekey(&moved);
+        let mut files = vec![];
+        let mut added = vec![];
+        for (idx, rule) in rules.rules.iter().enumerate() {
+            match &rule.trigger {
+                Trigger::Schedule(job) if !unchanged.contains(&idx) => {
+                    self.scheduler.add(idx, job.clone());
+                    added.push(idx);
+                }
+                Trigger::FileChange(path) => files.push((idx, path.clone())),
+                _ => {}
+            }
+        }
+        self.files = FileWatcher::new(files);
+        if self.socket.as_ref().map(|socket| socket.path()) != rules.socket.as_deref() {
+            self.socket = None;
+            self.socket = rules.socket.as_deref().and_then(bind_socket);
+        }
+        self.pool = WorkerPool::new(rules.max_concurrency);
+        self.rules = rules.rules.into_iter().map(Arc::new).collect();
+        for idx in added {
+            self.log_next_run(idx);
+        }
+
+        let profile = self.profiles[self.profile].name.clone();
+        let page = self.pages().pages.get(self.shown).map(|page| page.name.clone());
+        let mut old_screens = std::mem::take(&mut self.screens);
+        for new in &rules.profiles {
+            let old = self.profiles.iter().position(|old| old.name == new.name);
+            let mut screens = page_screens(&new.pages);
+            if let Some(old) = old {
+                let old_pages = &self.profiles[old].pages.pages;
+                for (idx, screen) in screens.iter_mut().enumerate() {
+                    let found = match new.pages.pages.get(idx) {
+                        Some(page) => old_pages.iter().position(|old_page| old_page.name == page.name),
+                        None if old_pages.is_empty() => Some(0),
+                        None => None
+                    };
+                    if let Some(found) = found {
+                        std::mem::swap(screen, &mut old_screens[old][found]);
+                    }
+                }
+                set_indicators(&new.pages, &mut screens);
+            }
+            self.screens.push(screens);
+        }
+        self.profiles = rules.profiles;
+        self.state_file = rules.state_file;
+        if self.sources.root() != rules.system_root {
+            self.sources = Sources::new(rules.system_root);
+        }
+        self.profile = self.profiles.iter().position(|known| known.name == profile).unwrap_or_default();
+        self.shown = page.and_then(|page| self.pages().pages.iter().position(|known| known.name == page)).unwrap_or_default();
+        self.screens[self.profile][self.shown].invalidate();
+        match self.profiles[self.profile].pages.pages.get(self.shown) {
+            Some(page) => {
+                self.page = Some(page.name.clone());
+                self.leds.set_overrides(&page.leds, now);
+            }
+            None => self.leds.set_overrides(&[], now)
+        }
+        info!("Reloaded config, {} rules, {} unchanged", self.rules.len(), unchanged.len());
+
+        if self.started {
+            for idx in 0..self.rules.len() {
+                if !unchanged.contains(&idx) && matches!(self.rules[idx].trigger, Trigger::Startup) {
+                    self.trigger(idx, vec![(VAR_TRIGGER, TRIGGER_STARTUP.to_owned())]);
+                }
+            }
+        }
+    }
+
+    #[cfg(test)]
+    pub fn rule_names(&self) -> Vec<&str> {
+        self.rules.iter().map(|rule| rule.name.as_str()).collect()
+    }
+
+    /// Show the first error on the device, the previous config stays in use
+    pub fn reload_failed(&mut self, errors: &[String]) {
+        let mut lines = vec!["Config not reloaded".to_owned()];
+        lines.extend(errors.iter().take(1).flat_map(|error| wrap(error, COLUMNS)));
+        lines.truncate(ROWS);
+        self.show_banner(lines, Align::Left, RELOAD_BANNER, Instant::now());
+    }
+
+    fn pages(&self) -> &Pages {
+        &self.profiles[self.profile].pages
+    }
+
+    /// Screen for `page` in the active profile, or the page showing if `page` isn't set.
+    /// `None` if the active profile doesn't have the page
+    fn screen(&mut self, page: &Option<String>) -> Option<&mut Screen> {
+        let idx = match page {
+            Some(page) => self.pages().pages.iter().position(|known| &known.name == page)?,
+            None => self.shown
+        };
+        self.screens[self.profile].get_mut(idx)
+    }
+
+    fn is_active(&self, rule: &Rule) -> bool {
+        rule.profile.map(|profile| profile == self.profile).unwrap_or(true)
+    }
+
+    /// Show the named page, without pages configured the name is only used for page conditions
+    fn switch_page(&mut self, page: String, now: Instant) {
+        if self.pages().pages.is_empty() {
+            self.page = Some(page);
+            return;
+        }
+        match self.pages().pages.iter().position(|known| known.name == page) {
+            Some(idx) => self.show_page(idx, now),
+            None => warn!("Unknown page {}", page)
+        }
+    }
+
+    fn log_next_run(&self, idx: usize) {
+        match self.scheduler.next_run(&idx) {
+            Some(next) => debug!("{} next runs in {}s", self.rules[idx].name, next.as_secs()),
+            None => debug!("{} won't run again", self.rules[idx].name)
+        }
+    }
+
+    /// Show the next page, or the previous one if `forward` isn't set, wrapping around at the ends
+    fn step_page(&mut self, forward: bool, now: Instant) {
+        let count = self.pages().pages.len();
+        if count == 0 {
+            return;
+        }
+        let idx = if forward { (self.shown + 1) % count } else { (self.shown + count - 1) % count };
+        self.show_page(idx, now);
+    }
+
+    fn show_page(&mut self, idx: usize, now: Instant) {
+        if idx != self.shown {
+            self.shown = idx;
+            self.screens[self.profile][idx].invalidate();
+        }
+        self.shown_since = now;
+        let page = &self.profiles[self.profile].pages.pages[idx];
+        debug!("Showing page {}", page.name);
+        self.page = Some(page.name.clone());
+        self.leds.set_overrides(&page.leds, now);
+    }
+
+    /// Switch to the profile's first page, save it and run its startup rules
+    fn show_profile(&mut self, idx: usize, now: Instant) {
+        if idx == self.profile {
+            self.show_profile_banner(now);
+            return;
+        }
+        self.profile = idx;
+        info!("Switched to profile {}", self.profiles[idx].name);
+        self.show_profile_banner(now);
+        self.shown = 0;
+        self.shown_since = now;
+        self.page = None;
+        self.leds.set_overrides(&[], now);
+        self.screens[idx][0].invalidate();
+        if !self.pages().pages.is_empty() {
+            self.show_page(0, now);
+        }
+        if let Some(file) = &self.state_file {
+            if let Err(err) = fs::write(file, &self.profiles[idx].name) {
+                error!("Unable to save profile to {}: {}", file.display(), err);
+            }
+        }
+        for rule in 0..self.rules.len() {
+            if self.rules[rule].profile != Some(idx) {
+                continue;
+            }
+            match &self.rules[rule].trigger {
+                Trigger::Startup => self.trigger(rule, vec![(VAR_TRIGGER, TRIGGER_STARTUP.to_owned())]),
+                Trigger::Schedule(job) if job.run_at_startup => self.trigger(rule, vec![(VAR_TRIGGER, TRIGGER_SCHEDULE.to_owned())]),
+                _ => {}
+            }
+        }
+    }
+
+    fn show_profile_banner(&mut self, now: Instant) {
+        self.show_banner(vec!["Profile".to_owned(), self.profiles[self.profile].name.clone()], Align::Center, PROFILE_BANNER, now);
+    }
+
+    /// Show `lines` centered vertically instead of the pages for `duration`
+    fn show_banner(&mut self, lines: Vec<String>, align: Align, duration: Duration, now: Instant) {
+        let top = ROWS.saturating_sub(lines.len()) / 2;
+        let mut banner = Screen::new();
+        banner.set_rows(top..ROWS, lines.iter().map(|line| showable(line)).collect(), align, None, now);
+        self.banner = Some((banner, now + duration));
+    }
+
+    /// Trigger chord rules whose buttons are all held down
+    /// Rules triggered by button `events` and buttons still held at `now`, the page buttons switch pages
+    fn button_triggers(&mut self, events: &[ButtonEvent], now: Instant) -> Vec<(usize, OwnedVars)> {
+        let activities = self.gestures.handle(events, now);
+        let mut triggered = vec![];
+        for &activity in &activities {
+            // navigation buttons only change pages, or every page change would also run their rules
+            let button = activity.button();
+            if self.pages().next_button == Some(button) || self.pages().previous_button == Some(button) {
+                if let Activity::Press { time, .. } = activity {
+                    self.step_page(self.pages().next_button == Some(button), time);
+                }
+                continue;
+            }
+            for idx in 0..self.rules.len() {
+                if let Some((button, trigger)) = self.gesture_matches(idx, activity) {
+                    triggered.push((idx, vec![(VAR_TRIGGER, trigger.to_owned()), (VAR_BUTTON_ID, button.to_string())]));
+                }
+            }
+        }
+        triggered.extend(self.check_chords(&activities));
+        triggered
+    }
+
+    fn check_chords(&mut self, activities: &[Activity]) -> Vec<(usize, OwnedVars)> {
+        let mut held = [None; BUTTON_COUNT];
+        for activity in activities {
+            if let Activity::Held { button, since, .. } = activity {
+                held[*button] = Some(*since);
+            }
+        }
+        let mut triggered = vec![];
+        for idx in 0..self.rules.len() {
+            let latest = match &self.rules[idx].trigger {
+                Trigger::Chord(buttons) => buttons.iter().map(|button| held[*button]).collect::<Option<Vec<Instant>>>().and_then(|times| times.into_iter().max()),
+                _ => None
+            };
+            if let Some(latest) = latest {
+                if self.states[idx].chord != Some(latest) {
+                    self.states[idx].chord = Some(latest);
+                    triggered.push((idx, vec![(VAR_TRIGGER, TRIGGER_CHORD.to_owned())]));
+                }
+            }
+        }
+        triggered
+    }
+
+    /// Returns the button and gesture name if `activity` triggers the rule
+    fn gesture_matches(&mut self, idx: usize, activity: Activity) -> Option<(usize, &'static str)> {
+        let (button, gesture, debounce) = match self.rules[idx].trigger {
+            Trigger::Button { button, gesture, debounce } => (button, gesture, debounce),
+            _ => return None
+        };
+        let state = &mut self.states[idx];
+        let (time, name) = match (gesture, activity) {
+            (Gesture::Press, Activity::Press { button: pressed, time }) if pressed == button => (time, TRIGGER_PRESS),
+            (Gesture::Release, Activity::Release { button: released, time }) if released == button => (time, TRIGGER_RELEASE),
+            (Gesture::LongPress(hold), Activity::Held { button: held_button, since, held }) if held_button == button && held >= hold => {
+                if state.long_press == Some(since) {
+                    return None;
+                }
+                state.long_press = Some(since);
+                (since + held, TRIGGER_LONG_PRESS)
+            }
+            (Gesture::DoublePress(window), Activity::Press { button: pressed, time }) if pressed == button => {
+                match state.first_press.take() {
+                    Some(first) if time.saturating_duration_since(first) <= window => (time, TRIGGER_DOUBLE_PRESS),
+                    _ => {
+                        state.first_press = Some(time);
+                        return None;
+                    }
+                }
+            }
+            _ => return None
+        };
+        if let Some(last) = state.last_trigger {
+            if time.saturating_duration_since(last) < debounce {
+                return None;
+            }
+        }
+        state.last_trigger = Some(time);
+        Some((button, name))
+    }
+
+    /// Check conditions that don't need scripts and queue the rule to run
+    fn trigger(&mut self, idx: usize, mut vars: OwnedVars) {
+        let rule = self.rules[idx].clone();
+        if !self.is_active(&rule) || !rule.conditions.iter().all(|condition| self.condition_passes(condition)) {
+            return;
+        }
+        debug!("Running {} ({})", rule.name, vars.iter().find(|(name, _)| *name == VAR_TRIGGER).map(|(_, value)| value.as_str()).unwrap_or_default());
+        vars.push((VAR_DEVICE_SERIAL, self.device_serial.clone()));
+        vars.push((VAR_RULE, rule.name.clone()));
+        self.pool.submit(idx, rule.overlap, rule.timeout, Box::new(move |worker| run_rule(&rule, &vars, worker)));
+    }
+
+    /// Lines of the filled in template, values that can't be read are shown as `?`
+    fn render(&mut self, template: &Template) -> Vec<String> {
+        let now = Instant::now();
+        let text = template.render(|field| match field {
+            Field::Source(source) => match self.sources.read(source, now) {
+                Ok(reading) => Value::Reading(reading),
+                Err(err) => {
+                    warn!("Unable to read {}: {}", source, err);
+                    Value::Text("?".to_owned())
+                }
+            },
+            Field::Script { rule, line } => {
+                let output = self.outputs.get(rule).map(String::as_str).unwrap_or_default();
+                Value::Text(match line {
+                    Some(line) => output.lines().nth(*line).unwrap_or_default().to_owned(),
+                    None => output.trim_end().to_owned()
+                })
+            }
+            Field::Page => Value::Text(self.page.clone().unwrap_or_default()),
+            Field::Profile => Value::Text(self.profiles[self.profile].name.clone()),
+        });
+        text.lines().map(String::from).collect()
+    }
+
+    /// Script conditions are checked when the rule runs
+    fn condition_passes(&mut self, condition: &Condition) -> bool {
+        match condition {
+            Condition::ScriptSucceeds(_) => true,
+            Condition::FileExists(path) => path.exists(),
+            Condition::Time(window) => window.contains(Local::now().naive_local()),
+            Condition::Led(led, on) => self.leds.is_on(*led) == *on,
+            Condition::Page(page) => self.page.as_ref() == Some(page),
+            Condition::Profile(profile) => &self.profiles[self.profile].name == profile,
+            Condition::Source(source, bounds) => match self.sources.read(source, Instant::now()) {
+                Ok(reading) => reading.number().is_some_and(|value| bounds.contains(value)),
+                Err(err) => {
+                    warn!("Unable to read {}: {}", source, err);
+                    false
+                }
+            },
+        }
+    }
+}
+
+/// Run on a worker, checks script conditions then runs the actions
+fn run_rule(rule: &Rule, vars: &OwnedVars, worker: &Worker<usize, Effect>) {
+    for condition in &rule.conditions {
+        if let Condition::ScriptSucceeds(command) = condition {
+            match worker.run(command, vars, false) {
+                Outcome::Failed(err) => {
+                    error!("Error when executing {} condition script: {}", rule.name, err);
+                    return;
+                }
+                Outcome::TimedOut => {
+                    warn!("{} condition script timed out", rule.name);
+                    return;
+                }
+                outcome if !outcome.success() => return,
+                _ => {}
+            }
+        }
+    }
+    for action in &rule.actions {
+        if worker.is_cancelled() {
+            return;
+        }
+        match action {
+            Action::SetLed(led, state) => worker.emit(Effect::Led(*led, state.clone())),
+            Action::ShowText(text) => worker.emit(Effect::Text(printable(text))),
+            Action::ShowTemplate(template, layout) => worker.emit(Effect::Template(template.clone(), layout.clone())),
+            Action::RunScript(command, target) => {
+                let outcome = worker.run(command, vars, rule.keep_output || matches!(target, Target::Display | Target::Lines(_) | Target::Json(_)));
+                match &outcome {
+                    Outcome::Failed(err) => error!("Error when executing {} script: {}", rule.name, err),
+                    Outcome::TimedOut => warn!("{} script timed out", rule.name),
+                    outcome => trace!("{} script finished: {:?}", rule.name, outcome)
+                }
+                if let (true, Outcome::Exited { stdout, .. }) = (rule.keep_output, &outcome) {
+                    worker.emit(Effect::Output(stdout.clone()));
+                }
+                match (target, outcome) {
+                    (_, Outcome::Cancelled) | (Target::None, _) => {}
+                    (Target::Led { led, states }, outcome) => {
+                        if let Some(state) = states.get(&outcome) {
+                            worker.emit(Effect::Led(*led, state.clone()));
+                        }
+                    }
+                    (Target::TrafficLight(lights), outcome) => {
+                        for (led, state) in lights.get(&outcome).into_iter().flatten().enumerate() {
+                            worker.emit(Effect::Led(led, state.clone()));
+                        }
+                    }
+                    (_, Outcome::Failed(_)) => {}
+                    (target, Outcome::TimedOut) => worker.emit(message(&format!("{} timed out", rule.name), target)),
+                    (Target::Display, Outcome::Exited { stdout, .. }) => worker.emit(Effect::Text(printable(&stdout))),
+                    (Target::Lines(layout), Outcome::Exited { stdout, .. }) => {
+                        worker.emit(lay_out(&stdout.lines().map(String::from).collect::<Vec<String>>(), layout));
+                    }
+                    (Target::Json(layout), Outcome::Exited { stdout, .. }) => match Document::parse(&stdout) {
+                        Ok(document) => apply_document(document, layout, worker),
+                        Err(err) => {
+                            warn!("{} script output is not valid: {}", rule.name, err);
+                            worker.emit(message(&format!("{}: {}", rule.name, err), target));
+                        }
+                    },
+                }
+            }
+            Action::SwitchPage(page) => worker.emit(Effect::Page(page.clone())),
+            Action::NextPage => worker.emit(Effect::NextPage),
+            Action::PreviousPage => worker.emit(Effect::PreviousPage),
+            Action::SwitchProfile(profile) => worker.emit(Effect::Profile(profile.clone())),
+            Action::NextProfile => worker.emit(Effect::NextProfile),
+        }
+    }
+}
+
+fn apply_document(document: Document, layout: &Layout, worker: &Worker<usize, Effect>) {
+    if let Some(lines) = &document.lines {
+        worker.emit(lay_out(lines, layout));
+    }
+    for (led, state) in &document.leds {
+        worker.emit(Effect::Led(*led, state.clone()));
+    }
+    if let Some(page) = document.page {
+        worker.emit(Effect::Page(page));
+    }
+    if let Some(after) = document.ttl {
+        worker.emit(Effect::Expire {
+            after,
+            rows: if document.lines.is_some() { layout.rows.clone() } else { 0..0 },
+            leds: document.leds.iter().map(|(led, _)| *led).collect(),
+        });
+    }
+}
+
+/// One per page, with the page number in the corner if the pages have an indicator
+fn page_screens(pages: &Pages) -> Vec<Screen> {
+    let mut screens = (0..pages.pages.len().max(1)).map(|_| Screen::new()).collect::<Vec<Screen>>();
+    set_indicators(pages, &mut screens);
+    screens
+}
+
+fn set_indicators(pages: &Pages, screens: &mut [Screen]) {
+    let count = pages.pages.len();
+    for (idx, screen) in screens.iter_mut().enumerate() {
+        screen.set_indicator(if pages.indicator && count > 1 { Some(format!("{}/{}", idx + 1, count)) } else { None });
+    }
+}
+
+fn bind_socket(path: &str) -> Option<SocketListener> {
+    match SocketListener::bind(path) {
+        Ok(socket) => Some(socket),
+        Err(err) => {
+            error!("Unable to open socket {}: {}", path, err);
+            None
+        }
+    }
+}
+
+fn send(manager: &mut DeviceManager, update: Update) {
+    if let Err(err) = manager.send(update) {
+        error!("Error when updating device: {}", err);
+    }
+}
+
+/// Fit lines to the display rows, rows that are too long are cut off or scrolled by [Screen]
+fn lay_out(lines: &[String], layout: &Layout) -> Effect {
+    let lines = match layout.tail {
+        Some(tail) => &lines[lines.len().saturating_sub(tail)..],
+        None => lines
+    };
+    let lines = lines.iter().map(|line| showable(line));
+    let mut rows = match layout.overflow {
+        Overflow::Wrap => lines.flat_map(|line| wrap(&line, COLUMNS)).collect(),
+        Overflow::Truncate | Overflow::Scroll(_) => lines.collect::<Vec<String>>()
+    };
+    let height = layout.rows.len();
+    if layout.tail.is_some() {
+        rows.drain(..rows.len().saturating_sub(height));
+    }
+    rows.truncate(height);
+    let scroll = match layout.overflow {
+        Overflow::Scroll(step) => Some(step),
+        Overflow::Truncate | Overflow::Wrap => None
+    };
+    Effect::Rows { region: layout.rows.clone(), lines: rows, align: layout.align, scroll }
+}
+
+/// Show an error or status message where the target's output would go
+fn message(text: &str, target: &Target) -> Effect {
+    match target {
+        Target::Lines(layout) | Target::Json(layout) => lay_out(&[text.to_owned()], &Layout { overflow: Overflow::Wrap, tail: None, ..layout.clone() }),
+        _ => Effect::Text(printable(text))
+    }
+}
+
+/// Only keep characters the display can show, cut off at the display size
+fn printable(text: &str) -> String {
+    showable(text).chars().take(TEXT_MAX_LEN).collect()
+}
+
+/// Only keep characters the display can show
+fn showable(text: &str) -> String {
+    text.chars()
+        .filter(|letter| letter.is_ascii_graphic() || *letter == ' ')
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::test_rules;
+
+    const MS: Duration = Duration::from_millis(1);
+
+    fn engine(rules: &[String]) -> Engine {
+        Engine::new(test_rules(&format!(r#"{{"output": "none", "rules": [{}]}}"#, rules.join(", "))), String::new())
+    }
+
+    fn button_rule(name: &str, trigger: &str) -> String {
+        format!(r#"{{"name": "{}", "trigger": {}, "actions": [{{"show_text": "{}"}}]}}"#, name, trigger, name)
+    }
+
+    fn event(button: usize, pressed: bool, time: Instant) -> ButtonEvent {
+        ButtonEvent { button, pressed, time }
+    }
+
+    /// Indexes of the rules triggered
+    fn triggered(engine: &mut Engine, events: &[ButtonEvent], now: Instant) -> Vec<usize> {
+        engine.button_triggers(events, now).into_iter().map(|(idx, _)| idx).collect()
+    }
+
+    #[test]
+    fn each_button_fires_once_per_press() {
+        let rules = (0..BUTTON_COUNT).map(|button| button_rule(&format!("Button {}", button), &format!(r#"{{"button": {{"button": {}}}}}"#, button))).collect::<Vec<String>>();
+        let mut engine = engine(&rules);
+        let start = Instant::now();
+        for button in 0..BUTTON_COUNT {
+            let pressed = start + MS * 1000 * button as u32;
+            let vars = engine.button_triggers(&[event(button, true, pressed)], pressed);
+            assert_eq!(vars, vec![(button, vec![(VAR_TRIGGER, TRIGGER_PRESS.to_owned()), (VAR_BUTTON_ID, button.to_string())])]);
+            // still held on the following ticks
+            for tick in 1..=20 {
+                assert_eq!(triggered(&mut engine, &[], pressed + MS * 50 * tick), Vec::<usize>::new(), "button {} tick {}", button, tick);
+            }
+            assert_eq!(triggered(&mut engine, &[event(button, false, pressed + MS * 900)], pressed + MS * 900), Vec::<usize>::new());
+        }
+        let again = start + MS * 10_000;
+        assert_eq!(triggered(&mut engine, &[event(2, true, again)], again), vec![2]);
+    }
+
+    #[test]
+    fn presses_within_the_debounce_are_ignored() {
+        let mut engine = engine(&[button_rule("Press", r#"{"button": {"button": 0, "debounce_ms": 100}}"#)]);
+        let start = Instant::now();
+        assert_eq!(triggered(&mut engine, &[event(0, true, start), event(0, false, start + MS * 10), event(0, true, start + MS * 50)], start + MS * 50), vec![0]);
+        assert_eq!(triggered(&mut engine, &[event(0, false, start + MS * 60), event(0, true, start + MS * 150)], start + MS * 150), vec![0]);
+    }
+
+    #[test]
+    fn gestures() {
+        let mut engine = engine(&[
+            button_rule("Release", r#"{"button": {"button": 0, "gesture": "release"}}"#),
+            button_rule("Long", r#"{"button": {"button": 1, "gesture": "long_press", "hold_ms": 500}}"#),
+            button_rule("Double", r#"{"button": {"button": 2, "gesture": "double_press", "double_ms": 300}}"#),
+        ]);
+        let start = Instant::now();
+        assert_eq!(triggered(&mut engine, &[event(0, true, start)], start), Vec::<usize>::new());
+        assert_eq!(triggered(&mut engine, &[event(0, false, start + MS * 100)], start + MS * 100), vec![0]);
+
+        let start = start + MS * 1000;
+        assert_eq!(triggered(&mut engine, &[event(1, true, start)], start + MS * 400), Vec::<usize>::new());
+        assert_eq!(triggered(&mut engine, &[], start + MS * 500), vec![1]);
+        assert_eq!(triggered(&mut engine, &[], start + MS * 2000), Vec::<usize>::new());
+        assert_eq!(triggered(&mut engine, &[event(1, false, start + MS * 2100)], start + MS * 2100), Vec::<usize>::new());
+
+        let start = start + MS * 5000;
+        assert_eq!(triggered(&mut engine, &[event(2, true, start), event(2, false, start + MS * 50)], start + MS * 50), Vec::<usize>::new());
+        assert_eq!(triggered(&mut engine, &[event(2, true, start + MS * 200)], start + MS * 200), vec![2]);
+        // too slow, starts a new double press
+        let start = start + MS * 2000;
+        assert_eq!(triggered(&mut engine, &[event(2, false, start), event(2, true, start + MS * 100)], start + MS * 100), Vec::<usize>::new());
+        assert_eq!(triggered(&mut engine, &[event(2, false, start + MS * 150), event(2, true, start + MS * 500)], start + MS * 500), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn chords_fire_once_per_hold() {
+        let mut engine = engine(&[
+            button_rule("Chord", r#"{"chord": {"buttons": [0, 3]}}"#),
+            button_rule("Press", r#"{"button": {"button": 0}}"#),
+        ]);
+        let start = Instant::now();
+        assert_eq!(triggered(&mut engine, &[event(0, true, start)], start), vec![1]);
+        assert_eq!(triggered(&mut engine, &[event(3, true, start + MS * 100)], start + MS * 100), vec![0]);
+        assert_eq!(triggered(&mut engine, &[], start + MS * 500), Vec::<usize>::new());
+        assert_eq!(triggered(&mut engine, &[event(3, false, start + MS * 600), event(3, true, start + MS * 700)], start + MS * 700), vec![0]);
+    }
+
+    #[test]
+    fn reload_keeps_the_schedule_of_renamed_rules() {
+        let rule = |name: &str, minutes: usize| format!(r#"{{"name": "{}", "trigger": {{"schedule": {{"freq_amount": {}, "freq_unit": "minutes", "run_at_startup": false}}}}, "actions": [{{"show_text": "tick"}}]}}"#, name, minutes);
+        let mut engine = engine(&[rule("Tick", 1), rule("Other", 1)]);
+        let before = engine.scheduler.next_run(&0).unwrap();
+        std::thread::sleep(MS * 50);
+        engine.reload(test_rules(&format!(r#"{{"output": "none", "rules": [{}, {}]}}"#, rule("Changed", 2), rule("Renamed", 1))));
+        assert_eq!(engine.rule_names(), vec!["Changed", "Renamed"]);
+        assert!(engine.scheduler.next_run(&1).unwrap() <= before - MS * 50);
+        assert!(engine.scheduler.next_run(&0).unwrap() > Duration::from_secs(60));
+    }
+
+    #[test]
+    fn navigation_buttons_only_change_pages() {
+        let rules = [button_rule("Next", r#"{"button": {"button": 3}}"#), button_rule("Press", r#"{"button": {"button": 0}}"#)];
+        let pages = r#"{"list": [{"name": "first"}, {"name": "second"}], "next_button": 3, "previous_button": 2}"#;
+        let mut engine = Engine::new(test_rules(&format!(r#"{{"output": "none", "pages": {}, "rules": [{}]}}"#, pages, rules.join(", "))), String::new());
+        let start = Instant::now();
+        assert_eq!(triggered(&mut engine, &[event(3, true, start)], start), Vec::<usize>::new());
+        assert_eq!(engine.page.as_deref(), Some("second"));
+        assert_eq!(triggered(&mut engine, &[event(3, false, start + MS * 100), event(0, true, start + MS * 100)], start + MS * 100), vec![1]);
+        assert_eq!(engine.page.as_deref(), Some("second"));
+        assert_eq!(triggered(&mut engine, &[event(2, true, start + MS * 200)], start + MS * 200), Vec::<usize>::new());
+        assert_eq!(engine.page.as_deref(), Some("first"));
+    }
+}
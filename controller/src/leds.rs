@@ -0,0 +1,131 @@
+use std::time::{Duration, Instant};
+use comm_lib::LED_COUNT;
+use crate::config::rules::LedState;
+
+/// Time on and then off for `LedState::Blink`
+const BLINK_HALF_PERIOD: Duration = Duration::from_millis(500);
+
+/// Tracks what each LED should be doing and works out when to turn them on or off,
+/// the device only supports on and off so blinking is done here
+pub struct LedDriver {
+    states: [LedState; LED_COUNT],
+    /// When the current state was set, patterns start from here
+    since: [Instant; LED_COUNT],
+    /// Used instead of `states` while set, with when they were set
+    overrides: [Option<(LedState, Instant)>; LED_COUNT],
+    /// Last value sent to the device
+    sent: [Option<bool>; LED_COUNT],
+}
+
+impl LedDriver {
+    pub fn new() -> Self {
+        let now = Instant::now();
+        LedDriver { states: Default::default(), since: [now; LED_COUNT], overrides: Default::default(), sent: [None; LED_COUNT] }
+    }
+}
+
+impl LedDriver {
+    /// `LedState::Unchanged` is ignored, setting the current state again doesn't restart it
+    pub fn set(&mut self, led: usize, state: LedState, now: Instant) {
+        if state == LedState::Unchanged || self.states[led] == state {
+            return;
+        }
+        self.states[led] = state;
+        self.since[led] = now;
+    }
+
+    /// Replace all overrides, LEDs not in `overrides` go back to their normal state
+    pub fn set_overrides(&mut self, overrides: &[(usize, LedState)], now: Instant) {
+        let mut new: [Option<(LedState, Instant)>; LED_COUNT] = Default::default();
+        for (led, state) in overrides {
+            if *state != LedState::Unchanged {
+                new[*led] = match &self.overrides[*led] {
+                    Some((current, since)) if current == state => Some((current.clone(), *since)),
+                    _ => Some((state.clone(), now))
+                };
+            }
+        }
+        self.overrides = new;
+    }
+
+    /// If the LED is anything other than off
+    pub fn is_on(&self, led: usize) -> bool {
+        let state = self.overrides[led].as_ref().map(|(state, _)| state).unwrap_or(&self.states[led]);
+        !matches!(state, LedState::Off | LedState::Unchanged)
+    }
+
+    /// LEDs that need to be turned on or off
+    pub fn update(&mut self, now: Instant) -> Vec<(usize, bool)> {
+        let mut changes = vec![];
+        for led in 0..LED_COUNT {
+            let lit = self.lit(led, now);
+            if self.sent[led] != Some(lit) {
+                self.sent[led] = Some(lit);
+                changes.push((led, lit));
+            }
+        }
+        changes
+    }
+
+    fn lit(&self, led: usize, now: Instant) -> bool {
+        let (state, since) = match &self.overrides[led] {
+            Some((state, since)) => (state, *since),
+            None => (&self.states[led], self.since[led])
+        };
+        let elapsed = now.saturating_duration_since(since);
+        match state {
+            LedState::On => true,
+            LedState::Off | LedState::Unchanged => false,
+            LedState::Blink => (elapsed.as_millis() / BLINK_HALF_PERIOD.as_millis()).is_multiple_of(2),
+            LedState::Pattern(steps, step) => {
+                let idx = elapsed.as_millis() / step.as_millis().max(1);
+                steps[idx as usize % steps.len()]
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use comm_lib::{LED_BLUE, LED_GREEN, LED_RED};
+
+    const MS: Duration = Duration::from_millis(1);
+
+    #[test]
+    fn overrides_are_used_over_the_normal_state() {
+        let mut leds = LedDriver::new();
+        let now = Instant::now();
+        leds.set(LED_GREEN, LedState::On, now);
+        leds.set(LED_BLUE, LedState::On, now);
+        assert_eq!(leds.update(now), vec![(LED_GREEN, true), (LED_BLUE, true), (LED_RED, false)]);
+
+        leds.set_overrides(&[(LED_GREEN, LedState::Off), (LED_BLUE, LedState::Unchanged), (LED_RED, LedState::On)], now);
+        assert!(!leds.is_on(LED_GREEN) && leds.is_on(LED_BLUE) && leds.is_on(LED_RED));
+        assert_eq!(leds.update(now), vec![(LED_GREEN, false), (LED_RED, true)]);
+        // the normal state still changes underneath
+        leds.set(LED_GREEN, LedState::Blink, now);
+        assert_eq!(leds.update(now), vec![]);
+
+        leds.set_overrides(&[], now);
+        assert_eq!(leds.update(now), vec![(LED_GREEN, true), (LED_RED, false)]);
+    }
+
+    #[test]
+    fn blinking_and_patterns_keep_time_when_set_again() {
+        let mut leds = LedDriver::new();
+        let now = Instant::now();
+        leds.set(LED_GREEN, LedState::Blink, now);
+        leds.set_overrides(&[(LED_RED, LedState::Pattern(vec![true, true, false], MS * 100))], now);
+        assert_eq!(leds.update(now), vec![(LED_GREEN, true), (LED_BLUE, false), (LED_RED, true)]);
+        assert_eq!(leds.update(now + MS * 150), vec![]);
+
+        leds.set(LED_GREEN, LedState::Blink, now + MS * 200);
+        leds.set_overrides(&[(LED_RED, LedState::Pattern(vec![true, true, false], MS * 100))], now + MS * 200);
+        assert_eq!(leds.update(now + MS * 250), vec![(LED_RED, false)]);
+        assert_eq!(leds.update(now + MS * 500), vec![(LED_GREEN, false)]);
+
+        leds.set(LED_GREEN, LedState::Unchanged, now + MS * 600);
+        assert_eq!(leds.update(now + MS * 1000), vec![(LED_GREEN, true), (LED_RED, true)]);
+    }
+}
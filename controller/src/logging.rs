@@ -0,0 +1,127 @@
+use std::io;
+use tracing::{info, warn};
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_appender::rolling::RollingFileAppender;
+use tracing_subscriber::{EnvFilter, fmt, Layer, Registry, reload};
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::layer::{Layered, SubscriberExt};
+use tracing_subscriber::util::SubscriberInitExt;
+use crate::config::rules::Logging;
+
+type BoxedLayer = Box<dyn Layer<Registry> + Send + Sync>;
+type Subscriber = Layered<reload::Layer<EnvFilter, Layered<Vec<BoxedLayer>, Registry>>, Layered<Vec<BoxedLayer>, Registry>>;
+
+/// Logging that has been started, must be kept until the program exits so the log file is flushed
+pub struct Logger {
+    filter: reload::Handle<EnvFilter, Layered<Vec<BoxedLayer>, Registry>>,
+    /// Settings in use, only the filter can be changed without restarting
+    logging: Logging,
+    _guard: Option<WorkerGuard>,
+}
+
+/// Start logging
+pub fn init(logging: &Logging) -> io::Result<Logger> {
+    let (subscriber, logger) = subscriber(logging)?;
+    subscriber.try_init().map_err(io::Error::other)?;
+    Ok(logger)
+}
+
+impl Logger {
+    /// Use the settings of a reloaded config, changes other than the filter need a restart
+    pub fn reload(&mut self, logging: &Logging) {
+        if logging.filter != self.logging.filter {
+            match parse_filter(&logging.filter).and_then(|filter| self.filter.reload(filter).map_err(io::Error::other)) {
+                Ok(()) => {
+                    info!("Log filter changed to {}", logging.filter);
+                    self.logging.filter = logging.filter.clone();
+                }
+                Err(err) => warn!("Log filter not changed: {}", err),
+            }
+        }
+        let current = &self.logging;
+        if logging.json != current.json || logging.stderr != current.stderr || logging.file != current.file || logging.journald != current.journald {
+            warn!("Log output settings have changed, restart the controller to use them");
+        }
+    }
+}
+
+fn subscriber(logging: &Logging) -> io::Result<(Subscriber, Logger)> {
+    let mut layers: Vec<BoxedLayer> = vec![];
+    let mut guard = None;
+    if logging.stderr {
+        layers.push(format_layer(logging.json, true, io::stderr));
+    }
+    if let Some(file) = &logging.file {
+        let name = file.path.file_name()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "log file has no file name"))?;
+        let mut builder = RollingFileAppender::builder()
+            .rotation(file.rotation.clone())
+            .filename_prefix(name.to_string_lossy());
+        if let Some(keep) = file.keep {
+            builder = builder.max_log_files(keep);
+        }
+        let dir = file.path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(".".as_ref());
+        let appender = builder.build(dir).map_err(io::Error::other)?;
+        let (writer, file_guard) = tracing_appender::non_blocking(appender);
+        guard = Some(file_guard);
+        layers.push(format_layer(logging.json, false, writer));
+    }
+    if logging.journald {
+        layers.push(journald_layer()?);
+    }
+    let (filter, handle) = reload::Layer::new(parse_filter(&logging.filter)?);
+    let subscriber = tracing_subscriber::registry().with(layers).with(filter);
+    Ok((subscriber, Logger { filter: handle, logging: logging.clone(), _guard: guard }))
+}
+
+fn parse_filter(filter: &str) -> io::Result<EnvFilter> {
+    EnvFilter::try_new(filter).map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))
+}
+
+fn format_layer<W>(json: bool, ansi: bool, writer: W) -> BoxedLayer
+    where W: for<'w> MakeWriter<'w> + Send + Sync + 'static {
+    let layer = fmt::layer().with_writer(writer).with_ansi(ansi);
+    if json {
+        layer.json().boxed()
+    } else {
+        layer.boxed()
+    }
+}
+
+#[cfg(unix)]
+fn journald_layer() -> io::Result<BoxedLayer> {
+    Ok(tracing_journald::layer()?.boxed())
+}
+
+#[cfg(not(unix))]
+fn journald_layer() -> io::Result<BoxedLayer> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "journald is only supported on unix"))
+}
+
+/// Logger that isn't in use, for testing code that reloads it
+#[cfg(test)]
+pub fn test_logger() -> Logger {
+    subscriber(&Logging::new("info".to_owned(), false, false, None, false)).unwrap().1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tracing::Level;
+
+    fn logging(filter: &str) -> Logging {
+        Logging::new(filter.to_owned(), false, true, None, false)
+    }
+
+    #[test]
+    fn reload_changes_the_filter() {
+        let (subscriber, mut logger) = subscriber(&logging("info")).unwrap();
+        let _default = tracing::subscriber::set_default(subscriber);
+        assert!(!tracing::enabled!(Level::DEBUG));
+        logger.reload(&logging("debug"));
+        assert!(tracing::enabled!(Level::DEBUG));
+        logger.reload(&logging("info,controller=[bad"));
+        assert!(tracing::enabled!(Level::DEBUG));
+        assert_eq!(logger.logging.filter, "debug");
+    }
+}
@@ -1,15 +1,39 @@
 mod config;
+mod buttons;
+mod command;
+mod engine;
+mod leds;
+mod logging;
+mod output;
+mod pool;
+mod reload;
+mod scheduler;
+mod screen;
+mod socket;
+mod sources;
+mod template;
+mod watch;
 
-use std::borrow::BorrowMut;
+use color_eyre::eyre::eyre;
 use color_eyre::Result;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::process;
 use std::thread::sleep;
 use std::time::Duration;
-use clap::{App, Arg, crate_authors, crate_description, crate_name, crate_version};
-use comm_lib::{get_best_match_device, LED_BLUE, LED_GREEN, LED_RED};
-use comm_lib::manager::{DeviceManager, Update};
-use crate::config::load_config;
-use crate::config::rules::{NextExecution, Rules};
+use clap::{App, Arg, ArgMatches, crate_authors, crate_description, crate_name, crate_version, SubCommand};
+use tracing::{error, info};
+use tracing_appender::rolling::Rotation;
+use comm_lib::get_best_match_device;
+use comm_lib::manager::DeviceManager;
+use crate::config::{check_config, config_schema, convert_config, exit_code, Format, load_config, read_config, Severity};
+use crate::config::rules::{LogFile, Rules};
+use crate::engine::Engine;
+use crate::logging::Logger;
+use crate::reload::ReloadWatcher;
+
+/// Longest time between checking for button presses
+const TICK: Duration = Duration::from_millis(50);
+const FORMATS: [&str; 4] = ["json", "toml", "yaml", "yml"];
 
 fn main() -> Result<()>{
     color_eyre::install()?;
@@ -25,88 +49,222 @@ fn main() -> Result<()>{
             .required(false)
             .takes_value(true)
             .value_name("FILE"))
+        .arg(Arg::with_name("format")
+            .long("format")
+            .help("Config file format, by default it's picked using the file extension")
+            .takes_value(true)
+            .possible_values(&FORMATS)
+            .value_name("FORMAT"))
+        .arg(Arg::with_name("log")
+            .long("log")
+            .help("Log filter, e.g. debug or info,controller::engine=trace, used instead of the config")
+            .takes_value(true)
+            .value_name("FILTER"))
+        .arg(Arg::with_name("log-json")
+            .long("log-json")
+            .help("Log JSON lines instead of plain text"))
+        .arg(Arg::with_name("log-file")
+            .long("log-file")
+            .help("Also log to this file, it's not rotated")
+            .takes_value(true)
+            .value_name("FILE"))
+        .arg(Arg::with_name("journald")
+            .long("journald")
+            .help("Also log to journald"))
+        .subcommand(SubCommand::with_name("check")
+            .about("Check a config file for errors and likely mistakes without connecting to the device, exits with 1 if there are errors")
+            .arg(Arg::with_name("config")
+                .default_value("config.json")
+                .help("Config file to check")
+                .value_name("FILE"))
+            .arg(Arg::with_name("format")
+                .long("format")
+                .help("Config file format, by default it's picked using the file extension")
+                .takes_value(true)
+                .possible_values(&FORMATS)
+                .value_name("FORMAT"))
+            .arg(Arg::with_name("deny-warnings")
+                .long("deny-warnings")
+                .help("Also exit with 1 if there are warnings")))
+        .subcommand(SubCommand::with_name("schema")
+            .about("Print the JSON Schema of the config file, for editors to autocomplete and validate configs"))
+        .subcommand(SubCommand::with_name("convert")
+            .about("Rewrite a config file in another format, comments aren't kept")
+            .arg(Arg::with_name("input")
+                .help("Config file to convert")
+                .required(true)
+                .value_name("INPUT"))
+            .arg(Arg::with_name("output")
+                .help("File to write, replaced if it exists")
+                .required(true)
+                .value_name("OUTPUT"))
+            .arg(Arg::with_name("from")
+                .long("from")
+                .help("Format of INPUT, by default it's picked using the file extension")
+                .takes_value(true)
+                .possible_values(&FORMATS)
+                .value_name("FORMAT"))
+            .arg(Arg::with_name("to")
+                .long("to")
+                .help("Format of OUTPUT, by default it's picked using the file extension")
+                .takes_value(true)
+                .possible_values(&FORMATS)
+                .value_name("FORMAT")))
         .get_matches();
 
-    let config_file_path = matches.value_of("config").unwrap();
+    if let Some(matches) = matches.subcommand_matches("check") {
+        check(matches);
+        return Ok(());
+    }
+    if matches.subcommand_matches("schema").is_some() {
+        println!("{}", config_schema()?);
+        return Ok(());
+    }
+    if let Some(matches) = matches.subcommand_matches("convert") {
+        return convert(matches);
+    }
+
+    let config_file_path = matches.value_of("config").unwrap_or_default();
     let file = Path::new(config_file_path);
-    if file.exists() {
-        if let Ok(rules) = load_config(config_file_path) {
-            run(rules)
+    let format = format(&matches, "format", file);
+    if !file.exists() {
+        eprintln!("No file found at {}", config_file_path);
+        process::exit(1);
+    }
+    let mut rules = match load_config(config_file_path, format) {
+        Ok(rules) => rules,
+        Err(_) => process::exit(1)
+    };
+    apply_log_args(&mut rules, &matches)?;
+    let mut logger = logging::init(&rules.logging)?;
+    run(rules, config_file_path, format, &matches, &mut logger)
+}
+
+/// Format set by `arg` or from the file extension
+fn format(matches: &ArgMatches, arg: &str, path: &Path) -> Format {
+    matches.value_of(arg)
+        .and_then(Format::from_name)
+        .unwrap_or_else(|| Format::from_path(path))
+}
+
+/// Print problems with the config as `file:line:column: severity: message`, the position is left out if it's not known
+fn check(matches: &ArgMatches) {
+    let path = matches.value_of("config").unwrap_or_default();
+    let diagnostics = check_config(path, format(matches, "format", Path::new(path)));
+    for diagnostic in &diagnostics {
+        let file = diagnostic.file.as_ref().map(|file| file.display().to_string()).unwrap_or_else(|| path.to_owned());
+        match diagnostic.position {
+            Some((line, column)) => eprintln!("{}:{}:{}: {}: {}", file, line, column, diagnostic.severity, diagnostic.message),
+            None => eprintln!("{}: {}: {}", file, diagnostic.severity, diagnostic.message)
         }
+    }
+    let errors = diagnostics.iter().filter(|diagnostic| diagnostic.severity == Severity::Error).count();
+    let warnings = diagnostics.len() - errors;
+    if diagnostics.is_empty() {
+        println!("{} is valid", path);
     } else {
-        eprintln!("No file found at {}", config_file_path);
+        eprintln!("{} error(s), {} warning(s)", errors, warnings);
+    }
+    let code = exit_code(&diagnostics, matches.is_present("deny-warnings"));
+    if code != 0 {
+        process::exit(code);
+    }
+}
+
+fn convert(matches: &ArgMatches) -> Result<()> {
+    let input = matches.value_of("input").unwrap_or_default();
+    let output = matches.value_of("output").unwrap_or_default();
+    let from = format(matches, "from", Path::new(input));
+    let to = format(matches, "to", Path::new(output));
+    convert_config(input, from, output, to).map_err(|err| eyre!(err.lines().join("\n")))
+}
+
+/// Command line log settings override the config
+fn apply_log_args(rules: &mut Rules, matches: &ArgMatches) -> Result<()> {
+    if let Some(filter) = matches.value_of("log") {
+        rules.logging.filter = filter.to_owned();
+    }
+    if matches.is_present("log-json") {
+        rules.logging.json = true;
+    }
+    if let Some(file) = matches.value_of("log-file") {
+        if file.trim().is_empty() {
+            return Err(eyre!("--log-file is empty"));
+        }
+        rules.logging.file = Some(LogFile::new(PathBuf::from(file), Rotation::NEVER, None));
+    }
+    if matches.is_present("journald") {
+        rules.logging.journald = true;
     }
     Ok(())
 }
 
-fn run(rules: Rules) {
-    let board = get_best_match_device().expect("Unable to find device");
+fn run(rules: Rules, config_file_path: &str, format: Format, matches: &ArgMatches, logger: &mut Logger) -> Result<()> {
+    let board = get_best_match_device().map_err(|err| eyre!("Unable to find device: {}", err))?;
     let mut manager = DeviceManager::new(board);
 
-    let mut next_execution = NextExecution::new();
+    let serial = manager.port_name().unwrap_or_default();
+    info!("Connected to device on {}", serial);
+    let mut reload = ReloadWatcher::new(rules.files.clone())?;
+    let mut engine = Engine::new(rules, serial);
     loop {
-        if let Some(led) = &rules.led_red {
-            if next_execution.is_red_led_ready() {
-                match std::process::Command::new(&led.script)
-                    .status() {
-                    Ok(code) => manager.send(Update::LED(LED_RED, code.success())).unwrap(),
-                    Err(err) => eprintln!("Error when executing Red LED script: {}", err)
-                }
-                next_execution.reset_red_led(led.freq.to_seconds());
-            }
-        }
-        if let Some(led) = &rules.led_blue {
-            if next_execution.is_blue_led_ready() {
-                match std::process::Command::new(&led.script)
-                    .status() {
-                    Ok(code) => manager.send(Update::LED(LED_BLUE, code.success())).unwrap(),
-                    Err(err) => eprintln!("Error when executing Blue LED script: {}", err)
-                }
-                next_execution.reset_blue_led(led.freq.to_seconds());
-            }
-        }
-        if let Some(led) = &rules.led_green {
-            if next_execution.is_green_led_ready() {
-                match std::process::Command::new(&led.script)
-                    .status() {
-                    Ok(code) => manager.send(Update::LED(LED_GREEN, code.success())).unwrap(),
-                    Err(err) => eprintln!("Error when executing Green LED script: {}", err)
-                }
-                next_execution.reset_green_led(led.freq.to_seconds());
+        manager.recv().map_err(|err| eyre!("Lost connection to device: {}", err))?;
+        if reload.requested() {
+            if let Some(files) = reload_config(&mut engine, logger, config_file_path, format, matches) {
+                reload.watch(files);
             }
         }
-        if let Some(display) = &rules.display {
-            if next_execution.is_display_ready() {
-                let output = std::process::Command::new(&display.script)
-                    .output()
-                    .unwrap()
-                    .stdout;
+        let wait = engine.tick(&mut manager);
 
-                let output = String::from_utf8(output).unwrap();
+        sleep(wait.unwrap_or(TICK).min(TICK))
+    }
+}
 
-                let mut text = String::new();
+/// Read the config again and use it, the current rules stay in use if it's invalid.
+/// Returns the files of the new config to watch
+fn reload_config(engine: &mut Engine, logger: &mut Logger, config_file_path: &str, format: Format, matches: &ArgMatches) -> Option<Vec<PathBuf>> {
+    let lines = match read_config(config_file_path, format) {
+        Ok(mut rules) => match apply_log_args(&mut rules, matches) {
+            Ok(()) => {
+                logger.reload(&rules.logging);
+                let files = rules.files.clone();
+                engine.reload(rules);
+                return Some(files);
+            }
+            Err(err) => vec![err.to_string()]
+        },
+        Err(err) => err.lines()
+    };
+    for line in &lines {
+        error!("Config not reloaded: {}", line);
+    }
+    engine.reload_failed(&lines);
+    None
+}
 
-                for letter in output.chars().borrow_mut() {
-                    if letter.is_ascii_graphic() || letter as u8 == 32 {
-                       text.push(letter);
-                    }
-                }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
 
-                manager.send(Update::Text(text)).unwrap();
+    #[test]
+    fn invalid_config_keeps_rules() {
+        let dir = std::env::temp_dir().join(format!("controller-main-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("config.json");
+        let path = file.to_str().unwrap();
+        fs::write(&file, r#"{"output": "none", "rules": [{"name": "Tick", "trigger": {"schedule": {"cron": "0 * * * *"}}, "actions": [{"show_text": "tick"}]}]}"#).unwrap();
+        let matches = App::new("test").get_matches_from(vec!["test"]);
+        let mut engine = Engine::new(read_config(path, Format::Json).ok().unwrap(), String::new());
+        let mut logger = logging::test_logger();
 
-                next_execution.reset_display(display.freq.to_seconds());
-            }
-        }
-        manager.recv().unwrap();
-        let results = manager.get_button_state();
-        if results[0] {
-            if let Some(button) = &rules.button0 {
-                std::process::Command::new(&button.script)
-                    .spawn()
-                    .unwrap();
-            }
-        }
+        fs::write(&file, r#"{"output": "none", "rules": [{"name": "Tock", "trigger": {"schedule": {"cron": "bad"}}, "actions": [{"show_text": "tock"}]}]}"#).unwrap();
+        assert_eq!(reload_config(&mut engine, &mut logger, path, Format::Json, &matches), None);
+        assert_eq!(engine.rule_names(), vec!["Tick"]);
 
-        sleep(Duration::from_millis(400))
+        fs::write(&file, r#"{"output": "none", "rules": [{"name": "Tock", "trigger": "startup", "actions": [{"show_text": "tock"}]}]}"#).unwrap();
+        assert_eq!(reload_config(&mut engine, &mut logger, path, Format::Json, &matches), Some(vec![file.clone()]));
+        assert_eq!(engine.rule_names(), vec!["Tock"]);
+        fs::remove_dir_all(&dir).unwrap();
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,96 @@
+use std::collections::HashMap;
+use std::time::Duration;
+use comm_lib::layout::ROWS;
+use serde::Deserialize;
+use crate::config::{validate_led_state, LedName, LedStateConfig};
+use crate::config::rules::LedState;
+
+/// What a script with `"format": "json"` prints
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ScriptOutput {
+    /// Display rows, top to bottom
+    lines: Option<Vec<String>>,
+    leds: Option<HashMap<LedName, LedStateConfig>>,
+    /// Page to switch to
+    page: Option<String>,
+    /// Seconds until the lines and LEDs are cleared, unless the script updates them again
+    ttl: Option<u64>,
+}
+
+/// Parsed and validated script output
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Document {
+    pub lines: Option<Vec<String>>,
+    pub leds: Vec<(usize, LedState)>,
+    pub page: Option<String>,
+    pub ttl: Option<Duration>,
+}
+
+impl Document {
+    /// Parse a script's stdout, the error describes what is wrong with it
+    pub fn parse(stdout: &str) -> Result<Document, String> {
+        let output: ScriptOutput = serde_json::from_str(stdout.trim())
+            .map_err(|err| format!("invalid JSON: {}", err))?;
+
+        let mut errors = vec![];
+        if let Some(lines) = &output.lines {
+            if lines.len() > ROWS {
+                errors.push(format!("{} lines, max is {}", lines.len(), ROWS));
+            }
+        }
+        for state in output.leds.iter().flat_map(|leds| leds.values()) {
+            validate_led_state("leds", state, &mut errors);
+        }
+        if output.page.as_ref().map(|page| page.trim().is_empty()).unwrap_or_default() {
+            errors.push("page is empty".to_owned());
+        }
+        if output.ttl == Some(0) {
+            errors.push("ttl is 0".to_owned());
+        }
+        if !errors.is_empty() {
+            return Err(errors.join(", "));
+        }
+
+        let mut leds: Vec<(usize, LedState)> = output.leds.unwrap_or_default().into_iter()
+            .map(|(led, state)| (led.into(), state.into()))
+            .collect();
+        leds.sort_by_key(|(led, _)| *led);
+        Ok(Document {
+            lines: output.lines,
+            leds,
+            page: output.page,
+            ttl: output.ttl.map(Duration::from_secs),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use comm_lib::{LED_GREEN, LED_RED};
+
+    #[test]
+    fn documents_are_parsed() {
+        let document = Document::parse(r#"
+            {"lines": ["build #123 ok", "tests: 2 failing"], "leds": {"red": "blink", "green": {"pattern": {"steps": "10", "step": "100ms"}}}, "page": "ci", "ttl": 60}
+        "#).unwrap();
+        assert_eq!(document, Document {
+            lines: Some(vec!["build #123 ok".to_owned(), "tests: 2 failing".to_owned()]),
+            leds: vec![(LED_GREEN, LedState::Pattern(vec![true, false], Duration::from_millis(100))), (LED_RED, LedState::Blink)],
+            page: Some("ci".to_owned()),
+            ttl: Some(Duration::from_secs(60)),
+        });
+        assert_eq!(Document::parse("{}").unwrap(), Document { lines: None, leds: vec![], page: None, ttl: None });
+    }
+
+    #[test]
+    fn mistakes_are_described() {
+        assert_eq!(
+            Document::parse(r#"{"lines": ["1", "2", "3", "4", "5"], "leds": {"blue": {"pattern": {"steps": "on"}}}, "page": " ", "ttl": 0}"#),
+            Err("5 lines, max is 4, leds pattern 'on' must be a list of 0s and 1s, page is empty, ttl is 0".to_owned())
+        );
+        assert!(Document::parse("build ok").unwrap_err().starts_with("invalid JSON: "));
+        assert!(Document::parse(r#"{"text": "build ok"}"#).unwrap_err().contains("unknown field `text`"));
+    }
+}
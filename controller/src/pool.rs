@@ -0,0 +1,305 @@
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
+use std::io::Read;
+use std::process::Child;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+use crate::command::{ScriptCommand, Vars};
+use crate::config::rules::Overlap;
+
+/// How often running scripts are checked for exit, timeout or cancellation
+const WAIT_INTERVAL: Duration = Duration::from_millis(10);
+/// Longest time to keep reading stdout after the script exits, anything it started in the background can keep it open
+const OUTPUT_WAIT: Duration = Duration::from_secs(1);
+
+/// Result of running a script
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    /// Script finished, `code` is `None` if it was killed by a signal
+    Exited { code: Option<i32>, stdout: String },
+    /// Script ran for longer than the timeout and was killed
+    TimedOut,
+    /// Script was killed because its task was restarted
+    Cancelled,
+    /// Script couldn't be started
+    Failed(String),
+}
+
+impl Outcome {
+    pub fn success(&self) -> bool {
+        matches!(self, Outcome::Exited { code: Some(0), .. })
+    }
+}
+
+pub type Task<K, E> = Box<dyn FnOnce(&Worker<K, E>) + Send>;
+
+enum Message<K, E> {
+    Effect(K, E),
+    Done(K),
+}
+
+/// Handed to tasks to run scripts and send results back to the main loop
+pub struct Worker<K, E> {
+    key: Option<K>,
+    sender: Sender<Message<K, E>>,
+    cancel: Arc<AtomicBool>,
+    timeout: Option<Duration>,
+}
+
+impl<K: Clone, E> Worker<K, E> {
+    /// Run script to completion, killing it if it times out or the task is cancelled
+    /// If `capture` is set stdout is returned in the outcome
+    pub fn run(&self, command: &ScriptCommand, vars: &Vars, capture: bool) -> Outcome {
+        if self.is_cancelled() {
+            return Outcome::Cancelled;
+        }
+        let result = if capture { command.spawn_captured(vars) } else { command.spawn(vars) };
+        let mut child = match result {
+            Ok(child) => child,
+            Err(err) => return Outcome::Failed(err.to_string())
+        };
+        // read on another thread so a script with lots of output can't fill the pipe and block
+        let (output, chunks) = channel();
+        if let Some(mut stdout) = child.stdout.take() {
+            thread::spawn(move || {
+                let mut buffer = [0; 4096];
+                while let Ok(count @ 1..) = stdout.read(&mut buffer) {
+                    if output.send(buffer[..count].to_vec()).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+
+        let start = Instant::now();
+        loop {
+            match child.try_wait() {
+                Ok(Some(status)) => {
+                    let remaining = self.timeout.map(|timeout| timeout.saturating_sub(start.elapsed())).unwrap_or(OUTPUT_WAIT);
+                    let stdout = collect(&chunks, Instant::now() + remaining.min(OUTPUT_WAIT));
+                    return Outcome::Exited { code: status.code(), stdout: String::from_utf8_lossy(&stdout).into_owned() };
+                }
+                Ok(None) => {}
+                Err(err) => return Outcome::Failed(err.to_string())
+            }
+            let outcome = if self.is_cancelled() {
+                Outcome::Cancelled
+            } else if self.timeout.map(|timeout| start.elapsed() >= timeout).unwrap_or_default() {
+                Outcome::TimedOut
+            } else {
+                sleep(WAIT_INTERVAL);
+                continue;
+            };
+            // the reader is left behind as anything the script started may still have stdout open
+            kill(&mut child);
+            return outcome;
+        }
+    }
+
+    /// Send a result to the main loop, returned by [WorkerPool::poll]
+    pub fn emit(&self, effect: E) {
+        if let Some(key) = &self.key {
+            let _ = self.sender.send(Message::Effect(key.clone(), effect));
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel.load(Ordering::Relaxed)
+    }
+}
+
+/// Output read until stdout is closed or `deadline`
+fn collect(chunks: &Receiver<Vec<u8>>, deadline: Instant) -> Vec<u8> {
+    let mut stdout = vec![];
+    while let Ok(chunk) = chunks.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+        stdout.extend(chunk);
+    }
+    stdout
+}
+
+/// Kill the script and everything it started, which are in the script's process group on unix
+fn kill(child: &mut Child) {
+    #[cfg(unix)]
+    {
+        use nix::sys::signal::{killpg, Signal};
+        use nix::unistd::Pid;
+        let _ = killpg(Pid::from_raw(child.id() as i32), Signal::SIGKILL);
+    }
+    let _ = child.kill();
+    let _ = child.wait();
+}
+
+impl<K, E> Drop for Worker<K, E> {
+    /// Done is sent on drop so the key is freed even if the task panics
+    fn drop(&mut self) {
+        if let Some(key) = self.key.take() {
+            let _ = self.sender.send(Message::Done(key));
+        }
+    }
+}
+
+struct Pending<K, E> {
+    key: K,
+    timeout: Option<Duration>,
+    task: Task<K, E>,
+}
+
+/// Runs tasks on background threads, at most `max_concurrency` at a time and one per key
+pub struct WorkerPool<K, E> {
+    max_concurrency: usize,
+    sender: Sender<Message<K, E>>,
+    receiver: Receiver<Message<K, E>>,
+    /// Cancel flags of running tasks
+    running: HashMap<K, Arc<AtomicBool>>,
+    /// Tasks waiting for a free worker or for the previous task with the same key to finish
+    waiting: VecDeque<Pending<K, E>>,
+}
+
+impl<K: Clone + Eq + Hash + Send + 'static, E: Send + 'static> WorkerPool<K, E> {
+    pub fn new(max_concurrency: usize) -> Self {
+        let (sender, receiver) = channel();
+        WorkerPool { max_concurrency: max_concurrency.max(1), sender, receiver, running: HashMap::new(), waiting: VecDeque::new() }
+    }
+}
+
+impl<K: Clone + Eq + Hash + Send + 'static, E: Send + 'static> WorkerPool<K, E> {
+    /// Queue `task`, `overlap` decides what happens if a task with the same key is already running or waiting
+    /// Scripts run by the task are killed if they run for longer than `timeout`
+    pub fn submit(&mut self, key: K, overlap: Overlap, timeout: Option<Duration>, task: Task<K, E>) {
+        if self.is_busy(&key) {
+            match overlap {
+                Overlap::Ignore => return,
+                // only one run waits, later triggers are dropped until it starts
+                Overlap::Queue if self.waiting.iter().any(|pending| pending.key == key) => return,
+                Overlap::Queue => {}
+                Overlap::Restart => {
+                    if let Some(cancel) = self.running.get(&key) {
+                        cancel.store(true, Ordering::Relaxed);
+                    }
+                    self.waiting.retain(|pending| pending.key != key);
+                }
+            }
+        }
+        self.waiting.push_back(Pending { key, timeout, task });
+        self.start_waiting();
+    }
+
+    /// Effects sent by tasks since the last call with the key of the task that sent them, also starts waiting tasks
+    pub fn poll(&mut self) -> Vec<(K, E)> {
+        let mut effects = vec![];
+        while let Ok(message) = self.receiver.try_recv() {
+            match message {
+                Message::Effect(key, effect) => effects.push((key, effect)),
+                Message::Done(key) => {
+                    self.running.remove(&key);
+                }
+            }
+        }
+        self.start_waiting();
+        effects
+    }
+
+    pub fn is_busy(&self, key: &K) -> bool {
+        self.running.contains_key(key) || self.waiting.iter().any(|pending| &pending.key == key)
+    }
+
+    fn start_waiting(&mut self) {
+        let mut idx = 0;
+        while idx < self.waiting.len() && self.running.len() < self.max_concurrency {
+            if self.running.contains_key(&self.waiting[idx].key) {
+                idx += 1;
+                continue;
+            }
+            if let Some(pending) = self.waiting.remove(idx) {
+                self.start(pending);
+            }
+        }
+    }
+
+    fn start(&mut self, pending: Pending<K, E>) {
+        let cancel = Arc::new(AtomicBool::new(false));
+        self.running.insert(pending.key.clone(), cancel.clone());
+
+        let worker = Worker { key: Some(pending.key), sender: self.sender.clone(), cancel, timeout: pending.timeout };
+        let task = pending.task;
+        thread::spawn(move || task(&worker));
+    }
+}
+
+impl<K, E> Drop for WorkerPool<K, E> {
+    /// Running tasks are cancelled as nothing will receive their effects
+    fn drop(&mut self) {
+        for cancel in self.running.values() {
+            cancel.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+
+    fn wait_until_idle(pool: &mut WorkerPool<u8, ()>, key: u8) {
+        let start = Instant::now();
+        while pool.is_busy(&key) {
+            assert!(start.elapsed() < Duration::from_secs(5), "still busy");
+            pool.poll();
+            sleep(WAIT_INTERVAL);
+        }
+    }
+
+    #[test]
+    fn queue_keeps_one_pending_run() {
+        let mut pool = WorkerPool::new(4);
+        let runs = Arc::new(AtomicUsize::new(0));
+        let release = Arc::new(AtomicBool::new(false));
+        for _ in 0..4 {
+            let (runs, release) = (runs.clone(), release.clone());
+            pool.submit(1, Overlap::Queue, None, Box::new(move |_: &Worker<u8, ()>| {
+                runs.fetch_add(1, Ordering::SeqCst);
+                while !release.load(Ordering::SeqCst) {
+                    sleep(WAIT_INTERVAL);
+                }
+            }));
+        }
+        release.store(true, Ordering::SeqCst);
+        wait_until_idle(&mut pool, 1);
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn background_children_dont_hold_up_the_result() {
+        let command = ScriptCommand::new("sleep 3 & echo done".to_owned(), vec![], HashMap::new(), None, true, None);
+        let (sender, receiver) = channel();
+        let mut pool = WorkerPool::new(1);
+        let start = Instant::now();
+        pool.submit(1, Overlap::Ignore, None, Box::new(move |worker: &Worker<u8, ()>| {
+            let _ = sender.send(worker.run(&command, &[], true));
+        }));
+        let outcome = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(outcome, Outcome::Exited { code: Some(0), stdout: "done\n".to_owned() });
+        assert!(start.elapsed() < Duration::from_secs(2), "{:?}", start.elapsed());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn timeouts_kill_what_the_script_started() {
+        let file = std::env::temp_dir().join(format!("controller-pool-test-{}", std::process::id()));
+        let _ = std::fs::remove_file(&file);
+        let command = ScriptCommand::new("(sleep 1; touch \"$1\") & wait".to_owned(), vec![file.display().to_string()], HashMap::new(), None, true, None);
+        let (sender, receiver) = channel();
+        let mut pool = WorkerPool::new(1);
+        pool.submit(1, Overlap::Ignore, Some(Duration::from_millis(100)), Box::new(move |worker: &Worker<u8, ()>| {
+            let _ = sender.send(worker.run(&command, &[], false));
+        }));
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(5)).unwrap(), Outcome::TimedOut);
+        sleep(Duration::from_millis(1500));
+        assert!(!file.exists());
+    }
+}
@@ -0,0 +1,136 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use tracing::warn;
+
+/// Time to wait after a config file changes before reloading, editors can write a file in several steps
+const SETTLE: Duration = Duration::from_millis(100);
+
+/// Reports when the config should be reloaded, because a config file has changed or SIGHUP was received
+pub struct ReloadWatcher {
+    /// `None` if the files can't be watched, then only SIGHUP reloads
+    watcher: Option<RecommendedWatcher>,
+    /// Directories being watched, files are often replaced instead of written to so they can't be watched directly
+    dirs: Vec<PathBuf>,
+    /// Config files, with the same directory path as the events for them
+    files: Arc<Mutex<Vec<PathBuf>>>,
+    /// When a config file last changed
+    changed: Arc<Mutex<Option<Instant>>>,
+    hangup: Arc<AtomicBool>,
+}
+
+impl ReloadWatcher {
+    /// `files` are the config file and the files it includes
+    pub fn new(files: Vec<PathBuf>) -> io::Result<Self> {
+        let hangup = Arc::new(AtomicBool::new(false));
+        #[cfg(unix)]
+        signal_hook::flag::register(signal_hook::consts::SIGHUP, hangup.clone())?;
+        let changed = Arc::new(Mutex::new(None));
+        let watched = Arc::new(Mutex::new(vec![]));
+        let handler = {
+            let changed = changed.clone();
+            let watched = watched.clone();
+            move |event: notify::Result<Event>| match event {
+                Ok(event) if !matches!(event.kind, EventKind::Access(_)) => {
+                    let watched = watched.lock().unwrap();
+                    if event.paths.iter().any(|path| watched.contains(path)) {
+                        *changed.lock().unwrap() = Some(Instant::now());
+                    }
+                }
+                Ok(_) => {}
+                Err(err) => warn!("Error watching config files: {}", err),
+            }
+        };
+        let watcher = match notify::recommended_watcher(handler) {
+            Ok(watcher) => Some(watcher),
+            Err(err) => {
+                warn!("Config files can't be watched, it will only be reloaded on SIGHUP: {}", err);
+                None
+            }
+        };
+        let mut reload = ReloadWatcher { watcher, dirs: vec![], files: watched, changed, hangup };
+        reload.watch(files);
+        Ok(reload)
+    }
+}
+
+impl ReloadWatcher {
+    /// Watch these files instead, after the includes have changed
+    pub fn watch(&mut self, files: Vec<PathBuf>) {
+        let Some(watcher) = self.watcher.as_mut() else {
+            return;
+        };
+        let files = files.iter().map(|file| event_path(file)).collect::<Vec<PathBuf>>();
+        let mut dirs = files.iter().filter_map(|file| file.parent()).map(Path::to_path_buf).collect::<Vec<PathBuf>>();
+        dirs.sort();
+        dirs.dedup();
+        for dir in self.dirs.iter().filter(|dir| !dirs.contains(dir)) {
+            // fails if the directory has been deleted, it's no longer watched either way
+            let _ = watcher.unwatch(dir);
+        }
+        for dir in dirs.iter().filter(|dir| !self.dirs.contains(dir)) {
+            if let Err(err) = watcher.watch(dir, RecursiveMode::NonRecursive) {
+                warn!("Unable to watch {} for config changes: {}", dir.display(), err);
+            }
+        }
+        self.dirs = dirs;
+        *self.files.lock().unwrap() = files;
+    }
+
+    pub fn requested(&mut self) -> bool {
+        let hangup = self.hangup.swap(false, Ordering::Relaxed);
+        let mut changed = self.changed.lock().unwrap();
+        let settled = changed.is_some_and(|at| at.elapsed() >= SETTLE);
+        if settled || hangup {
+            *changed = None;
+        }
+        settled || hangup
+    }
+}
+
+/// `file` with its directory made absolute and with symlinks resolved, as it is in events
+fn event_path(file: &Path) -> PathBuf {
+    let dir = match file.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new(".")
+    };
+    match (fs::canonicalize(dir), file.file_name()) {
+        (Ok(dir), Some(name)) => dir.join(name),
+        _ => file.to_path_buf()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread::sleep;
+
+    #[test]
+    fn replaced_files_are_reported() {
+        let dir = std::env::temp_dir().join(format!("controller-reload-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("config.json");
+        fs::write(&file, "{}").unwrap();
+        let mut reload = ReloadWatcher::new(vec![file.clone()]).unwrap();
+
+        fs::write(dir.join("other.json"), "{}").unwrap();
+        sleep(SETTLE * 3);
+        assert!(!reload.requested());
+
+        // the way editors save, written to another file and renamed over the config
+        fs::write(dir.join("config.json.tmp"), "{\"rules\": []}").unwrap();
+        fs::rename(dir.join("config.json.tmp"), &file).unwrap();
+        sleep(SETTLE * 3);
+        assert!(reload.requested());
+        assert!(!reload.requested());
+
+        fs::write(&file, "{}").unwrap();
+        sleep(SETTLE * 3);
+        assert!(reload.requested());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,62 @@
+use std::time::Instant;
+use chrono::{Local, NaiveDateTime};
+
+/// Source of time for the scheduler so it can be tested without waiting
+pub trait Clock {
+    /// Monotonic time, used for intervals and deadlines
+    fn now(&self) -> Instant;
+    /// Local wall clock time, used for cron expressions
+    fn local(&self) -> NaiveDateTime;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn local(&self) -> NaiveDateTime {
+        Local::now().naive_local()
+    }
+}
+
+#[cfg(test)]
+pub use fake::FakeClock;
+
+#[cfg(test)]
+mod fake {
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use std::time::{Duration, Instant};
+    use chrono::NaiveDateTime;
+    use crate::scheduler::clock::Clock;
+
+    /// Clock that only moves when told to, clones share the same time
+    #[derive(Clone)]
+    pub struct FakeClock {
+        start: Instant,
+        start_local: NaiveDateTime,
+        elapsed: Rc<Cell<Duration>>,
+    }
+
+    impl FakeClock {
+        pub fn new(start_local: NaiveDateTime) -> Self {
+            FakeClock { start: Instant::now(), start_local, elapsed: Rc::new(Cell::new(Duration::ZERO)) }
+        }
+
+        pub fn advance(&self, duration: Duration) {
+            self.elapsed.set(self.elapsed.get() + duration);
+        }
+    }
+
+    impl Clock for FakeClock {
+        fn now(&self) -> Instant {
+            self.start + self.elapsed.get()
+        }
+
+        fn local(&self) -> NaiveDateTime {
+            self.start_local + chrono::Duration::from_std(self.elapsed.get()).unwrap()
+        }
+    }
+}
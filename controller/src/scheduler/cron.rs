@@ -0,0 +1,152 @@
+use std::fmt::{Display, Formatter};
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime, NaiveTime, Timelike};
+
+const MONTH_NAMES: [&str; 12] = ["jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec"];
+pub(crate) const DAY_NAMES: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+/// Give up looking for a matching time after this many years, i.e. for `0 0 30 2 *`
+const MAX_SEARCH_YEARS: i32 = 5;
+
+/// Standard 5 field cron expression: minute hour day-of-month month day-of-week
+///
+/// Supports `*`, `a`, `a-b`, `*/n`, `a-b/n`, lists (`a,b`), month and day names (`jan`, `mon`)
+/// and `@hourly`, `@daily`, `@weekly`, `@monthly` and `@yearly`
+/// As with cron if both day fields are restricted a time matches if either matches
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CronExpr {
+    source: String,
+    minutes: Vec<bool>,
+    hours: Vec<bool>,
+    days_of_month: Vec<bool>,
+    months: Vec<bool>,
+    days_of_week: Vec<bool>,
+    dom_restricted: bool,
+    dow_restricted: bool,
+}
+
+impl CronExpr {
+    pub fn parse(text: &str) -> Result<CronExpr, String> {
+        let expanded = match text.trim() {
+            "@hourly" => "0 * * * *",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            other => other
+        };
+        let fields = expanded.split_whitespace().collect::<Vec<&str>>();
+        if fields.len() != 5 {
+            return Err(format!("expected 5 fields (minute hour day month weekday), found {}", fields.len()));
+        }
+        let mut days_of_week = parse_field(fields[4], "weekday", 0, 7, &DAY_NAMES)?;
+        // 7 is also Sunday
+        if days_of_week[7] {
+            days_of_week[0] = true;
+        }
+        days_of_week.truncate(7);
+        Ok(CronExpr {
+            source: text.trim().to_owned(),
+            minutes: parse_field(fields[0], "minute", 0, 59, &[])?,
+            hours: parse_field(fields[1], "hour", 0, 23, &[])?,
+            days_of_month: parse_field(fields[2], "day", 1, 31, &[])?,
+            months: parse_field(fields[3], "month", 1, 12, &MONTH_NAMES)?,
+            days_of_week,
+            dom_restricted: !fields[2].starts_with('*'),
+            dow_restricted: !fields[4].starts_with('*'),
+        })
+    }
+}
+
+impl CronExpr {
+    /// First matching time strictly after `after`, to the minute
+    pub fn next_after(&self, after: NaiveDateTime) -> Option<NaiveDateTime> {
+        let start = after.date().and_hms_opt(after.hour(), after.minute(), 0)? + Duration::minutes(1);
+        let limit = start.year() + MAX_SEARCH_YEARS;
+        let mut time = start;
+        while time.year() <= limit {
+            if !self.months[time.month() as usize] {
+                let (year, month) = if time.month() == 12 { (time.year() + 1, 1) } else { (time.year(), time.month() + 1) };
+                time = NaiveDate::from_ymd_opt(year, month, 1)?.and_time(NaiveTime::MIN);
+                continue;
+            }
+            if !self.day_matches(time.date()) {
+                time = time.date().succ_opt()?.and_time(NaiveTime::MIN);
+                continue;
+            }
+            if !self.hours[time.hour() as usize] {
+                time = time.date().and_hms_opt(time.hour(), 0, 0)? + Duration::hours(1);
+                continue;
+            }
+            if !self.minutes[time.minute() as usize] {
+                time += Duration::minutes(1);
+                continue;
+            }
+            return Some(time);
+        }
+        None
+    }
+
+    fn day_matches(&self, date: NaiveDate) -> bool {
+        let dom = self.days_of_month[date.day() as usize];
+        let dow = self.days_of_week[date.weekday().num_days_from_sunday() as usize];
+        match (self.dom_restricted, self.dow_restricted) {
+            (true, true) => dom || dow,
+            (true, false) => dom,
+            (false, true) => dow,
+            (false, false) => true,
+        }
+    }
+}
+
+impl Display for CronExpr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+/// Returns a list indexed by value (0..=max) of whether the value is allowed
+fn parse_field(text: &str, name: &str, min: usize, max: usize, names: &[&str]) -> Result<Vec<bool>, String> {
+    let mut allowed = vec![false; max + 1];
+    for part in text.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => {
+                let step = step.parse::<usize>()
+                    .map_err(|_| format!("invalid {} step '{}'", name, step))?;
+                if step == 0 {
+                    return Err(format!("{} step must be at least 1", name));
+                }
+                (range, step)
+            }
+            None => (part, 1)
+        };
+        let (start, end) = if range == "*" {
+            (min, max)
+        } else if let Some((start, end)) = range.split_once('-') {
+            (parse_value(start, name, min, max, names)?, parse_value(end, name, min, max, names)?)
+        } else {
+            let value = parse_value(range, name, min, max, names)?;
+            // `5/10` means from 5 to the end in steps of 10
+            (value, if part.contains('/') { max } else { value })
+        };
+        if start > end {
+            return Err(format!("{} range '{}' is backwards", name, range));
+        }
+        for value in (start..=end).step_by(step) {
+            allowed[value] = true;
+        }
+    }
+    Ok(allowed)
+}
+
+fn parse_value(text: &str, name: &str, min: usize, max: usize, names: &[&str]) -> Result<usize, String> {
+    let lower = text.to_ascii_lowercase();
+    if let Some(idx) = names.iter().position(|candidate| *candidate == lower) {
+        // month names start at 1, day names at 0
+        return Ok(idx + min);
+    }
+    let value = text.parse::<usize>()
+        .map_err(|_| format!("invalid {} '{}'", name, text))?;
+    if value < min || value > max {
+        return Err(format!("{} {} is out of range, must be {} - {}", name, value, min, max));
+    }
+    Ok(value)
+}
@@ -0,0 +1,377 @@
+pub mod clock;
+pub mod cron;
+pub mod window;
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::time::{Duration, Instant};
+use crate::scheduler::clock::Clock;
+use crate::scheduler::cron::CronExpr;
+use crate::scheduler::window::Window;
+
+/// When a job runs
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Schedule {
+    Interval(Duration),
+    Cron(CronExpr),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Job {
+    pub schedule: Schedule,
+    /// For intervals the delay before the first run, for cron added to every run
+    pub offset: Duration,
+    /// Max random delay added to each run
+    pub jitter: Duration,
+    /// Also run as soon as the job is added
+    pub run_at_startup: bool,
+    /// Runs outside this window are skipped
+    pub window: Window,
+}
+
+impl Job {
+    pub fn new(schedule: Schedule, offset: Duration, jitter: Duration, run_at_startup: bool, window: Window) -> Self {
+        Job { schedule, offset, jitter, run_at_startup, window }
+    }
+}
+
+struct JobState {
+    job: Job,
+    /// Next run without jitter, intervals are based on this so they don't drift
+    base: Option<Instant>,
+    /// Next run including jitter
+    next: Option<Instant>,
+}
+
+/// Keeps track of when jobs, identified by `K`, should next run
+pub struct Scheduler<K> {
+    clock: Box<dyn Clock>,
+    jobs: HashMap<K, JobState>,
+    rng: fastrand::Rng,
+}
+
+impl<K: Clone + Eq + Hash> Scheduler<K> {
+    pub fn new(clock: Box<dyn Clock>) -> Self {
+        Scheduler { clock, jobs: HashMap::new(), rng: fastrand::Rng::new() }
+    }
+
+    #[cfg(test)]
+    fn with_seed(clock: Box<dyn Clock>, seed: u64) -> Self {
+        Scheduler { clock, jobs: HashMap::new(), rng: fastrand::Rng::with_seed(seed) }
+    }
+}
+
+impl<K: Clone + Eq + Hash> Scheduler<K> {
+    /// Add or replace job
+    pub fn add(&mut self, key: K, job: Job) {
+        let now = self.clock.now();
+        let mut state = JobState { job, base: None, next: None };
+        if state.job.run_at_startup {
+            state.base = Some(now);
+            state.next = Some(now);
+        } else {
+            self.reschedule(&mut state, now, true);
+        }
+        self.jobs.insert(key, state);
+    }
+
+    /// Stop running job, returns `false` if there was no such job
+    pub fn remove(&mut self, key: &K) -> bool {
+        self.jobs.remove(key).is_some()
+    }
+
+    /// Give jobs new keys, from old to new, keeping when they next run. Jobs without a new key are removed
+    pub fn rekey(&mut self, keys: &HashMap<K, K>) {
+        let mut jobs = std::mem::take(&mut self.jobs);
+        self.jobs = keys.iter()
+            .filter_map(|(old, new)| jobs.remove(old).map(|state| (new.clone(), state)))
+            .collect();
+    }
+
+    /// Returns the jobs that should run now, in the order they were due, and schedules their next run
+    ///
+    /// Jobs that are due outside their window are rescheduled without being returned
+    pub fn due(&mut self) -> Vec<K> {
+        let now = self.clock.now();
+        let local = self.clock.local();
+        let mut due = self.jobs.iter()
+            .filter_map(|(key, state)| state.next.filter(|next| *next <= now).map(|next| (next, key.clone())))
+            .collect::<Vec<(Instant, K)>>();
+        due.sort_by_key(|(next, _)| *next);
+
+        let mut jobs = std::mem::take(&mut self.jobs);
+        for (_, key) in &due {
+            if let Some(state) = jobs.get_mut(key) {
+                self.reschedule(state, now, false);
+            }
+        }
+        self.jobs = jobs;
+
+        due.into_iter()
+            .map(|(_, key)| key)
+            .filter(|key| self.jobs.get(key).map(|state| state.job.window.contains(local)).unwrap_or_default())
+            .collect()
+    }
+
+    /// Time until job is next due, zero if it's due and `None` if there is no such job
+    /// or it will never run again
+    /// The run may be skipped if it's outside the job's window
+    pub fn next_run(&self, key: &K) -> Option<Duration> {
+        let now = self.clock.now();
+        self.jobs.get(key)
+            .and_then(|state| state.next)
+            .map(|next| next.saturating_duration_since(now))
+    }
+
+    /// Time until any job is due
+    pub fn time_until_next(&self) -> Option<Duration> {
+        let now = self.clock.now();
+        self.jobs.values()
+            .filter_map(|state| state.next)
+            .min()
+            .map(|next| next.saturating_duration_since(now))
+    }
+
+    fn reschedule(&mut self, state: &mut JobState, now: Instant, first: bool) {
+        let base = match &state.job.schedule {
+            Schedule::Interval(interval) => {
+                let interval = (*interval).max(Duration::from_millis(1));
+                let from = match state.base {
+                    Some(base) if !first => base,
+                    _ => now + state.job.offset,
+                };
+                let mut next = from + interval;
+                if next <= now {
+                    // fell behind (i.e. a slow script), skip the missed runs
+                    next = now + interval;
+                }
+                Some(next)
+            }
+            Schedule::Cron(cron) => {
+                let local = self.clock.local();
+                cron.next_after(local)
+                    .and_then(|next| (next - local).to_std().ok())
+                    .map(|wait| now + wait + state.job.offset)
+            }
+        };
+        state.base = base;
+        state.next = base.map(|base| base + self.jitter(state.job.jitter));
+    }
+
+    fn jitter(&mut self, max: Duration) -> Duration {
+        if max.is_zero() {
+            Duration::ZERO
+        } else {
+            Duration::from_millis(self.rng.u64(0..=max.as_millis() as u64))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{NaiveDate, NaiveDateTime};
+    use super::*;
+    use crate::scheduler::clock::FakeClock;
+    use crate::scheduler::window::{parse_days, parse_hours};
+
+    fn start() -> NaiveDateTime {
+        // a Monday
+        NaiveDate::from_ymd_opt(2024, 1, 1).unwrap().and_hms_opt(9, 30, 15).unwrap()
+    }
+
+    fn interval(secs: u64) -> Job {
+        Job::new(Schedule::Interval(Duration::from_secs(secs)), Duration::ZERO, Duration::ZERO, false, Window::default())
+    }
+
+    fn setup() -> (FakeClock, Scheduler<&'static str>) {
+        let clock = FakeClock::new(start());
+        let scheduler = Scheduler::with_seed(Box::new(clock.clone()), 1);
+        (clock, scheduler)
+    }
+
+    #[test]
+    fn interval_runs_after_each_period() {
+        let (clock, mut scheduler) = setup();
+        scheduler.add("a", interval(10));
+
+        assert!(scheduler.due().is_empty());
+        clock.advance(Duration::from_secs(9));
+        assert!(scheduler.due().is_empty());
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(scheduler.due(), vec!["a"]);
+        assert!(scheduler.due().is_empty());
+        clock.advance(Duration::from_secs(10));
+        assert_eq!(scheduler.due(), vec!["a"]);
+    }
+
+    #[test]
+    fn interval_does_not_drift_or_repeat_missed_runs() {
+        let (clock, mut scheduler) = setup();
+        scheduler.add("a", interval(10));
+
+        clock.advance(Duration::from_secs(12));
+        assert_eq!(scheduler.due(), vec!["a"]);
+        assert_eq!(scheduler.next_run(&"a"), Some(Duration::from_secs(8)));
+
+        clock.advance(Duration::from_secs(45));
+        assert_eq!(scheduler.due(), vec!["a"]);
+        assert!(scheduler.due().is_empty());
+        assert_eq!(scheduler.next_run(&"a"), Some(Duration::from_secs(10)));
+    }
+
+    #[test]
+    fn run_at_startup_is_due_immediately() {
+        let (clock, mut scheduler) = setup();
+        let mut job = interval(60);
+        job.run_at_startup = true;
+        scheduler.add("a", job);
+
+        assert_eq!(scheduler.due(), vec!["a"]);
+        assert_eq!(scheduler.next_run(&"a"), Some(Duration::from_secs(60)));
+        clock.advance(Duration::from_secs(60));
+        assert_eq!(scheduler.due(), vec!["a"]);
+    }
+
+    #[test]
+    fn offset_delays_interval() {
+        let (clock, mut scheduler) = setup();
+        let mut job = interval(10);
+        job.offset = Duration::from_secs(5);
+        scheduler.add("a", job);
+
+        clock.advance(Duration::from_secs(10));
+        assert!(scheduler.due().is_empty());
+        clock.advance(Duration::from_secs(5));
+        assert_eq!(scheduler.due(), vec!["a"]);
+        clock.advance(Duration::from_secs(10));
+        assert_eq!(scheduler.due(), vec!["a"]);
+    }
+
+    #[test]
+    fn jitter_stays_within_limit() {
+        let (clock, mut scheduler) = setup();
+        let mut job = interval(10);
+        job.jitter = Duration::from_secs(3);
+        scheduler.add("a", job);
+
+        let mut elapsed = Duration::ZERO;
+        for run in 1..=20 {
+            let wait = scheduler.next_run(&"a").unwrap();
+            elapsed += wait;
+            clock.advance(wait);
+            assert_eq!(scheduler.due(), vec!["a"]);
+            // jitter is applied to each run but doesn't accumulate
+            let base = Duration::from_secs(10 * run);
+            assert!(elapsed >= base && elapsed <= base + Duration::from_secs(3), "{:?}", elapsed);
+        }
+    }
+
+    #[test]
+    fn cron_uses_wall_clock() {
+        let (clock, mut scheduler) = setup();
+        let cron = CronExpr::parse("0 10 * * mon-fri").unwrap();
+        scheduler.add("a", Job::new(Schedule::Cron(cron), Duration::ZERO, Duration::ZERO, false, Window::default()));
+
+        // 09:30:15 -> 10:00:00
+        assert_eq!(scheduler.next_run(&"a"), Some(Duration::from_secs(29 * 60 + 45)));
+        clock.advance(Duration::from_secs(29 * 60 + 45));
+        assert_eq!(scheduler.due(), vec!["a"]);
+        assert_eq!(scheduler.next_run(&"a"), Some(Duration::from_secs(24 * 60 * 60)));
+    }
+
+    #[test]
+    fn multiple_jobs_are_returned_in_order() {
+        let (clock, mut scheduler) = setup();
+        scheduler.add("slow", interval(30));
+        scheduler.add("fast", interval(20));
+        scheduler.add("never", interval(3600));
+
+        assert_eq!(scheduler.time_until_next(), Some(Duration::from_secs(20)));
+        clock.advance(Duration::from_secs(40));
+        assert_eq!(scheduler.due(), vec!["fast", "slow"]);
+        assert!(scheduler.remove(&"fast"));
+        assert_eq!(scheduler.next_run(&"fast"), None);
+        assert_eq!(scheduler.time_until_next(), Some(Duration::from_secs(20)));
+    }
+
+    #[test]
+    fn rekey_keeps_next_run() {
+        let (clock, mut scheduler) = setup();
+        scheduler.add("a", interval(10));
+        scheduler.add("b", interval(10));
+
+        clock.advance(Duration::from_secs(4));
+        scheduler.rekey(&HashMap::from([("a", "c")]));
+        assert_eq!(scheduler.next_run(&"c"), Some(Duration::from_secs(6)));
+        assert_eq!(scheduler.next_run(&"a"), None);
+        assert_eq!(scheduler.next_run(&"b"), None);
+        clock.advance(Duration::from_secs(6));
+        assert_eq!(scheduler.due(), vec!["c"]);
+    }
+
+    #[test]
+    fn runs_outside_window_are_skipped() {
+        let (clock, mut scheduler) = setup();
+        let mut job = interval(15 * 60);
+        job.run_at_startup = true;
+        job.window = Window::new(Some(parse_hours("10:00-11:00").unwrap()), parse_days(&["weekdays".to_owned()]).unwrap());
+        scheduler.add("a", job);
+
+        // 09:30, 09:45
+        assert!(scheduler.due().is_empty());
+        clock.advance(Duration::from_secs(15 * 60));
+        assert!(scheduler.due().is_empty());
+        // 10:00 - 10:45
+        for _ in 0..4 {
+            clock.advance(Duration::from_secs(15 * 60));
+            assert_eq!(scheduler.due(), vec!["a"]);
+        }
+        // 11:00
+        clock.advance(Duration::from_secs(15 * 60));
+        assert!(scheduler.due().is_empty());
+    }
+
+    #[test]
+    fn window_parsing() {
+        let at = |d: u32, h: u32, m: u32| NaiveDate::from_ymd_opt(2024, 1, d).unwrap().and_hms_opt(h, m, 0).unwrap();
+        let days = |list: &[&str]| parse_days(&list.iter().map(|day| day.to_string()).collect::<Vec<String>>());
+
+        let overnight = Window::new(Some(parse_hours("22:00-06:00").unwrap()), [true; 7]);
+        assert!(overnight.contains(at(1, 23, 0)));
+        assert!(overnight.contains(at(1, 5, 59)));
+        assert!(!overnight.contains(at(1, 6, 0)));
+
+        // the 6th is a Saturday
+        let weekend = Window::new(None, days(&["fri-sun"]).unwrap());
+        assert!(weekend.contains(at(5, 12, 0)));
+        assert!(weekend.contains(at(7, 12, 0)));
+        assert!(!weekend.contains(at(8, 12, 0)));
+
+        assert_eq!(days(&["Monday", "wed"]).unwrap(), [false, true, false, true, false, false, false]);
+        assert!(days(&["someday"]).is_err());
+        assert!(days(&[]).is_err());
+        assert!(parse_hours("9-17").is_err());
+        assert!(parse_hours("25:00-26:00").is_err());
+        assert!(parse_hours("09:00-09:00").is_err());
+    }
+
+    #[test]
+    fn cron_parsing() {
+        let next = |expr: &str| CronExpr::parse(expr).unwrap().next_after(start()).unwrap();
+        let at = |d: u32, h: u32, m: u32| NaiveDate::from_ymd_opt(2024, 1, d).unwrap().and_hms_opt(h, m, 0).unwrap();
+
+        assert_eq!(next("* * * * *"), at(1, 9, 31));
+        assert_eq!(next("*/15 * * * *"), at(1, 9, 45));
+        assert_eq!(next("0 7 * * 1-5"), at(2, 7, 0));
+        assert_eq!(next("0 7 * * sat"), at(6, 7, 0));
+        assert_eq!(next("0 0 15 * *"), at(15, 0, 0));
+        assert_eq!(next("@daily"), at(2, 0, 0));
+        assert_eq!(next("0 9-17/4 * * *"), at(1, 13, 0));
+        assert_eq!(next("30 9,12 * * 7"), at(7, 9, 30));
+        assert!(CronExpr::parse("0 30 31 2 *").is_err());
+        assert!(CronExpr::parse("0 0 31 2 *").unwrap().next_after(start()).is_none());
+        assert!(CronExpr::parse("* * *").is_err());
+        assert!(CronExpr::parse("60 * * * *").is_err());
+        assert!(CronExpr::parse("5-1 * * * *").is_err());
+    }
+}
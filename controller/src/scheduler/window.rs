@@ -0,0 +1,95 @@
+use chrono::{Datelike, NaiveDateTime, NaiveTime};
+use crate::scheduler::cron::DAY_NAMES;
+
+/// Times a job is allowed to run, runs outside the window are skipped
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Window {
+    /// Start (inclusive) and end (exclusive), if start is after end the window crosses midnight
+    hours: Option<(NaiveTime, NaiveTime)>,
+    /// Indexed from Sunday
+    days: [bool; 7],
+}
+
+impl Window {
+    pub fn new(hours: Option<(NaiveTime, NaiveTime)>, days: [bool; 7]) -> Self {
+        Window { hours, days }
+    }
+}
+
+impl Default for Window {
+    fn default() -> Self {
+        Window { hours: None, days: [true; 7] }
+    }
+}
+
+impl Window {
+    pub fn contains(&self, time: NaiveDateTime) -> bool {
+        if !self.days[time.weekday().num_days_from_sunday() as usize] {
+            return false;
+        }
+        match self.hours {
+            Some((start, end)) if start <= end => time.time() >= start && time.time() < end,
+            Some((start, end)) => time.time() >= start || time.time() < end,
+            None => true
+        }
+    }
+}
+
+/// Parse `HH:MM-HH:MM`, i.e. `09:00-17:30` or `22:00-06:00`
+pub fn parse_hours(text: &str) -> Result<(NaiveTime, NaiveTime), String> {
+    let (start, end) = text.split_once('-')
+        .ok_or_else(|| format!("'{}' is not a valid time range, expected HH:MM-HH:MM", text))?;
+    let parse = |time: &str| NaiveTime::parse_from_str(time.trim(), "%H:%M")
+        .map_err(|_| format!("'{}' is not a valid time, expected HH:MM", time.trim()));
+    let (start, end) = (parse(start)?, parse(end)?);
+    if start == end {
+        return Err(format!("'{}' is empty, start and end are the same", text));
+    }
+    Ok((start, end))
+}
+
+/// Parse a list of days, each entry is a day (`mon`), a range (`mon-fri`), `weekdays` or `weekends`
+pub fn parse_days(entries: &[String]) -> Result<[bool; 7], String> {
+    if entries.is_empty() {
+        return Err("no days listed".to_owned());
+    }
+    let mut days = [false; 7];
+    for entry in entries {
+        let entry = entry.trim().to_ascii_lowercase();
+        match entry.as_str() {
+            "weekdays" => days[1..=5].iter_mut().for_each(|day| *day = true),
+            "weekends" => {
+                days[0] = true;
+                days[6] = true;
+            }
+            _ => {
+                let (start, end) = match entry.split_once('-') {
+                    Some((start, end)) => (parse_day(start)?, parse_day(end)?),
+                    None => {
+                        let day = parse_day(&entry)?;
+                        (day, day)
+                    }
+                };
+                // ranges can wrap, i.e. fri-mon
+                let mut day = start;
+                loop {
+                    days[day] = true;
+                    if day == end {
+                        break;
+                    }
+                    day = (day + 1) % 7;
+                }
+            }
+        }
+    }
+    Ok(days)
+}
+
+fn parse_day(text: &str) -> Result<usize, String> {
+    const FULL_NAMES: [&str; 7] = ["sunday", "monday", "tuesday", "wednesday", "thursday", "friday", "saturday"];
+    let text = text.trim();
+    DAY_NAMES.iter()
+        .zip(FULL_NAMES.iter())
+        .position(|(short, full)| text == *short || text == *full)
+        .ok_or_else(|| format!("'{}' is not a day, expected mon, tue, wed, thu, fri, sat or sun", text))
+}
@@ -0,0 +1,225 @@
+use std::ops::Range;
+use std::time::{Duration, Instant};
+use comm_lib::layout::{Align, COLUMNS, fit, marquee, ROWS};
+use comm_lib::manager::Update;
+use comm_lib::TEXT_MAX_LEN;
+
+/// What is on the display
+enum Content {
+    /// Sent as is, the device wraps it across the rows
+    Text(String),
+    Rows(Box<[Row; ROWS]>),
+}
+
+#[derive(Clone, Debug)]
+struct Row {
+    text: String,
+    align: Align,
+    /// Rows longer than the display scroll by one character this often, otherwise they are cut off
+    scroll: Option<Duration>,
+    /// When the row was set, scrolling starts from here
+    since: Instant,
+}
+
+impl Row {
+    fn blank(now: Instant) -> Self {
+        Row { text: String::new(), align: Align::Left, scroll: None, since: now }
+    }
+
+    fn frame(&self, now: Instant) -> String {
+        match self.scroll {
+            Some(step) if self.text.chars().count() > COLUMNS => {
+                let elapsed = now.saturating_duration_since(self.since);
+                marquee(&self.text, COLUMNS, (elapsed.as_millis() / step.as_millis().max(1)) as usize)
+            }
+            _ => fit(&self.text, COLUMNS, self.align),
+        }
+    }
+}
+
+/// Works out what to send to the device, scrolling rows is done here and only rows that have
+/// changed are sent
+pub struct Screen {
+    content: Content,
+    /// When rows set by a script with a `ttl` should be cleared
+    expires: [Option<Instant>; ROWS],
+    /// Shown in the bottom right corner over the content
+    indicator: Option<String>,
+    /// Last text sent to the device, if it was sent as a whole
+    sent_text: Option<String>,
+    /// Last text sent for each row, `None` if it isn't known
+    sent_rows: [Option<String>; ROWS],
+}
+
+impl Screen {
+    pub fn new() -> Self {
+        // nothing is sent until something is set so the device's startup message stays
+        Screen {
+            content: Content::Text(String::new()),
+            expires: [None; ROWS],
+            indicator: None,
+            sent_text: Some(String::new()),
+            sent_rows: Default::default(),
+        }
+    }
+}
+
+impl Screen {
+    /// Replace the whole screen
+    pub fn set_text(&mut self, text: String) {
+        self.content = Content::Text(text);
+        self.expires = [None; ROWS];
+    }
+
+    /// Set the rows in `region`, `lines` are put in order from the top of the region and any rows left
+    /// over are cleared. Setting a row to what it already is doesn't restart scrolling
+    pub fn set_rows(&mut self, region: Range<usize>, lines: Vec<String>, align: Align, scroll: Option<Duration>, now: Instant) {
+        if let Content::Text(_) = self.content {
+            self.content = Content::Rows(Box::new(std::array::from_fn(|_| Row::blank(now))));
+        }
+        self.expires[region.clone()].fill(None);
+        if let Content::Rows(rows) = &mut self.content {
+            let mut lines = lines.into_iter();
+            for row in &mut rows[region] {
+                let text = lines.next().unwrap_or_default();
+                if row.text != text || row.align != align || row.scroll != scroll {
+                    *row = Row { text, align, scroll, since: now };
+                }
+            }
+        }
+    }
+
+    /// Clear `rows` at `time` unless they are set again before then
+    pub fn expire(&mut self, rows: Range<usize>, time: Instant) {
+        self.expires[rows].fill(Some(time));
+    }
+
+    pub fn set_indicator(&mut self, indicator: Option<String>) {
+        self.indicator = indicator;
+    }
+
+    /// The device is showing something else, the next update sends everything
+    pub fn invalidate(&mut self) {
+        self.sent_text = None;
+        self.sent_rows = Default::default();
+    }
+
+    /// Updates needed to bring the device up to date
+    pub fn update(&mut self, now: Instant) -> Vec<Update> {
+        for row in 0..ROWS {
+            if self.expires[row].map(|expires| expires <= now).unwrap_or_default() {
+                self.set_rows(row..row + 1, vec![], Align::Left, None, now);
+            }
+        }
+        match &self.content {
+            Content::Text(text) => {
+                let text = match &self.indicator {
+                    Some(indicator) => overlay(&fit(text, TEXT_MAX_LEN, Align::Left), indicator),
+                    None => text.clone()
+                };
+                if self.sent_text.as_ref() == Some(&text) {
+                    return vec![];
+                }
+                self.sent_text = Some(text.clone());
+                self.sent_rows = Default::default();
+                vec![Update::Text(text)]
+            }
+            Content::Rows(rows) => {
+                let mut frames = rows.iter().map(|row| row.frame(now)).collect::<Vec<String>>();
+                if let Some(indicator) = &self.indicator {
+                    frames[ROWS - 1] = overlay(&frames[ROWS - 1], indicator);
+                }
+                if self.sent_rows.iter().any(|row| row.is_none()) {
+                    self.sent_text = None;
+                    self.sent_rows = std::array::from_fn(|idx| Some(frames[idx].clone()));
+                    return vec![Update::Text(frames.concat())];
+                }
+                let mut updates = vec![];
+                for (idx, frame) in frames.into_iter().enumerate() {
+                    if self.sent_rows[idx].as_ref() != Some(&frame) {
+                        self.sent_rows[idx] = Some(frame.clone());
+                        updates.push(Update::Row(idx, frame));
+                    }
+                }
+                updates
+            }
+        }
+    }
+}
+
+/// Replace the end of `text` with `indicator`
+fn overlay(text: &str, indicator: &str) -> String {
+    let keep = text.chars().count().saturating_sub(indicator.chars().count());
+    text.chars().take(keep).chain(indicator.chars()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MS: Duration = Duration::from_millis(1);
+
+    /// Updates as the row they replace, `None` for the whole screen
+    fn sent(updates: Vec<Update>) -> Vec<(Option<usize>, String)> {
+        updates.into_iter().map(|update| match update {
+            Update::Text(text) => (None, text),
+            Update::Row(row, text) => (Some(row), text),
+            update => panic!("unexpected update {:?}", update)
+        }).collect()
+    }
+
+    fn lines(lines: &[&str]) -> Vec<String> {
+        lines.iter().map(|line| line.to_string()).collect()
+    }
+
+    fn row(text: &str) -> String {
+        fit(text, COLUMNS, Align::Left)
+    }
+
+    #[test]
+    fn only_changed_rows_are_sent() {
+        let now = Instant::now();
+        let mut screen = Screen::new();
+        assert_eq!(sent(screen.update(now)), vec![]);
+
+        screen.set_rows(0..ROWS, lines(&["clock", "ci", "ok", "news"]), Align::Left, None, now);
+        assert_eq!(sent(screen.update(now)), vec![(None, [row("clock"), row("ci"), row("ok"), row("news")].concat())]);
+        screen.set_rows(1..3, lines(&["ci", "failed"]), Align::Left, None, now);
+        assert_eq!(sent(screen.update(now)), vec![(Some(2), row("failed"))]);
+        screen.set_rows(3..4, lines(&["news"]), Align::Left, None, now);
+        assert_eq!(sent(screen.update(now)), vec![]);
+
+        screen.invalidate();
+        assert_eq!(sent(screen.update(now)), vec![(None, [row("clock"), row("ci"), row("failed"), row("news")].concat())]);
+    }
+
+    #[test]
+    fn long_rows_scroll_and_rows_expire() {
+        let now = Instant::now();
+        let mut screen = Screen::new();
+        let long = "a notification longer than the display";
+        screen.set_rows(0..1, lines(&[long]), Align::Left, Some(MS * 100), now);
+        screen.set_rows(1..2, lines(&["ttl"]), Align::Left, None, now);
+        screen.expire(1..2, now + MS * 150);
+        screen.update(now);
+
+        assert_eq!(sent(screen.update(now + MS * 100)), vec![(Some(0), marquee(long, COLUMNS, 1))]);
+        // setting it again doesn't restart it
+        screen.set_rows(0..1, lines(&[long]), Align::Left, Some(MS * 100), now + MS * 150);
+        assert_eq!(sent(screen.update(now + MS * 150)), vec![(Some(1), row(""))]);
+        assert_eq!(sent(screen.update(now + MS * 200)), vec![(Some(0), marquee(long, COLUMNS, 2))]);
+    }
+
+    #[test]
+    fn indicator_is_over_the_content() {
+        let now = Instant::now();
+        let mut screen = Screen::new();
+        screen.set_indicator(Some("1/2".to_owned()));
+        screen.set_text("hello".to_owned());
+        assert_eq!(sent(screen.update(now)), vec![(None, format!("{}1/2", fit("hello", TEXT_MAX_LEN - 3, Align::Left)))]);
+        screen.set_rows(3..4, lines(&["a row as long as the display"]), Align::Left, None, now);
+        let updates = sent(screen.update(now));
+        assert_eq!(updates.len(), 1);
+        assert!(updates[0].1.ends_with(&format!("{}1/2", &row("a row as long as the display")[..COLUMNS - 3])));
+    }
+}
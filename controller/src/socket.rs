@@ -0,0 +1,179 @@
+use std::io;
+use tracing::{error, warn};
+
+/// Connections sending more than this without a newline are closed
+#[cfg(unix)]
+const MAX_LINE: usize = 64 * 1024;
+
+/// Receives messages, one per line, from other programs over a unix socket
+///
+/// i.e. `echo deploy | nc -U /tmp/button_device.sock`
+pub struct SocketListener {
+    #[cfg(unix)]
+    listener: std::os::unix::net::UnixListener,
+    /// Open connections and what they've sent since their last newline
+    #[cfg(unix)]
+    connections: Vec<(std::os::unix::net::UnixStream, Vec<u8>)>,
+    /// Device and inode of the socket file, so only this socket is removed when dropped
+    #[cfg(unix)]
+    id: (u64, u64),
+    path: String,
+}
+
+#[cfg(unix)]
+impl SocketListener {
+    /// Replaces an existing socket at `path`, but not any other kind of file
+    pub fn bind(path: &str) -> io::Result<Self> {
+        use std::os::unix::fs::{FileTypeExt, MetadataExt};
+        use std::os::unix::net::UnixListener;
+
+        match std::fs::symlink_metadata(path) {
+            Ok(meta) if meta.file_type().is_socket() => std::fs::remove_file(path)?,
+            Ok(_) => return Err(io::Error::new(io::ErrorKind::AlreadyExists, "file exists and isn't a socket, it wasn't replaced")),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err)
+        }
+        let listener = UnixListener::bind(path)?;
+        listener.set_nonblocking(true)?;
+        let meta = std::fs::symlink_metadata(path)?;
+        Ok(SocketListener { listener, connections: vec![], id: (meta.dev(), meta.ino()), path: path.to_owned() })
+    }
+
+    /// Complete lines received since the last call, never waits for connections to send more
+    pub fn messages(&mut self) -> Vec<String> {
+        loop {
+            match self.listener.accept() {
+                Ok((stream, _)) => match stream.set_nonblocking(true) {
+                    Ok(()) => self.connections.push((stream, vec![])),
+                    Err(err) => error!("Error when reading from socket {}: {}", self.path, err)
+                },
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                Err(err) => {
+                    error!("Error when reading from socket {}: {}", self.path, err);
+                    break;
+                }
+            }
+        }
+
+        let mut messages = vec![];
+        let path = &self.path;
+        self.connections.retain_mut(|(stream, pending)| {
+            let open = read_available(stream, pending).unwrap_or_else(|err| {
+                error!("Error when reading from socket {}: {}", path, err);
+                false
+            });
+            while let Some(end) = pending.iter().position(|byte| *byte == b'\n') {
+                let line = pending.drain(..=end).collect::<Vec<u8>>();
+                push_message(&mut messages, &line);
+            }
+            if !open {
+                // the last line doesn't need a newline
+                push_message(&mut messages, pending);
+                return false;
+            }
+            if pending.len() > MAX_LINE {
+                warn!("Closed connection to socket {}, it sent more than {} bytes without a newline", path, MAX_LINE);
+                return false;
+            }
+            true
+        });
+        messages
+    }
+}
+
+/// Add everything that can be read without waiting to `pending`, returns false once the other end has closed
+#[cfg(unix)]
+fn read_available(stream: &mut std::os::unix::net::UnixStream, pending: &mut Vec<u8>) -> io::Result<bool> {
+    use std::io::Read;
+
+    let mut buffer = [0; 4096];
+    loop {
+        match stream.read(&mut buffer) {
+            Ok(0) => return Ok(false),
+            Ok(count) => pending.extend_from_slice(&buffer[..count]),
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(true),
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err)
+        }
+    }
+}
+
+#[cfg(unix)]
+fn push_message(messages: &mut Vec<String>, line: &[u8]) {
+    let line = String::from_utf8_lossy(line);
+    if !line.trim().is_empty() {
+        messages.push(line.trim().to_owned());
+    }
+}
+
+#[cfg(not(unix))]
+impl SocketListener {
+    pub fn bind(_path: &str) -> io::Result<Self> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "sockets are only supported on unix"))
+    }
+
+    pub fn messages(&mut self) -> Vec<String> {
+        vec![]
+    }
+}
+
+impl SocketListener {
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+}
+
+#[cfg(unix)]
+impl Drop for SocketListener {
+    /// Remove the socket file, unless it's been replaced by something else
+    fn drop(&mut self) {
+        use std::os::unix::fs::{FileTypeExt, MetadataExt};
+
+        if let Ok(meta) = std::fs::symlink_metadata(&self.path) {
+            if meta.file_type().is_socket() && (meta.dev(), meta.ino()) == self.id {
+                let _ = std::fs::remove_file(&self.path);
+            }
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::io::Write;
+    use std::os::unix::net::UnixStream;
+    use std::path::PathBuf;
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("controller-socket-{}-{}", name, std::process::id()))
+    }
+
+    #[test]
+    fn other_files_are_not_replaced() {
+        let path = temp_path("file");
+        std::fs::write(&path, "config").unwrap();
+        assert!(SocketListener::bind(path.to_str().unwrap()).is_err());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "config");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn lines_are_read_without_waiting() {
+        let path = temp_path("lines");
+        let mut listener = SocketListener::bind(path.to_str().unwrap()).unwrap();
+        let mut open = UnixStream::connect(&path).unwrap();
+        open.write_all(b"deploy\npartial").unwrap();
+        let mut closed = UnixStream::connect(&path).unwrap();
+        closed.write_all(b"one\n\ntwo").unwrap();
+        drop(closed);
+
+        let mut messages = listener.messages();
+        messages.sort();
+        assert_eq!(messages, vec!["deploy", "one", "two"]);
+        open.write_all(b" line\n").unwrap();
+        assert_eq!(listener.messages(), vec!["partial line"]);
+
+        drop(listener);
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,364 @@
+pub mod system;
+
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, Local};
+
+/// Rates and CPU use are worked out from samples at least this far apart
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Value built into the controller, read without running a script
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Source {
+    Time,
+    Date,
+    Uptime,
+    Load1,
+    Load5,
+    Load15,
+    /// Percent of CPU time that wasn't idle
+    Cpu,
+    /// Percent of memory used
+    Memory,
+    /// Percent of swap used
+    Swap,
+    /// Percent of the filesystem mounted here used
+    Disk(PathBuf),
+    /// Bytes per second received by the interface
+    NetRx(String),
+    /// Bytes per second sent by the interface
+    NetTx(String),
+}
+
+impl Source {
+    /// From names like `cpu`, `disk[/home]` or `net_rx[eth0]`
+    pub fn parse(name: &str) -> Result<Source, String> {
+        let (base, arg) = match name.split_once('[') {
+            Some((base, rest)) => match rest.strip_suffix(']') {
+                Some(arg) => (base, Some(arg)),
+                None => return Err(format!("Source {} is missing a closing ]", name))
+            },
+            None => (name, None)
+        };
+        let source = match (base, arg) {
+            ("time", None) => Source::Time,
+            ("date", None) => Source::Date,
+            ("uptime", None) => Source::Uptime,
+            ("load1", None) => Source::Load1,
+            ("load5", None) => Source::Load5,
+            ("load15", None) => Source::Load15,
+            ("cpu", None) => Source::Cpu,
+            ("memory", None) => Source::Memory,
+            ("swap", None) => Source::Swap,
+            ("disk", None) => Source::Disk(PathBuf::from("/")),
+            ("disk", Some(mount)) if mount.starts_with('/') => Source::Disk(PathBuf::from(mount)),
+            ("disk", Some(mount)) => return Err(format!("Disk mount {} isn't an absolute path", mount)),
+            ("net_rx" | "net_tx", None) => return Err(format!("{} needs an interface, e.g. {}[eth0]", base, base)),
+            ("net_rx" | "net_tx", Some(interface)) if interface.is_empty() || interface.contains('/') => {
+                return Err(format!("{} isn't a network interface name", interface))
+            }
+            ("net_rx", Some(interface)) => Source::NetRx(interface.to_owned()),
+            ("net_tx", Some(interface)) => Source::NetTx(interface.to_owned()),
+            ("time" | "date" | "uptime" | "load1" | "load5" | "load15" | "cpu" | "memory" | "swap", Some(_)) => {
+                return Err(format!("Source {} doesn't take [...]", base))
+            }
+            _ => return Err(format!("Unknown source {}, expected one of time, date, uptime, load1, load5, load15, cpu, memory, swap, disk[MOUNT], net_rx[INTERFACE] or net_tx[INTERFACE]", name))
+        };
+        Ok(source)
+    }
+
+    /// Reads as a time rather than a number
+    pub fn is_time(&self) -> bool {
+        matches!(self, Source::Time | Source::Date)
+    }
+}
+
+impl Display for Source {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Source::Time => write!(f, "time"),
+            Source::Date => write!(f, "date"),
+            Source::Uptime => write!(f, "uptime"),
+            Source::Load1 => write!(f, "load1"),
+            Source::Load5 => write!(f, "load5"),
+            Source::Load15 => write!(f, "load15"),
+            Source::Cpu => write!(f, "cpu"),
+            Source::Memory => write!(f, "memory"),
+            Source::Swap => write!(f, "swap"),
+            Source::Disk(mount) => write!(f, "disk[{}]", mount.display()),
+            Source::NetRx(interface) => write!(f, "net_rx[{}]", interface),
+            Source::NetTx(interface) => write!(f, "net_tx[{}]", interface),
+        }
+    }
+}
+
+/// Value read from a [Source]
+#[derive(Clone, Debug, PartialEq)]
+pub enum Reading {
+    /// Local time and the strftime format it's shown with by default
+    Time(DateTime<Local>, &'static str),
+    Duration(Duration),
+    Number(f64),
+    Percent(f64),
+    /// Bytes per second
+    Rate(f64),
+}
+
+impl Reading {
+    /// Value compared against by conditions, seconds for durations, `None` for times
+    pub fn number(&self) -> Option<f64> {
+        match self {
+            Reading::Time(..) => None,
+            Reading::Duration(duration) => Some(duration.as_secs_f64()),
+            Reading::Number(value) | Reading::Percent(value) | Reading::Rate(value) => Some(*value),
+        }
+    }
+
+    /// Show numbers with `precision` decimal places and times with a strftime `format`,
+    /// the format must already have been checked with [check_strftime]
+    pub fn format(&self, precision: Option<usize>, format: Option<&str>) -> String {
+        match (self, precision, format) {
+            (Reading::Time(time, _), _, Some(format)) => time.format(format).to_string(),
+            (Reading::Number(value), Some(precision), _) => format!("{:.*}", precision, value),
+            (Reading::Percent(value), Some(precision), _) => format!("{:.*}%", precision, value),
+            (Reading::Rate(value), Some(precision), _) => rate(*value, Some(precision)),
+            _ => self.to_string()
+        }
+    }
+}
+
+impl Display for Reading {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Reading::Time(time, format) => write!(f, "{}", time.format(format)),
+            Reading::Duration(duration) => {
+                let secs = duration.as_secs();
+                let (days, hours, minutes) = (secs / 86400, secs / 3600 % 24, secs / 60 % 60);
+                if days > 0 {
+                    write!(f, "{}d {}h", days, hours)
+                } else if hours > 0 {
+                    write!(f, "{}h {}m", hours, minutes)
+                } else {
+                    write!(f, "{}m", minutes)
+                }
+            }
+            Reading::Number(value) => write!(f, "{:.2}", value),
+            Reading::Percent(value) => write!(f, "{:.0}%", value),
+            Reading::Rate(value) => write!(f, "{}", rate(*value, None)),
+        }
+    }
+}
+
+/// Error if `format` isn't a strftime format chrono can show
+pub fn check_strftime(format: &str) -> Result<(), String> {
+    if StrftimeItems::new(format).any(|item| matches!(item, Item::Error)) {
+        return Err(format!("{} isn't a valid strftime format", format));
+    }
+    Ok(())
+}
+
+/// Bytes per second in B, K, M or G, by default bytes have no decimal places and the rest have one
+fn rate(bytes: f64, precision: Option<usize>) -> String {
+    let mut value = bytes;
+    let mut unit = "B";
+    for next in ["K", "M", "G"] {
+        if value < 1000.0 {
+            break;
+        }
+        value /= 1000.0;
+        unit = next;
+    }
+    let precision = precision.unwrap_or(if unit == "B" { 0 } else { 1 });
+    format!("{:.*}{}/s", precision, value, unit)
+}
+
+/// Last two samples of counters that only go up, e.g. CPU time or bytes sent
+#[derive(Default)]
+struct Counter {
+    previous: Option<(Instant, Vec<u64>)>,
+    latest: Option<(Instant, Vec<u64>)>,
+}
+
+impl Counter {
+    /// Take a new sample with `read` unless the latest one is too recent
+    fn sample(&mut self, now: Instant, read: impl FnOnce() -> io::Result<Vec<u64>>) -> io::Result<()> {
+        if let Some((time, _)) = &self.latest {
+            if now.saturating_duration_since(*time) < SAMPLE_INTERVAL {
+                return Ok(());
+            }
+        }
+        let values = read()?;
+        self.previous = self.latest.replace((now, values));
+        Ok(())
+    }
+
+    /// Increase in each counter between the last two samples and the time between them
+    fn delta(&self) -> Option<(Duration, Vec<u64>)> {
+        let ((before, old), (after, new)) = (self.previous.as_ref()?, self.latest.as_ref()?);
+        let delta = old.iter().zip(new).map(|(old, new)| new.saturating_sub(*old)).collect();
+        Some((after.saturating_duration_since(*before), delta))
+    }
+}
+
+/// Reads sources from `/proc` and `/sys` under a root directory, keeping the samples needed for rates
+pub struct Sources {
+    root: PathBuf,
+    cpu: Counter,
+    net: HashMap<String, Counter>,
+}
+
+impl Sources {
+    /// `root` is usually `/`, tests use a directory with fake `proc` and `sys` files
+    pub fn new(root: PathBuf) -> Self {
+        Sources { root, cpu: Counter::default(), net: HashMap::new() }
+    }
+}
+
+impl Sources {
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn read(&mut self, source: &Source, now: Instant) -> io::Result<Reading> {
+        let root = self.root.as_path();
+        let reading = match source {
+            Source::Time => Reading::Time(Local::now(), "%H:%M"),
+            Source::Date => Reading::Time(Local::now(), "%Y-%m-%d"),
+            Source::Uptime => Reading::Duration(Duration::from_secs_f64(system::uptime(root)?.max(0.0))),
+            Source::Load1 => Reading::Number(system::load(root)?[0]),
+            Source::Load5 => Reading::Number(system::load(root)?[1]),
+            Source::Load15 => Reading::Number(system::load(root)?[2]),
+            Source::Cpu => {
+                self.cpu.sample(now, || system::cpu_times(root).map(Vec::from))?;
+                // use the average since boot until there are two samples
+                let [busy, total] = match (self.cpu.delta(), &self.cpu.latest) {
+                    (Some((_, delta)), _) => [delta[0], delta[1]],
+                    (None, Some((_, latest))) => [latest[0], latest[1]],
+                    (None, None) => [0, 0]
+                };
+                Reading::Percent(percent(busy, total))
+            }
+            Source::Memory => {
+                let [total, available, _, _] = system::memory(root)?;
+                Reading::Percent(percent(total.saturating_sub(available), total))
+            }
+            Source::Swap => {
+                let [_, _, total, free] = system::memory(root)?;
+                Reading::Percent(percent(total.saturating_sub(free), total))
+            }
+            Source::Disk(mount) => Reading::Percent(system::disk_use(&root.join(mount.strip_prefix("/").unwrap_or(mount)))?),
+            Source::NetRx(interface) | Source::NetTx(interface) => {
+                let counter = self.net.entry(interface.clone()).or_default();
+                counter.sample(now, || system::net_bytes(root, interface).map(Vec::from))?;
+                let idx = if matches!(source, Source::NetRx(_)) { 0 } else { 1 };
+                // nothing to compare the first sample with
+                let rate = counter.delta()
+                    .filter(|(elapsed, _)| !elapsed.is_zero())
+                    .map(|(elapsed, delta)| delta[idx] as f64 / elapsed.as_secs_f64())
+                    .unwrap_or_default();
+                Reading::Rate(rate)
+            }
+        };
+        Ok(reading)
+    }
+}
+
+fn percent(part: u64, total: u64) -> f64 {
+    if total == 0 {
+        return 0.0;
+    }
+    part as f64 / total as f64 * 100.0
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use super::*;
+
+    /// Directory with fake `/proc` and `/sys` files, removed when dropped
+    struct FakeRoot(PathBuf);
+
+    impl FakeRoot {
+        fn new(name: &str) -> Self {
+            let root = std::env::temp_dir().join(format!("controller-sources-{}-{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&root);
+            fs::create_dir_all(root.join("proc")).unwrap();
+            fs::create_dir_all(root.join("sys/class/net/eth0/statistics")).unwrap();
+            FakeRoot(root)
+        }
+
+        fn write(&self, path: &str, contents: &str) {
+            fs::write(self.0.join(path), contents).unwrap();
+        }
+    }
+
+    impl Drop for FakeRoot {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn parse_names() {
+        assert_eq!(Source::parse("cpu"), Ok(Source::Cpu));
+        assert_eq!(Source::parse("disk"), Ok(Source::Disk(PathBuf::from("/"))));
+        assert_eq!(Source::parse("disk[/home]"), Ok(Source::Disk(PathBuf::from("/home"))));
+        assert_eq!(Source::parse("net_tx[eth0]"), Ok(Source::NetTx("eth0".to_owned())));
+        for name in ["net_rx", "net_rx[]", "disk[home]", "cpu[0]", "disk[/", "temperature"] {
+            assert!(Source::parse(name).is_err(), "{}", name);
+        }
+        for name in ["time", "load15", "disk[/var/lib]", "net_rx[wlan0]"] {
+            assert_eq!(Source::parse(name).unwrap().to_string(), name);
+        }
+    }
+
+    #[test]
+    fn load_uptime_and_memory() {
+        let root = FakeRoot::new("proc");
+        root.write("proc/loadavg", "0.52 0.58 0.59 1/389 12345\n");
+        root.write("proc/uptime", "273605.32 1063398.57\n");
+        root.write("proc/meminfo", "MemTotal:        1000000 kB\nMemFree:          100000 kB\nMemAvailable:     250000 kB\nSwapTotal:             0 kB\nSwapFree:              0 kB\n");
+        let mut sources = Sources::new(root.0.clone());
+        let now = Instant::now();
+
+        assert_eq!(sources.read(&Source::Load5, now).unwrap(), Reading::Number(0.58));
+        assert_eq!(sources.read(&Source::Uptime, now).unwrap().to_string(), "3d 4h");
+        assert_eq!(sources.read(&Source::Memory, now).unwrap(), Reading::Percent(75.0));
+        assert_eq!(sources.read(&Source::Swap, now).unwrap(), Reading::Percent(0.0));
+    }
+
+    #[test]
+    fn cpu_uses_time_between_samples() {
+        let root = FakeRoot::new("cpu");
+        root.write("proc/stat", "cpu  100 0 100 700 100 0 0 0 0 0\ncpu0 100 0 100 700 100 0 0 0 0 0\n");
+        let mut sources = Sources::new(root.0.clone());
+        let now = Instant::now();
+        assert_eq!(sources.read(&Source::Cpu, now).unwrap(), Reading::Percent(20.0));
+
+        root.write("proc/stat", "cpu  190 0 100 710 100 0 0 0 0 0\n");
+        // too soon for a new sample
+        assert_eq!(sources.read(&Source::Cpu, now + Duration::from_millis(500)).unwrap(), Reading::Percent(20.0));
+        assert_eq!(sources.read(&Source::Cpu, now + Duration::from_secs(1)).unwrap(), Reading::Percent(90.0));
+    }
+
+    #[test]
+    fn network_rate() {
+        let root = FakeRoot::new("net");
+        root.write("sys/class/net/eth0/statistics/rx_bytes", "1000\n");
+        root.write("sys/class/net/eth0/statistics/tx_bytes", "500\n");
+        let mut sources = Sources::new(root.0.clone());
+        let now = Instant::now();
+        assert_eq!(sources.read(&Source::NetRx("eth0".to_owned()), now).unwrap(), Reading::Rate(0.0));
+
+        root.write("sys/class/net/eth0/statistics/rx_bytes", "5001000\n");
+        root.write("sys/class/net/eth0/statistics/tx_bytes", "1500\n");
+        let later = now + Duration::from_secs(2);
+        assert_eq!(sources.read(&Source::NetRx("eth0".to_owned()), later).unwrap().to_string(), "2.5M/s");
+        assert_eq!(sources.read(&Source::NetTx("eth0".to_owned()), later).unwrap().to_string(), "500B/s");
+        assert!(sources.read(&Source::NetRx("wlan0".to_owned()), later).is_err());
+    }
+}
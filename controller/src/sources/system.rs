@@ -0,0 +1,84 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Busy and total CPU time since boot, in clock ticks, from `/proc/stat`
+pub fn cpu_times(root: &Path) -> io::Result<[u64; 2]> {
+    let stat = fs::read_to_string(root.join("proc/stat"))?;
+    let line = stat.lines()
+        .find(|line| line.starts_with("cpu "))
+        .ok_or_else(|| invalid("no cpu line in /proc/stat"))?;
+    let times = line.split_whitespace()
+        .skip(1)
+        .map(|time| time.parse::<u64>().map_err(|_| invalid("invalid cpu time in /proc/stat")))
+        .collect::<io::Result<Vec<u64>>>()?;
+    if times.len() < 4 {
+        return Err(invalid("too few cpu times in /proc/stat"));
+    }
+    // user, nice, system, idle, iowait, irq, softirq and steal, guest time is already in user
+    let total = times.iter().take(8).sum::<u64>();
+    let idle = times[3] + times.get(4).copied().unwrap_or_default();
+    Ok([total - idle, total])
+}
+
+/// 1, 5 and 15 minute load averages from `/proc/loadavg`
+pub fn load(root: &Path) -> io::Result<[f64; 3]> {
+    let loadavg = fs::read_to_string(root.join("proc/loadavg"))?;
+    let mut values = loadavg.split_whitespace().map(|value| value.parse::<f64>().map_err(|_| invalid("invalid load in /proc/loadavg")));
+    let mut next = || values.next().unwrap_or_else(|| Err(invalid("too few values in /proc/loadavg")));
+    Ok([next()?, next()?, next()?])
+}
+
+/// Seconds since boot from `/proc/uptime`
+pub fn uptime(root: &Path) -> io::Result<f64> {
+    fs::read_to_string(root.join("proc/uptime"))?
+        .split_whitespace()
+        .next()
+        .and_then(|secs| secs.parse::<f64>().ok())
+        .ok_or_else(|| invalid("invalid /proc/uptime"))
+}
+
+/// Total and available memory, then total and free swap, in kB from `/proc/meminfo`
+pub fn memory(root: &Path) -> io::Result<[u64; 4]> {
+    let meminfo = fs::read_to_string(root.join("proc/meminfo"))?;
+    let field = |name: &str| {
+        meminfo.lines()
+            .find_map(|line| line.strip_prefix(name)?.strip_prefix(':'))
+            .and_then(|value| value.trim().trim_end_matches("kB").trim().parse::<u64>().ok())
+            .ok_or_else(|| invalid(&format!("no {} in /proc/meminfo", name)))
+    };
+    Ok([field("MemTotal")?, field("MemAvailable")?, field("SwapTotal")?, field("SwapFree")?])
+}
+
+/// Bytes received and sent by the interface from `/sys/class/net`
+pub fn net_bytes(root: &Path, interface: &str) -> io::Result<[u64; 2]> {
+    let dir = root.join("sys/class/net").join(interface).join("statistics");
+    let read = |name: &str| {
+        fs::read_to_string(dir.join(name))?
+            .trim()
+            .parse::<u64>()
+            .map_err(|_| invalid(&format!("invalid {} for {}", name, interface)))
+    };
+    Ok([read("rx_bytes")?, read("tx_bytes")?])
+}
+
+/// Percent of the space available to users that's used by the filesystem `path` is on, the same as `df`
+#[cfg(unix)]
+pub fn disk_use(path: &Path) -> io::Result<f64> {
+    let stat = nix::sys::statvfs::statvfs(path).map_err(io::Error::from)?;
+    let used = (stat.blocks() - stat.blocks_free()) as f64;
+    let available = stat.blocks_available() as f64;
+    if used + available == 0.0 {
+        return Ok(0.0);
+    }
+    Ok(used / (used + available) * 100.0)
+}
+
+#[cfg(not(unix))]
+pub fn disk_use(_path: &Path) -> io::Result<f64> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "disk use is only supported on unix"))
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
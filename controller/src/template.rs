@@ -0,0 +1,272 @@
+use comm_lib::layout::Align;
+use crate::sources::{check_strftime, Reading, Source};
+
+/// Text with `{field}`s that are filled in each time it's shown, e.g. `{time:%H:%M} L{load1:.1}`
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Part {
+    Text(String),
+    Field(Field, Spec),
+}
+
+/// Value a template can show
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Field {
+    Source(Source),
+    /// Last output of the rule's scripts, all of it or just one line
+    Script { rule: String, line: Option<usize> },
+    /// Name of the page showing
+    Page,
+    /// Name of the active profile
+    Profile,
+}
+
+/// Filled in value of a [Field]
+pub enum Value {
+    Reading(Reading),
+    Text(String),
+}
+
+/// `[[fill]align][width][.precision][format]` after the field name
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Spec {
+    fill: char,
+    align: Option<Align>,
+    width: Option<usize>,
+    /// Decimal places for numbers, max length for text
+    precision: Option<usize>,
+    /// strftime format, times only
+    format: Option<String>,
+}
+
+impl Template {
+    pub fn parse(text: &str) -> Result<Template, String> {
+        let mut parts = vec![];
+        let mut literal = String::new();
+        let mut chars = text.chars().peekable();
+        while let Some(letter) = chars.next() {
+            match letter {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '}' => return Err("} isn't opened, use }} for a literal }".to_owned()),
+                '{' => {
+                    let mut field = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(letter) => field.push(letter),
+                            None => return Err(format!("{{{} isn't closed, use {{{{ for a literal {{", field))
+                        }
+                    }
+                    if !literal.is_empty() {
+                        parts.push(Part::Text(std::mem::take(&mut literal)));
+                    }
+                    let (field, spec) = parse_field(&field)?;
+                    parts.push(Part::Field(field, spec));
+                }
+                letter => literal.push(letter),
+            }
+        }
+        if !literal.is_empty() {
+            parts.push(Part::Text(literal));
+        }
+        Ok(Template { parts })
+    }
+}
+
+impl Template {
+    /// Fields in the order they're used
+    pub fn fields(&self) -> impl Iterator<Item=&Field> {
+        self.parts.iter().filter_map(|part| match part {
+            Part::Field(field, _) => Some(field),
+            Part::Text(_) => None
+        })
+    }
+
+    /// Fill in the fields with `value`, newlines in the template and values are kept
+    pub fn render(&self, mut value: impl FnMut(&Field) -> Value) -> String {
+        let mut text = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Text(literal) => text.push_str(literal),
+                Part::Field(field, spec) => text.push_str(&spec.apply(value(field))),
+            }
+        }
+        text
+    }
+}
+
+/// `name` or `name:spec`
+fn parse_field(text: &str) -> Result<(Field, Spec), String> {
+    // names with brackets, e.g. disk[/mnt/a:b], can have a colon inside them
+    let search_from = match (text.find('['), text.find(':')) {
+        (Some(open), colon) if colon.is_none_or(|colon| open < colon) => text.find(']').unwrap_or_default(),
+        _ => 0
+    };
+    let (name, spec) = match text[search_from..].find(':') {
+        Some(idx) => (&text[..search_from + idx], Some(&text[search_from + idx + 1..])),
+        None => (text, None)
+    };
+    let field = match name.trim() {
+        "" => return Err("{} has no field name".to_owned()),
+        "page" => Field::Page,
+        "profile" => Field::Profile,
+        name => match name.strip_prefix("script.") {
+            Some(script) => parse_script(script)?,
+            None => Field::Source(Source::parse(name)?),
+        }
+    };
+    let spec = parse_spec(spec.unwrap_or_default(), &field).map_err(|err| format!("{{{}}}: {}", text, err))?;
+    Ok((field, spec))
+}
+
+/// `NAME` or `NAME.lineN`
+fn parse_script(text: &str) -> Result<Field, String> {
+    let (rule, line) = match text.rsplit_once(".line") {
+        Some((rule, line)) if !line.is_empty() && line.chars().all(|digit| digit.is_ascii_digit()) => {
+            (rule, Some(line.parse::<usize>().map_err(|_| format!("{} isn't a line number", line))?))
+        }
+        _ => (text, None)
+    };
+    if rule.trim().is_empty() {
+        return Err("script field has no rule name, e.g. {script.NAME}".to_owned());
+    }
+    Ok(Field::Script { rule: rule.to_owned(), line })
+}
+
+fn parse_spec(text: &str, field: &Field) -> Result<Spec, String> {
+    let mut spec = Spec { fill: ' ', align: None, width: None, precision: None, format: None };
+    let mut rest = text;
+    let mut chars = text.chars();
+    let (first, second) = (chars.next(), chars.next());
+    if let (Some(fill), Some(align)) = (first, second.and_then(align)) {
+        spec.fill = fill;
+        spec.align = Some(align);
+        rest = &rest[fill.len_utf8() + 1..];
+    } else if let Some(align) = first.and_then(align) {
+        spec.align = Some(align);
+        rest = &rest[1..];
+    }
+    let digits = rest.find(|letter: char| !letter.is_ascii_digit()).unwrap_or(rest.len());
+    if digits > 0 {
+        spec.width = Some(rest[..digits].parse().map_err(|_| format!("{} isn't a width", &rest[..digits]))?);
+        rest = &rest[digits..];
+    }
+
+    let is_time = matches!(field, Field::Source(source) if source.is_time());
+    if !is_time {
+        if let Some(precision) = rest.strip_prefix('.') {
+            spec.precision = Some(precision.parse().map_err(|_| format!("{} isn't a precision, e.g. .1", precision))?);
+            rest = "";
+        }
+    }
+    match field {
+        _ if rest.is_empty() => {}
+        Field::Source(_) if is_time => {
+            check_strftime(rest)?;
+            spec.format = Some(rest.to_owned());
+        }
+        _ => return Err(format!("{} isn't a format, only time and date take a strftime format", rest))
+    }
+    if spec.precision.is_some() && matches!(field, Field::Source(Source::Uptime)) {
+        return Err("uptime doesn't take a precision".to_owned());
+    }
+    Ok(spec)
+}
+
+fn align(letter: char) -> Option<Align> {
+    match letter {
+        '<' => Some(Align::Left),
+        '^' => Some(Align::Center),
+        '>' => Some(Align::Right),
+        _ => None
+    }
+}
+
+impl Spec {
+    /// Format the value and pad it to the width, numbers are aligned right by default and everything else left
+    fn apply(&self, value: Value) -> String {
+        let (text, default_align) = match value {
+            Value::Reading(reading) => {
+                let align = if matches!(reading, Reading::Number(_) | Reading::Percent(_) | Reading::Rate(_)) { Align::Right } else { Align::Left };
+                (reading.format(self.precision, self.format.as_deref()), align)
+            }
+            Value::Text(text) => match self.precision {
+                Some(max) => (text.chars().take(max).collect(), Align::Left),
+                None => (text, Align::Left)
+            },
+        };
+        let padding = self.width.unwrap_or_default().saturating_sub(text.chars().count());
+        let (before, after) = match self.align.unwrap_or(default_align) {
+            Align::Left => (0, padding),
+            Align::Center => (padding / 2, padding - padding / 2),
+            Align::Right => (padding, 0),
+        };
+        let fill = |count: usize| std::iter::repeat_n(self.fill, count).collect::<String>();
+        format!("{}{}{}", fill(before), text, fill(after))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(template: &str) -> String {
+        Template::parse(template).unwrap().render(|field| match field {
+            Field::Source(Source::Load1) => Value::Reading(Reading::Number(0.583)),
+            Field::Source(Source::Cpu) => Value::Reading(Reading::Percent(7.0)),
+            Field::Source(_) => Value::Text("?".to_owned()),
+            Field::Script { line: Some(line), .. } => Value::Text(["passing", "2 queued"][*line].to_owned()),
+            Field::Script { line: None, .. } => Value::Text("passing\n2 queued".to_owned()),
+            Field::Page => Value::Text("home".to_owned()),
+            Field::Profile => Value::Text("work".to_owned()),
+        })
+    }
+
+    #[test]
+    fn fields_and_literals() {
+        assert_eq!(render("L{load1:.1} {cpu}"), "L0.6 7%");
+        assert_eq!(render("{script.ci.line1}\n{page}/{profile}"), "2 queued\nhome/work");
+        assert_eq!(render("{script.ci}"), "passing\n2 queued");
+        assert_eq!(render("{{page}} }}"), "{page} }");
+    }
+
+    #[test]
+    fn width_and_alignment() {
+        assert_eq!(render("[{cpu:5}]"), "[   7%]");
+        assert_eq!(render("[{page:6}]"), "[home  ]");
+        assert_eq!(render("[{page:>6}]"), "[  home]");
+        assert_eq!(render("[{page:-^8}]"), "[--home--]");
+        assert_eq!(render("[{cpu:0>4.1}]"), "[7.0%]");
+        assert_eq!(render("[{script.ci.line0:.4}]"), "[pass]");
+        assert_eq!(render("[{page:2}]"), "[home]");
+    }
+
+    #[test]
+    fn field_names() {
+        let template = Template::parse("{time:%H:%M} {disk[/mnt/a:b]:>4} {script.Deploy status.line2} {net_rx[eth0]}").unwrap();
+        assert_eq!(template.fields().cloned().collect::<Vec<Field>>(), vec![
+            Field::Source(Source::Time),
+            Field::Source(Source::Disk("/mnt/a:b".into())),
+            Field::Script { rule: "Deploy status".to_owned(), line: Some(2) },
+            Field::Source(Source::NetRx("eth0".to_owned())),
+        ]);
+    }
+
+    #[test]
+    fn invalid() {
+        for template in ["{cpu", "cpu}", "{}", "{temperature}", "{cpu:%H}", "{time:%Q}", "{uptime:.1}", "{load1:.x}", "{script.}", "{page:x}"] {
+            assert!(Template::parse(template).is_err(), "{}", template);
+        }
+    }
+}
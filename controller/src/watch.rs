@@ -0,0 +1,53 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+/// How often files are checked
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Polls files for changes to their modified time, including being created or deleted
+pub struct FileWatcher<K> {
+    files: Vec<WatchedFile<K>>,
+    last_poll: Option<Instant>,
+}
+
+struct WatchedFile<K> {
+    key: K,
+    path: PathBuf,
+    modified: Option<SystemTime>,
+}
+
+impl<K: Copy> FileWatcher<K> {
+    pub fn new(files: Vec<(K, PathBuf)>) -> Self {
+        let files = files.into_iter()
+            .map(|(key, path)| {
+                let modified = modified(&path);
+                WatchedFile { key, path, modified }
+            })
+            .collect();
+        FileWatcher { files, last_poll: None }
+    }
+}
+
+impl<K: Copy> FileWatcher<K> {
+    /// Files that have changed since the last call
+    pub fn changed(&mut self) -> Vec<(K, PathBuf)> {
+        if self.files.is_empty() || self.last_poll.map(|last| last.elapsed() < POLL_INTERVAL).unwrap_or_default() {
+            return vec![];
+        }
+        self.last_poll = Some(Instant::now());
+        let mut changed = vec![];
+        for file in &mut self.files {
+            let modified = modified(&file.path);
+            if modified != file.modified {
+                file.modified = modified;
+                changed.push((file.key, file.path.clone()));
+            }
+        }
+        changed
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}